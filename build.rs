@@ -0,0 +1,26 @@
+use std::process::Command;
+
+fn main() {
+    // Record the git commit this was built from so it can be written into output files.
+    // This is optional: builds from a source archive without git just won't have it.
+    println!("cargo:rerun-if-changed=build.rs");
+    // Only watch the git files if they exist; watching a missing path makes cargo rerun this every build.
+    for git_path in [".git/HEAD", ".git/refs"] {
+        if std::path::Path::new(git_path).exists() {
+            println!("cargo:rerun-if-changed={git_path}");
+        }
+    }
+
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty());
+
+    if let Some(hash) = hash {
+        println!("cargo:rustc-env=OCO_MATCHUP_GIT_HASH={hash}");
+    }
+}
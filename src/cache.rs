@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::RunOneArgs;
+use crate::error::MatchupError;
+use crate::grouping::Instrument;
+use crate::oco::OcoGeo;
+
+type CacheKey = (PathBuf, Instrument, bool);
+
+/// A cache of loaded lite files shared by the matchups in a multi run.
+///
+/// In a multi run, each lite file is usually listed in several matchups (e.g. each day's OCO-3
+/// file is matched against the previous, same, and next day's OCO-2 files). This cache ensures
+/// that each file is read once and the same copy handed to every matchup that needs it.
+///
+/// The cache is created knowing which matchups will be run, and keeps a count of how many of
+/// them still need each file. As each matchup finishes, [`LiteFileCache::release_matchup`]
+/// should be called; once no pending matchups need a file, it is dropped from the cache.
+/// The total size of the cached files is also limited to a memory budget; if loading a file
+/// would exceed that, the least recently used files not in use by a running matchup are
+/// dropped (and will be reread if needed again). If there is still not room, the file is
+/// loaded without caching it.
+pub struct LiteFileCache {
+    max_bytes: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// Number of matchups not yet finished that use each file
+    pending_uses: HashMap<CacheKey, usize>,
+    /// The currently cached files
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Locks to ensure that only one thread loads a given file at a time
+    loading: HashMap<CacheKey, Arc<Mutex<()>>>,
+    /// Total size of the cached files, plus any memory reserved by running matchups (see
+    /// [`LiteFileCache::reserve`]), in bytes
+    total_bytes: usize,
+    /// Counter incremented on each access, used to find the least recently used files
+    clock: u64,
+}
+
+/// Memory counted against a [`LiteFileCache`]'s limit for a running matchup's own copies of lite file data,
+/// until this is dropped. See [`LiteFileCache::reserve`].
+pub struct CacheReservation<'c> {
+    cache: &'c LiteFileCache,
+    nbytes: usize,
+}
+
+impl Drop for CacheReservation<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.cache.state.lock() {
+            state.total_bytes = state.total_bytes.saturating_sub(self.nbytes);
+        }
+    }
+}
+
+struct CacheEntry {
+    geo: Arc<OcoGeo>,
+    nbytes: usize,
+    last_used: u64,
+}
+
+impl LiteFileCache {
+    /// Create a new cache for the lite files used by `matchups`, limited to using at most
+    /// `max_bytes` of memory. Matchups that read previous full matches instead of
+    /// lite files do not count as users of their lite files.
+    pub fn new<'a, I: IntoIterator<Item = &'a RunOneArgs>>(matchups: I, max_bytes: usize) -> Self {
+        let mut state = CacheState::default();
+        for m in matchups {
+            for key in Self::matchup_keys(m) {
+                *state.pending_uses.entry(key).or_default() += 1;
+            }
+        }
+
+        Self { max_bytes, state: Mutex::new(state) }
+    }
+
+    /// Get the loaded lite file at `lite_file` (see [`OcoGeo::load_lite_file`]), reading it
+    /// only if it is not already cached.
+    pub fn get(&self, lite_file: &Path, instrument: Instrument, flag0_only: bool) -> Result<Arc<OcoGeo>, MatchupError> {
+        let key = Self::key(lite_file, instrument, flag0_only);
+        let load_lock = {
+            let mut state = self.lock_state()?;
+            if let Some(geo) = state.get_cached(&key) {
+                return Ok(geo);
+            }
+            Arc::clone(state.loading.entry(key.clone()).or_default())
+        };
+
+        // Hold the load lock while reading the file, so that other matchups needing the same file
+        // wait for this read to finish rather than read it themselves. Once we have the lock, check
+        // whether another thread loaded the file while we were waiting.
+        let _guard = load_lock.lock()
+            .map_err(|_| MatchupError::InternalError("Lite file cache load lock poisoned".to_owned()))?;
+        if let Some(geo) = self.lock_state()?.get_cached(&key) {
+            return Ok(geo);
+        }
+
+        log::debug!("Lite file cache: reading {}", lite_file.display());
+        let geo = Arc::new(OcoGeo::load_lite_file(lite_file, instrument, flag0_only)?);
+        let nbytes = geo.memory_size();
+
+        let mut state = self.lock_state()?;
+        if state.pending_uses.get(&key).copied().unwrap_or(0) == 0 {
+            // No later matchup needs this file, so there's no point caching it
+            return Ok(geo);
+        }
+
+        state.make_room(nbytes, self.max_bytes);
+        if state.total_bytes + nbytes <= self.max_bytes {
+            state.clock += 1;
+            let last_used = state.clock;
+            state.total_bytes += nbytes;
+            state.entries.insert(key, CacheEntry { geo: Arc::clone(&geo), nbytes, last_used });
+        } else {
+            log::debug!("Lite file cache: no room to cache {} ({nbytes} bytes)", lite_file.display());
+        }
+        Ok(geo)
+    }
+
+    /// Count `nbytes` of memory used by a running matchup for its own copies of lite file data (e.g. several files
+    /// concatenated into one) against the memory limit, until the returned reservation is dropped. Cached files not
+    /// in use are dropped to make room if needed; the memory is counted even if there is still not enough room,
+    /// since the copies already exist.
+    pub fn reserve(&self, nbytes: usize) -> Result<CacheReservation<'_>, MatchupError> {
+        let mut state = self.lock_state()?;
+        state.make_room(nbytes, self.max_bytes);
+        state.total_bytes += nbytes;
+        Ok(CacheReservation { cache: self, nbytes })
+    }
+
+    /// Record that `matchup` has finished (successfully or not), so that any lite files no
+    /// other pending matchups need are dropped from the cache.
+    pub fn release_matchup(&self, matchup: &RunOneArgs) -> Result<(), MatchupError> {
+        let mut state = self.lock_state()?;
+        for key in Self::matchup_keys(matchup) {
+            let remaining = state.pending_uses.get_mut(&key)
+                .map(|n| { *n = n.saturating_sub(1); *n })
+                .unwrap_or(0);
+
+            if remaining == 0 {
+                state.pending_uses.remove(&key);
+                state.loading.remove(&key);
+                if let Some(entry) = state.entries.remove(&key) {
+                    log::debug!("Lite file cache: dropping {}", key.0.display());
+                    state.total_bytes -= entry.nbytes;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn matchup_keys(matchup: &RunOneArgs) -> Vec<CacheKey> {
+        if matchup.read_full_matches.is_some() {
+            return vec![];
+        }
+
+        let first = Self::key(&matchup.oco2_lite_file, Instrument::first(matchup.oco3_self_cross), matchup.flag0_only);
+        std::iter::once(first)
+            .chain(matchup.oco3_lite_files.iter().map(|p| Self::key(p, Instrument::Oco3, matchup.flag0_only)))
+            .collect()
+    }
+
+    /// The cache key for a lite file, using its canonical path (if it exists) so that the same file given by
+    /// different paths is only loaded once
+    fn key(lite_file: &Path, instrument: Instrument, flag0_only: bool) -> CacheKey {
+        let path = lite_file.canonicalize().unwrap_or_else(|_| lite_file.to_owned());
+        (path, instrument, flag0_only)
+    }
+
+    fn lock_state(&self) -> Result<std::sync::MutexGuard<'_, CacheState>, MatchupError> {
+        self.state.lock()
+            .map_err(|_| MatchupError::InternalError("Lite file cache lock poisoned".to_owned()))
+    }
+}
+
+impl CacheState {
+    fn get_cached(&mut self, key: &CacheKey) -> Option<Arc<OcoGeo>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            Arc::clone(&entry.geo)
+        })
+    }
+
+    /// Drop least recently used entries that are not in use by any running matchup until
+    /// `nbytes` more can be stored without exceeding `max_bytes`, or there are no more entries
+    /// that can be dropped.
+    fn make_room(&mut self, nbytes: usize, max_bytes: usize) {
+        while self.total_bytes + nbytes > max_bytes {
+            // Running matchups hold their own reference to the files they use, so only the cache's reference
+            // means nothing else is using the file and dropping it will free its memory
+            let lru_key = self.entries.iter()
+                .filter(|(_, e)| Arc::strong_count(&e.geo) == 1)
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone());
+
+            if let Some(key) = lru_key {
+                let entry = self.entries.remove(&key).expect("LRU key must be in the cache");
+                log::debug!("Lite file cache: evicting {} to make room", key.0.display());
+                self.total_bytes -= entry.nbytes;
+            } else {
+                break;
+            }
+        }
+    }
+}
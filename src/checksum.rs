@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ChecksumOptions;
+use crate::error::MatchupError;
+use crate::utils;
+
+/// Computes and remembers the SHA-256 checksums of input files.
+///
+/// Hashing a lite file means reading all of it, and in a multi run the same file is usually used by
+/// several matchups, so each checksum is kept along with the size and modification time of the file
+/// it was computed from. As long as neither has changed, later requests for the same file reuse the
+/// stored checksum. If a persistent cache file is given, the stored checksums are loaded from it when
+/// created and written back to it by [`ChecksumCache::save`], so they can also be reused between runs.
+///
+/// Checksums can also be disabled entirely, in which case [`ChecksumCache::checksums`] returns `None`.
+pub struct ChecksumCache {
+    enabled: bool,
+    persistent_file: Option<PathBuf>,
+    entries: Mutex<HashMap<PathBuf, CachedChecksum>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedChecksum {
+    size: u64,
+    mtime_ns: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ChecksumCacheFile {
+    #[serde(default)]
+    files: Vec<ChecksumCacheFileEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChecksumCacheFileEntry {
+    path: PathBuf,
+    #[serde(flatten)]
+    checksum: CachedChecksum,
+}
+
+impl ChecksumCache {
+    /// Create a cache that computes checksums and keeps them only in memory.
+    pub fn new() -> Self {
+        Self { enabled: true, persistent_file: None, entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Create a cache that never computes checksums.
+    pub fn disabled() -> Self {
+        Self { enabled: false, persistent_file: None, entries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Create a cache that loads previously computed checksums from `cache_file` (if it exists)
+    /// and will write all known checksums back to it when [`ChecksumCache::save`] is called.
+    /// A cache file that cannot be parsed is ignored with a warning, since it can always be rebuilt.
+    pub fn with_persistent_file(cache_file: &Path) -> Result<Self, MatchupError> {
+        let mut entries = HashMap::new();
+        if cache_file.exists() {
+            let contents = std::fs::read_to_string(cache_file)?;
+            match toml::from_str::<ChecksumCacheFile>(&contents) {
+                Ok(file) => {
+                    for entry in file.files {
+                        entries.insert(entry.path, entry.checksum);
+                    }
+                },
+                Err(e) => log::warn!("Ignoring unreadable checksum cache file {}: {e}", cache_file.display()),
+            }
+        }
+
+        Ok(Self { enabled: true, persistent_file: Some(cache_file.to_owned()), entries: Mutex::new(entries) })
+    }
+
+    /// Create the cache requested by the command line options.
+    pub fn from_options(opts: &ChecksumOptions) -> Result<Self, MatchupError> {
+        if opts.no_checksums {
+            Ok(Self::disabled())
+        } else if let Some(cache_file) = opts.checksum_cache.as_deref() {
+            Self::with_persistent_file(cache_file)
+        } else {
+            Ok(Self::new())
+        }
+    }
+
+    /// Returns `false` if this cache was created with checksums disabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Get the SHA-256 checksums of `files`, in the same order. Files without an up-to-date cached checksum
+    /// are hashed in parallel. Returns `None` if checksums are disabled.
+    pub fn checksums<P: AsRef<Path> + Sync>(&self, files: &[P]) -> Result<Option<Vec<String>>, MatchupError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let checksums = files.par_iter()
+            .map(|f| self.checksum(f.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(checksums))
+    }
+
+    /// Compute and cache the checksums of `files` ahead of time, so that matchups running in parallel do not
+    /// each hash the same file. Files that cannot be hashed are skipped; the error will be reported when
+    /// a matchup needs that checksum.
+    pub fn precompute<P: AsRef<Path> + Sync>(&self, files: &[P]) {
+        if !self.enabled {
+            return;
+        }
+
+        files.par_iter().for_each(|f| {
+            if let Err(e) = self.checksum(f.as_ref()) {
+                log::debug!("Could not precompute checksum for {}: {e}", f.as_ref().display());
+            }
+        });
+    }
+
+    /// Write all known checksums to the persistent cache file, if there is one.
+    pub fn save(&self) -> Result<(), MatchupError> {
+        let cache_file = if let Some(f) = self.persistent_file.as_deref() {
+            f
+        } else {
+            return Ok(());
+        };
+
+        let mut files: Vec<ChecksumCacheFileEntry> = self.lock_entries()?
+            .iter()
+            .map(|(path, checksum)| ChecksumCacheFileEntry { path: path.to_owned(), checksum: checksum.clone() })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let contents = toml::to_string_pretty(&ChecksumCacheFile { files })?;
+        std::fs::write(cache_file, contents)?;
+        Ok(())
+    }
+
+    fn checksum(&self, file: &Path) -> Result<String, MatchupError> {
+        let key = file.canonicalize().unwrap_or_else(|_| file.to_owned());
+        let (size, mtime_ns) = file_size_and_mtime(&key)
+            .map_err(|e| io_error_with_path(e, file))?;
+
+        if let Some(cached) = self.lock_entries()?.get(&key) {
+            if cached.size == size && cached.mtime_ns == mtime_ns {
+                return Ok(cached.sha256.clone());
+            }
+        }
+
+        log::debug!("Computing SHA-256 checksum of {}", file.display());
+        let sha256 = utils::file_sha256(&key)
+            .map_err(|e| io_error_with_path(e, file))?;
+        self.lock_entries()?.insert(key, CachedChecksum { size, mtime_ns, sha256: sha256.clone() });
+        Ok(sha256)
+    }
+
+    fn lock_entries(&self) -> Result<std::sync::MutexGuard<'_, HashMap<PathBuf, CachedChecksum>>, MatchupError> {
+        self.entries.lock()
+            .map_err(|_| MatchupError::InternalError("Checksum cache lock poisoned".to_owned()))
+    }
+}
+
+impl Default for ChecksumCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn file_size_and_mtime(file: &Path) -> std::io::Result<(u64, u64)> {
+    let meta = std::fs::metadata(file)?;
+    let mtime_ns = meta.modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    Ok((meta.len(), mtime_ns))
+}
+
+/// The I/O error variant of [`MatchupError`] has no place for a path, so add it to the message.
+fn io_error_with_path(e: std::io::Error, file: &Path) -> MatchupError {
+    MatchupError::IOError(std::io::Error::new(e.kind(), format!("could not checksum {}: {e}", file.display())))
+}
@@ -1,10 +1,27 @@
-use std::path::PathBuf;
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use chrono::{NaiveDate, Duration};
 use clap::Args;
+use itertools::Itertools;
 use serde::{Serialize, Deserialize};
 
+use crate::distance::DistanceCriterion;
+use crate::geodesy::DistanceModel;
+use crate::grouping::{GroupingMode, RejectedGroups, DEFAULT_PASS_GAP_S};
+use crate::error::MatchupError;
+use crate::modes::{ModePair, OperationMode};
+use crate::nearest::NearestMetric;
+use crate::score::{DEFAULT_DISTANCE_SCALE_KM, DEFAULT_TIME_SCALE_S};
+use crate::surface::{SurfaceClass, DEFAULT_LAND_THRESHOLD, DEFAULT_OCEAN_THRESHOLD};
+use crate::region::{RegionBox, RegionFilter};
+use crate::remap::PrefixRule;
 
-#[derive(Debug, Args, Serialize, Deserialize)]
+
+#[derive(Debug, Clone, Args, Serialize, Deserialize)]
 pub struct RunOneArgs {
     /// Path to write the output netCDF file containing the matched groups of soundings
     pub output_file: PathBuf,
@@ -18,6 +35,7 @@ pub struct RunOneArgs {
     
     /// Set this flag to only include good quality soundings when calculating the matches
     #[clap(short='0', long)]
+    #[serde(default)]
     pub flag0_only: bool,
 
     /// Set this flag to indicate that we are looking for OCO-3 self crossings, rather than 
@@ -25,6 +43,7 @@ pub struct RunOneArgs {
     /// considered a match and change the output variable names to reflect more accurately
     /// what is being searched for.
     #[clap(long)]
+    #[serde(default)]
     pub oco3_self_cross: bool,
 
     /// Give this argument with a path to save a netCDF file containing an exact map of OCO-2 to OCO-3 soundings.
@@ -36,15 +55,879 @@ pub struct RunOneArgs {
     /// read in the full matches rather than calculating them from the OCO-2/3 lite files.
     #[clap(short='i', long)]
     pub read_full_matches: Option<PathBuf>,
+
+    /// Give this argument with an approximate memory limit in megabytes to find matches in blocks of
+    /// OCO-2 soundings, writing each block's matches to a temporary file next to the output file rather
+    /// than holding all of them in memory. Use this for very large inputs (e.g. month-long windows)
+    /// that otherwise run out of memory. Cannot be combined with --save-full-matches-as.
+    #[clap(long="max-memory")]
+    pub max_memory_mb: Option<usize>,
+
+    /// How close soundings must be to match: "circle:MAX_KM", "ellipse:ALONG_TRACK_KM,CROSS_TRACK_KM" (aligned with the
+    /// OCO-2 track), "latitude-scaled:EQUATOR_KM,POLE_KM", or "box:MAX_DLON_DEG,MAX_DLAT_DEG".
+    #[clap(long, default_value_t = DistanceCriterion::default())]
+    #[serde(default)]
+    pub distance_criterion: DistanceCriterion,
+
+    /// How to calculate distances between soundings: "haversine" (a sphere with the Earth's mean radius),
+    /// "vincenty" (the WGS84 ellipsoid; slower but accurate to well under a meter), or "equirectangular"
+    /// (fastest, but only accurate for short distances away from the poles).
+    #[clap(long, value_enum, default_value_t = DistanceModel::default())]
+    #[serde(default)]
+    pub distance_model: DistanceModel,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub mode_options: ModeOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub surface_options: SurfaceOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub region_options: RegionOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub nearest_options: NearestOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub score_options: ScoreOptions,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub grouping_options: GroupingOptions,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl RunOneArgs {
+    /// The default value of every option that has one, as it would be written in a configuration file. Options
+    /// with no value by default (e.g. `save_full_matches_as`) and the input and output paths are not included.
+    pub fn default_options_table() -> Result<toml::Table, MatchupError> {
+        let args = Self {
+            output_file: PathBuf::new(),
+            oco2_lite_file: PathBuf::new(),
+            oco3_lite_files: Vec::new(),
+            flag0_only: false,
+            oco3_self_cross: false,
+            save_full_matches_as: None,
+            read_full_matches: None,
+            max_memory_mb: None,
+            distance_criterion: DistanceCriterion::default(),
+            distance_model: DistanceModel::default(),
+            mode_options: ModeOptions::default(),
+            surface_options: SurfaceOptions::default(),
+            region_options: RegionOptions::default(),
+            nearest_options: NearestOptions::default(),
+            score_options: ScoreOptions::default(),
+            grouping_options: GroupingOptions::default(),
+        };
+
+        let mut table = toml::Table::try_from(args)?;
+        for key in ["output_file", "oco2_lite_file", "oco3_lite_files"] {
+            table.remove(key);
+        }
+        Ok(table)
+    }
+}
+
+/// Command line and configuration options restricting which operation modes are matched.
+///
+/// The "oco2" options apply to the first instrument's lite file and the "oco3" options to the second
+/// instrument's lite files; for OCO-3 self crossings, that is the first and second pass files.
+#[derive(Debug, Clone, Default, Args, Serialize, Deserialize)]
+pub struct ModeOptions {
+    /// Only match OCO-2 soundings in these operation modes (comma separated, e.g. "nadir,glint"). Default is all modes.
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub oco2_modes: Vec<OperationMode>,
+
+    /// Do not match OCO-2 soundings in these operation modes (comma separated).
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_oco2_modes: Vec<OperationMode>,
+
+    /// Only match OCO-3 soundings in these operation modes (comma separated, e.g. "sam,target"). Default is all modes.
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub oco3_modes: Vec<OperationMode>,
+
+    /// Do not match OCO-3 soundings in these operation modes (comma separated).
+    #[clap(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_oco3_modes: Vec<OperationMode>,
+
+    /// Only allow matches between these pairs of modes, given as OCO2MODE:OCO3MODE (e.g. "nadir:sam").
+    /// Repeat this option or separate pairs with commas to allow several pairs. Default is to allow any pair.
+    #[clap(long = "mode-pair", value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mode_pairs: Vec<ModePair>,
+}
+
+/// Command line and configuration options for classifying soundings and groups as land or ocean.
+#[derive(Debug, Clone, Args, Serialize, Deserialize)]
+pub struct SurfaceOptions {
+    /// Minimum land fraction, in percent, for a sounding to be classed as land.
+    #[clap(long, default_value_t = DEFAULT_LAND_THRESHOLD)]
+    #[serde(default = "default_land_threshold")]
+    pub land_threshold: f32,
+
+    /// Maximum land fraction, in percent, for a sounding to be classed as ocean. Soundings between
+    /// this and --land-threshold are classed as mixed.
+    #[clap(long, default_value_t = DEFAULT_OCEAN_THRESHOLD)]
+    #[serde(default = "default_ocean_threshold")]
+    pub ocean_threshold: f32,
+
+    /// Only match soundings of this surface class (from both instruments).
+    #[clap(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surface: Option<SurfaceClass>,
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        Self { land_threshold: DEFAULT_LAND_THRESHOLD, ocean_threshold: DEFAULT_OCEAN_THRESHOLD, surface: None }
+    }
+}
+
+fn default_land_threshold() -> f32 {
+    DEFAULT_LAND_THRESHOLD
+}
+
+fn default_ocean_threshold() -> f32 {
+    DEFAULT_OCEAN_THRESHOLD
+}
+
+/// Command line and configuration options giving regions of interest to restrict matches to.
+/// See [`crate::region::RegionSet::from_options`].
+#[derive(Debug, Clone, Default, Args, Serialize, Deserialize)]
+pub struct RegionOptions {
+    /// A region given as a bounding box, [NAME=]WEST,SOUTH,EAST,NORTH in degrees (e.g. "conus=-125,24,-66,50").
+    /// Give more than once for several regions.
+    #[clap(long)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub region_bbox: Vec<RegionBox>,
+
+    /// A GeoJSON file of regions; each feature with polygons is one region, named by its "name" property.
+    /// Give more than once for several files.
+    #[clap(long)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub region_geojson: Vec<PathBuf>,
+
+    /// A TOML file listing named regions, each with a bounding box, polygon, or GeoJSON file.
+    /// All of its regions are used unless --region is given.
+    #[clap(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region_file: Option<PathBuf>,
+
+    /// The name of a region in the --region-file to use. Give more than once for several regions.
+    #[clap(long = "region")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<String>,
+
+    /// How to use the regions: "soundings" only matches soundings inside a region, "groups" only keeps groups
+    /// with at least one sounding inside a region, and "tag" keeps everything. In every case, the output records
+    /// which regions each group is in.
+    #[clap(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub region_filter: RegionFilter,
+}
+
+impl RegionOptions {
+    /// Make relative region file paths relative to `dir`, e.g. the directory of the configuration file giving them.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        for geojson in self.region_geojson.iter_mut() {
+            *geojson = dir.join(&geojson);
+        }
+        self.region_file = self.region_file.take().map(|p| dir.join(p));
+    }
+
+    /// The reverse of [`RegionOptions::resolve_paths`]: make region file paths relative to `dir`, e.g. the directory
+    /// of a configuration file they are about to be written to (see [`path_relative_to`]).
+    pub fn make_paths_relative(&mut self, dir: &Path) {
+        for geojson in self.region_geojson.iter_mut() {
+            *geojson = path_relative_to(geojson, dir);
+        }
+        self.region_file = self.region_file.take().map(|p| path_relative_to(&p, dir));
+    }
+
+    /// The region files these options read, with the name of the option giving each
+    pub fn region_files(&self) -> impl Iterator<Item = (&'static str, &Path)> {
+        self.region_geojson.iter().map(|p| ("region_geojson", p.as_path()))
+            .chain(self.region_file.as_deref().map(|p| ("region_file", p)))
+    }
+}
+
+/// Command line and configuration options for keeping only the nearest match of each sounding.
+/// See [`crate::nearest::NearestRule::from_options`].
+#[derive(Debug, Clone, Default, Args, Serialize, Deserialize)]
+pub struct NearestOptions {
+    /// Keep only the nearest OCO-3 match of each OCO-2 sounding, by "distance", "time" difference, or
+    /// "space-time" score (see --space-time-distance-scale). Default is to keep every match.
+    #[clap(long, value_enum)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nearest: Option<NearestMetric>,
+
+    /// With --nearest, only keep a pair if the OCO-2 sounding is also the nearest match of the OCO-3 sounding.
+    #[clap(long)]
+    #[serde(default)]
+    pub mutual_nearest: bool,
+}
+
+/// Command line and configuration options for scoring matched pairs by their distance and time difference.
+/// See [`crate::score::MatchScore::from_options`].
+#[derive(Debug, Clone, Args, Serialize, Deserialize)]
+pub struct ScoreOptions {
+    /// Distance in kilometers that counts as a space-time score of 1. Each matched pair is scored as
+    /// sqrt((distance / this)^2 + (time difference / --space-time-time-scale)^2).
+    #[clap(long = "space-time-distance-scale", default_value_t = DEFAULT_DISTANCE_SCALE_KM)]
+    #[serde(default = "default_distance_scale_km")]
+    pub space_time_distance_scale_km: f32,
+
+    /// Time difference in seconds that counts as a space-time score of 1.
+    #[clap(long = "space-time-time-scale", default_value_t = DEFAULT_TIME_SCALE_S)]
+    #[serde(default = "default_time_scale_s")]
+    pub space_time_time_scale_s: f32,
+
+    /// Only match pairs with a space-time score of at most this, in addition to the distance and time limits.
+    #[clap(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_score: Option<f32>,
+}
+
+impl Default for ScoreOptions {
+    fn default() -> Self {
+        Self {
+            space_time_distance_scale_km: DEFAULT_DISTANCE_SCALE_KM,
+            space_time_time_scale_s: DEFAULT_TIME_SCALE_S,
+            max_score: None,
+        }
+    }
+}
+
+fn default_distance_scale_km() -> f32 {
+    DEFAULT_DISTANCE_SCALE_KM
+}
+
+fn default_time_scale_s() -> f32 {
+    DEFAULT_TIME_SCALE_S
+}
+
+/// Command line and configuration options for how matched soundings are grouped.
+/// See [`crate::grouping::GroupingRules::from_options`].
+#[derive(Debug, Clone, Args, Serialize, Deserialize)]
+pub struct GroupingOptions {
+    /// How to group matched soundings: "connected" groups all matches that share an OCO-3 sounding, "pass" makes
+    /// one group for each pair of OCO-2 and OCO-3 passes (see --pass-gap), and "orbit" one group for each pair of orbits.
+    #[clap(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub group_by: GroupingMode,
+
+    /// With --group-by pass, a gap of more than this many seconds between one instrument's consecutive soundings
+    /// (or a change of orbit) starts a new pass.
+    #[clap(long = "pass-gap", default_value_t = DEFAULT_PASS_GAP_S)]
+    #[serde(default = "default_pass_gap_s")]
+    pub pass_gap_s: f64,
+
+    /// Reject groups with fewer than this many soundings from either instrument.
+    #[clap(long, default_value_t = 1)]
+    #[serde(default = "default_min_group_soundings")]
+    pub min_group_soundings: usize,
+
+    /// Reject groups where less than this fraction (0 to 1) of either instrument's soundings have
+    /// xco2_quality_flag = 0.
+    #[clap(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_flag0_fraction: Option<f32>,
+
+    /// Reject groups where the standard deviation of either instrument's XCO2 is more than this many ppm.
+    #[clap(long = "max-xco2-std")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_xco2_std_ppm: Option<f32>,
+
+    /// What to do with groups rejected by --min-group-soundings, --min-flag0-fraction, or --max-xco2-std:
+    /// "drop" leaves them out of the output, "flag" keeps them with a nonzero rejection_reason.
+    #[clap(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub rejected_groups: RejectedGroups,
+}
+
+impl Default for GroupingOptions {
+    fn default() -> Self {
+        Self {
+            group_by: GroupingMode::default(),
+            pass_gap_s: DEFAULT_PASS_GAP_S,
+            min_group_soundings: default_min_group_soundings(),
+            min_flag0_fraction: None,
+            max_xco2_std_ppm: None,
+            rejected_groups: RejectedGroups::default(),
+        }
+    }
+}
+
+fn default_pass_gap_s() -> f64 {
+    DEFAULT_PASS_GAP_S
+}
+
+fn default_min_group_soundings() -> usize {
+    1
+}
+
+/// Configuration for running multiple matchups, read from or written to a TOML file.
+/// 
+/// In the TOML file, a top-level `[defaults]` table can give values for any of the
+/// `[[matchups]]` options (e.g. `flag0_only`); these are used for every matchup that
+/// does not set that option itself. A top-level `output_dir` key gives a directory
+/// that relative `output_file` and `save_full_matches_as` paths are resolved against.
+/// Both are applied when the file is read, so `matchups` always holds the complete
+/// options for each matchup.
+#[derive(Debug, Default, Clone)]
 pub struct RunMultiConfig {
+    /// Option values to use for matchups that do not specify them.
+    pub defaults: toml::Table,
+    /// Directory to resolve relative output paths against.
+    pub output_dir: Option<PathBuf>,
+    /// The options for each matchup, with defaults and the output directory applied.
     pub matchups: Vec<RunOneArgs>
 }
 
+/// The on-disk form of [`RunMultiConfig`], before defaults are merged into each matchup.
+#[derive(Debug, Serialize, Deserialize)]
+struct RunMultiConfigFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "toml::Table::is_empty")]
+    defaults: toml::Table,
+    matchups: Vec<toml::Table>
+}
+
+impl RunMultiConfig {
+    /// Read a configuration from a TOML file.
+    pub fn from_file(config_file: &Path) -> Result<Self, MatchupError> {
+        let mut buf = String::new();
+        let mut f = std::fs::File::open(config_file)?;
+        f.read_to_string(&mut buf)?;
+        let mut cfg = Self::from_toml_str(&buf)?;
+
+        // Region files are given relative to the configuration file, not the working directory. Resolve them in
+        // the defaults too, so that they still match the matchups' values when the configuration is written out.
+        if let Some(config_dir) = config_file.parent() {
+            map_region_paths(&mut cfg.defaults, |p| config_dir.join(p));
+            for matchup in cfg.matchups.iter_mut() {
+                matchup.region_options.resolve_paths(config_dir);
+            }
+        }
+        Ok(cfg)
+    }
+
+    /// Parse a configuration from the contents of a TOML file.
+    pub fn from_toml_str(s: &str) -> Result<Self, MatchupError> {
+        let cfg_file: RunMultiConfigFile = toml::from_str(s)?;
+
+        let matchups = cfg_file.matchups.into_iter()
+            .map(|mut entry| {
+                for (key, value) in cfg_file.defaults.iter() {
+                    if !entry.contains_key(key) {
+                        entry.insert(key.to_owned(), value.clone());
+                    }
+                }
+
+                let mut matchup: RunOneArgs = entry.try_into()?;
+                if let Some(output_dir) = cfg_file.output_dir.as_deref() {
+                    matchup.output_file = output_dir.join(&matchup.output_file);
+                    matchup.save_full_matches_as = matchup.save_full_matches_as.map(|p| output_dir.join(p));
+                }
+                Ok(matchup)
+            }).collect::<Result<Vec<_>, MatchupError>>()?;
+
+        Ok(Self { defaults: cfg_file.defaults, output_dir: cfg_file.output_dir, matchups })
+    }
+
+    /// Convert this configuration to a TOML string.
+    /// 
+    /// Only the options in `defaults` are written to the `[defaults]` table. Options in each matchup
+    /// that are the same as in `defaults` (or, for options not in `defaults`, the same as the
+    /// program's default; see [`RunOneArgs::default_options_table`]) are left out, and output
+    /// paths inside `output_dir` are written relative to it, so that the output is as short
+    /// as possible while still being read back as the same configuration.
+    pub fn to_toml_string(&self) -> Result<String, MatchupError> {
+        let builtin_defaults = RunOneArgs::default_options_table()?;
+
+        let matchups = self.matchups.iter()
+            .map(|m| {
+                let mut m = m.clone();
+                if let Some(output_dir) = self.output_dir.as_deref() {
+                    if let Ok(p) = m.output_file.strip_prefix(output_dir) {
+                        m.output_file = p.to_owned();
+                    }
+                    if let Some(Ok(p)) = m.save_full_matches_as.as_deref().map(|p| p.strip_prefix(output_dir)) {
+                        m.save_full_matches_as = Some(p.to_owned());
+                    }
+                }
+
+                let entry = toml::Table::try_from(m)?
+                    .into_iter()
+                    .filter(|(key, value)| {
+                        let default = self.defaults.get(key).or_else(|| builtin_defaults.get(key));
+                        default != Some(value)
+                    })
+                    .collect();
+                Ok(entry)
+            }).collect::<Result<Vec<_>, MatchupError>>()?;
+
+        let cfg_file = RunMultiConfigFile { output_dir: self.output_dir.clone(), defaults: self.defaults.clone(), matchups };
+        let cfg_str = toml::to_string_pretty(&cfg_file)?;
+        Ok(cfg_str)
+    }
+
+    /// Write this configuration to `config_file` as TOML. See [`RunMultiConfig::to_toml_string`]
+    /// for how the configuration is written. Region file paths are written relative to `config_file`'s
+    /// directory, so that [`RunMultiConfig::from_file`] reads them back as the same files.
+    pub fn write_toml(&self, config_file: &Path) -> Result<(), MatchupError> {
+        let config_dir = config_file.parent().unwrap_or(Path::new(""));
+        let mut cfg = self.clone();
+        map_region_paths(&mut cfg.defaults, |p| path_relative_to(p, config_dir));
+        for matchup in cfg.matchups.iter_mut() {
+            matchup.region_options.make_paths_relative(config_dir);
+        }
+
+        let cfg_str = cfg.to_toml_string()?;
+        let mut f = std::fs::File::create(config_file)?;
+        write!(f, "{}", cfg_str)?;
+        Ok(())
+    }
+}
+
+/// Apply `f` to the region file paths (`region_file` and `region_geojson`) in a table of matchup options.
+fn map_region_paths<F: Fn(&Path) -> PathBuf>(table: &mut toml::Table, f: F) {
+    if let Some(toml::Value::String(p)) = table.get_mut("region_file") {
+        *p = f(Path::new(p)).display().to_string();
+    }
+    if let Some(toml::Value::Array(paths)) = table.get_mut("region_geojson") {
+        for value in paths.iter_mut() {
+            if let toml::Value::String(p) = value {
+                *p = f(Path::new(p)).display().to_string();
+            }
+        }
+    }
+}
+
+/// `path` (relative to the working directory, or absolute) relative to `dir` instead. Paths that are not inside
+/// `dir` are made absolute.
+fn path_relative_to(path: &Path, dir: &Path) -> PathBuf {
+    if dir.as_os_str().is_empty() {
+        return path.to_owned();
+    }
+    if let Ok(p) = path.strip_prefix(dir) {
+        return p.to_owned();
+    }
+    if path.is_absolute() {
+        return path.to_owned();
+    }
+    std::env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_owned())
+}
+
 #[derive(Debug, Args)]
 pub struct RunMultiArgs {
     /// Path to the TOML configuration file that specifies how to run multiple matchups
+    pub config_file: PathBuf,
+
+    #[command(flatten)]
+    pub run_options: MultiRunOptions,
+}
+
+/// Options controlling how a set of multiple matchups is run, shared by the subcommands
+/// that run more than one matchup.
+#[derive(Debug, Args)]
+pub struct MultiRunOptions {
+    /// Set this flag to skip any matchup whose output file already exists, whether or not
+    /// that file was completely written.
+    #[clap(long)]
+    pub skip_existing: bool,
+
+    /// Set this flag to skip matchups whose output files (including the full match file, if
+    /// one is requested) exist and were completely written. Any other matchups are rerun and
+    /// their incomplete outputs overwritten.
+    #[clap(long)]
+    pub resume: bool,
+
+    /// Give this argument with a path to write a TOML report listing the status, run time, and number
+    /// of soundings, matches, and groups for each matchup, along with the error for any that failed.
+    #[clap(long)]
+    pub report: Option<PathBuf>,
+
+    /// Give this argument with a path to write a TOML configuration containing only the matchups
+    /// that failed, which can be passed back to the `multi` subcommand to rerun them.
+    #[clap(long)]
+    pub failed_config: Option<PathBuf>,
+
+    /// Maximum memory in megabytes to use to keep lite files loaded for reuse by other matchups.
+    /// Each lite file is normally read once and shared by all the matchups that use it; if this
+    /// limit is reached, files may need to be read again. Set to 0 to disable this sharing.
+    #[clap(long, default_value_t = 4096)]
+    pub cache_memory_mb: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// The match (group) files to check.
+    #[clap(required = true)]
+    pub match_files: Vec<PathBuf>,
+}
+
+/// Options for finding lite files whose paths recorded in match files are not valid on this system,
+/// e.g. because the match files were made on another system or the lite files have moved.
+#[derive(Debug, Args)]
+pub struct RemapOptions {
+    /// A path prefix replacement to apply to lite file paths recorded in match files, given as
+    /// OLD_PREFIX=NEW_PREFIX. Can be given multiple times; the first matching prefix is used.
+    #[clap(long = "remap", global = true)]
+    pub remap: Vec<PrefixRule>,
+
+    /// A directory to look for lite files in (by file name) if they are not found at their recorded
+    /// (or remapped) path. When the match file recorded checksums, only files with the same checksum
+    /// are used. Can be given multiple times; directories are searched in order.
+    #[clap(long = "search-dir", global = true)]
+    pub search_dirs: Vec<PathBuf>,
+}
+
+/// Options controlling how the SHA-256 checksums of the input lite files recorded in the output files are computed.
+#[derive(Debug, Args)]
+pub struct ChecksumOptions {
+    /// Set this flag to skip computing checksums of the input lite files. The output files will not
+    /// contain the checksum variables. This saves reading every input file in full, which is useful
+    /// for quick exploratory runs.
+    #[clap(long)]
+    pub no_checksums: bool,
+
+    /// Give this argument with a path to a file in which to store the checksums of input lite files
+    /// between runs. Checksums are only recomputed for files whose size or modification time changed.
+    #[clap(long, conflicts_with = "no_checksums")]
+    pub checksum_cache: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RunRangeArgs {
+    #[command(flatten)]
+    pub date_range: DateRangeArgs,
+
+    /// Give this argument with a path to also write the matchups run as a TOML configuration
+    /// file, identical to what `make-oco-match-config` would produce with the same arguments.
+    #[clap(long)]
+    pub write_config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub run_options: MultiRunOptions,
+}
+
+/// Arguments that define a set of daily matchups between two directory trees of lite files.
+#[derive(Debug, Args)]
+pub struct DateRangeArgs {
+    /// A string that gives the directory structure that lite files are found in. This can
+    /// include format substrings recognized by chrono for date formatting; the most common 
+    /// are %Y for four-digit year, %m for two-digit month, and %d for two-digit day. For 
+    /// example, the string "/data/%Y/%m/%d/lite" indicates that the data are in year/month/day
+    /// directories under "/data" with a "lite" subdirectory for each day directory. Note that 
+    /// at present, this tool only supports directory structures where there is one .nc4 file
+    /// per directory. See https://docs.rs/chrono/latest/chrono/format/strftime/index.html for
+    /// the full list of chrono format specifiers. Without --oco3-self-cross, this must be
+    /// the directory structure for OCO-2 lite files. With --oco3-self-cross, this will be
+    /// the OCO-3 lite file directory structure.
+    #[arg(long="first-dir", value_parser = DirStructure::from_str)]
+    pub first_dir_structure: DirStructure,
+
+    /// If --oco3-self-cross is not specified, then this must be the OCO-3 lite file directory
+    /// structure. In that case, it has the same format as --first-dir. If --oco3-self-cross
+    /// is given, then this can be omitted.
+    #[arg(long="second-dir", value_parser = DirStructure::from_str)]
+    pub second_dir_structure: Option<DirStructure>,
+
+    /// First OCO-2 date to search for matchups, in YYYY-MM-DD format. There will be one
+    /// matchup for each date between start_date and end_date (inclusive).
+    #[clap(long="start")]
+    pub start_date: NaiveDate,
+
+    /// Last OCO-2 date to search for matchups, in YYYY-MM-DD format.
+    #[clap(long="end")]
+    pub end_date: NaiveDate,
+
+    /// Number of days on either side of the OCO-2 file to include OCO-3 files from in the matchups.
+    /// That is, 0 will only match OCO-3 data from the lite file with the same date's OCO-2 file, while
+    /// a value of 1 will include 3 OCO-3 files (day before, same day, and day after the OCO-2 file).
+    #[clap(long="ndays")]
+    pub ndays_buffer: u32,
+
+    /// Pattern to use for the match output netCDF files. Date formatting patterns (e.g. %Y, %m, %d) 
+    /// recognized by chrono can be used to insert the OCO-2 date in the file name.
+    #[arg(long="out-fmt", default_value = "oco_lite_matches_%Y%m%d.nc4")]
+    pub outfile_format: String,
+
+    /// Directory to write the match output netCDF files to. The output file names given by --out-fmt
+    /// are relative to this, and it is written as the `output_dir` key of any configuration file.
+    #[clap(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Use this flag to only include good quality soundings when calculating the matches
+    #[clap(short='0', long)]
+    pub flag0_only: bool,
+
+    /// Use this option to indicate that we want to look for OCO-3 self crossings. This changes the match
+    /// rules to avoid all points next to each other counting as a "match" and modifies the output format
+    /// to reflect what's being matched. This also eliminates the need for the --second-dir argument. 
+    #[clap(long)]
+    pub oco3_self_cross: bool,
+
+    /// How close soundings must be to match; see the `one` subcommand's --distance-criterion.
+    #[clap(long, default_value_t = DistanceCriterion::default())]
+    pub distance_criterion: DistanceCriterion,
+
+    /// How to calculate distances between soundings; see the `one` subcommand's --distance-model.
+    #[clap(long, value_enum, default_value_t = DistanceModel::default())]
+    pub distance_model: DistanceModel,
+
+    #[command(flatten)]
+    pub mode_options: ModeOptions,
+
+    #[command(flatten)]
+    pub surface_options: SurfaceOptions,
+
+    #[command(flatten)]
+    pub region_options: RegionOptions,
+
+    #[command(flatten)]
+    pub nearest_options: NearestOptions,
+
+    #[command(flatten)]
+    pub score_options: ScoreOptions,
+
+    #[command(flatten)]
+    pub grouping_options: GroupingOptions,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// Path to the TOML configuration file (as used by the `multi` subcommand) to check
     pub config_file: PathBuf
-}
\ No newline at end of file
+}
+
+/// Build the configuration for daily matchups over a range of dates.
+/// 
+/// For each date from `args.start_date` to `args.end_date`, this finds the first instrument's
+/// lite file for that date and the second instrument's files for that date plus `args.ndays_buffer`
+/// days on either side. Dates missing any of those files are skipped with a message printed to
+/// stderr. The options shared by all matchups are put in the `defaults` table of the returned
+/// configuration.
+pub fn matchups_for_date_range(args: &DateRangeArgs) -> Result<RunMultiConfig, MatchupError> {
+    let mut matchups = Vec::new();
+
+    if args.second_dir_structure.is_none() && !args.oco3_self_cross {
+        return Err(MatchupError::ArgumentError("May only omit --second-dir when the --oco3-self-cross flag is present".to_string()));
+    }
+
+    let second_dir_structure = args.second_dir_structure.as_ref().unwrap_or(&args.first_dir_structure);
+    for (first_date, second_dates) in MatchupIter::new(args.start_date, args.end_date, args.ndays_buffer) {
+        let first_dir = args.first_dir_structure.dir_for_date(first_date);
+        let first_file = find_nc4_file(&first_dir)?;
+        let first_file = if let Some(f) = first_file {
+            f
+        }else{
+            eprintln!("Skipping matchup for {first_date} due to missing OCO-2 file");
+            continue;
+        };
+
+        let second_files = second_dates.iter()
+            .filter_map(|&d| {
+                let oco3_dir = second_dir_structure.dir_for_date(d);
+                find_nc4_file(&oco3_dir).transpose()
+            }).collect::<Result<Vec<_>, _>>()?;
+        if second_files.len() < second_dates.len() {
+            eprintln!("Skipping matchup for {first_date} due to at least one missing OCO-3 file");
+            continue;
+        }
+
+        let output_file = PathBuf::from(first_date.format(&args.outfile_format).to_string());
+        let output_file = if let Some(output_dir) = args.output_dir.as_deref() {
+            output_dir.join(output_file)
+        } else {
+            output_file
+        };
+
+        let this_args = RunOneArgs {
+            output_file,
+            oco2_lite_file: first_file,
+            oco3_lite_files: second_files,
+            flag0_only: args.flag0_only,
+            oco3_self_cross: args.oco3_self_cross,
+            save_full_matches_as: None,
+            read_full_matches: None,
+            max_memory_mb: None,
+            distance_criterion: args.distance_criterion,
+            distance_model: args.distance_model,
+            mode_options: args.mode_options.clone(),
+            surface_options: args.surface_options.clone(),
+            region_options: args.region_options.clone(),
+            nearest_options: args.nearest_options.clone(),
+            score_options: args.score_options.clone(),
+            grouping_options: args.grouping_options.clone(),
+        };
+
+        matchups.push(this_args);
+    }
+
+    // The options shared by every matchup go in the defaults table, so that
+    // they can be changed for the whole set of matchups in one place.
+    let mut defaults = toml::Table::new();
+    defaults.insert("flag0_only".to_owned(), toml::Value::Boolean(args.flag0_only));
+    defaults.insert("oco3_self_cross".to_owned(), toml::Value::Boolean(args.oco3_self_cross));
+    defaults.insert("distance_criterion".to_owned(), toml::Value::String(args.distance_criterion.to_string()));
+    defaults.insert("distance_model".to_owned(), toml::Value::String(args.distance_model.to_string()));
+    defaults.extend(toml::Table::try_from(&args.mode_options)?);
+    defaults.extend(toml::Table::try_from(&args.surface_options)?);
+    defaults.extend(toml::Table::try_from(&args.region_options)?);
+    defaults.extend(toml::Table::try_from(&args.nearest_options)?);
+    defaults.extend(toml::Table::try_from(&args.score_options)?);
+    defaults.extend(toml::Table::try_from(&args.grouping_options)?);
+
+    Ok(RunMultiConfig{ defaults, output_dir: args.output_dir.clone(), matchups })
+}
+
+struct MatchupIter {
+    curr_date: NaiveDate,
+    end_date: NaiveDate,
+    ndays_buffer: u32
+}
+
+impl MatchupIter {
+    fn new(start_date: NaiveDate, end_date: NaiveDate, ndays_buffer: u32) -> Self {
+        Self { curr_date: start_date, end_date, ndays_buffer }
+    }
+}
+
+impl Iterator for MatchupIter {
+    type Item = (NaiveDate, Vec<NaiveDate>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.curr_date > self.end_date {
+            return None
+        }
+
+        let n = self.ndays_buffer as i64;
+        let oco3_dates = (-n..=n)
+            .map(|d| {
+                let dur = Duration::days(d);
+                self.curr_date + dur
+            }).collect_vec();
+
+        let tup = (self.curr_date, oco3_dates);
+        self.curr_date += Duration::days(1);
+        Some(tup)
+    }
+}
+
+/// A directory path template with chrono date format specifiers, e.g. "/data/%Y/%m/%d".
+#[derive(Debug, Clone)]
+pub struct DirStructure {
+    pattern: String
+}
+
+impl Default for DirStructure {
+    fn default() -> Self {
+        Self { pattern: "%Y/%m/%d".to_owned() }
+    }
+}
+
+impl FromStr for DirStructure {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self { pattern: s.to_owned() })
+    }
+}
+
+impl DirStructure {
+    pub fn dir_for_date(&self, date: NaiveDate) -> PathBuf {
+        PathBuf::from(date.format(&self.pattern).to_string())
+    }
+}
+
+fn find_nc4_file(dir: &Path) -> Result<Option<PathBuf>, MatchupError> {
+    let mut files = Vec::new();
+    if !dir.exists() {
+        eprintln!("Directory {} does not exist", dir.display());
+        return Ok(None)
+    }
+
+
+    let nc4_ext = OsString::from("nc4");
+    for entry in std::fs::read_dir(dir)? {
+        let p = entry?.path();
+        if p.extension() == Some(&nc4_ext) {
+            files.push(p);
+        }
+    }
+
+    if files.len() == 1 {
+        Ok(Some(files.pop().unwrap()))
+    } else if files.is_empty() {
+        Ok(None)
+    } else {
+        Err(MatchupError::InternalError("Case of multiple .nc4 files in a single directory not implemented".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_paths_round_trip() {
+        let test_dir = std::env::temp_dir().join(format!("oco_lite_matchup_test_{}_config", std::process::id()));
+        let config_dir = test_dir.join("configs");
+        let other_dir = test_dir.join("other");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::create_dir_all(&other_dir).unwrap();
+
+        let config_file = config_dir.join("run.toml");
+        std::fs::write(&config_file, r#"
+            [defaults]
+            region_file = "regions.toml"
+
+            [[matchups]]
+            output_file = "out1.nc"
+            oco2_lite_file = "oco2_a.nc4"
+            oco3_lite_files = ["oco3_a.nc4"]
+
+            [[matchups]]
+            output_file = "out2.nc"
+            oco2_lite_file = "oco2_b.nc4"
+            oco3_lite_files = ["oco3_b.nc4"]
+            region_geojson = ["shapes/a.geojson"]
+        "#).unwrap();
+
+        let cfg = RunMultiConfig::from_file(&config_file).unwrap();
+        let expected_file = config_dir.join("regions.toml");
+        assert!(cfg.matchups.iter().all(|m| m.region_options.region_file.as_deref() == Some(expected_file.as_path())));
+        assert_eq!(cfg.matchups[1].region_options.region_geojson, vec![config_dir.join("shapes/a.geojson")]);
+
+        // Written next to the original, the paths stay relative and the shared one stays in [defaults] only
+        let same_dir_file = config_dir.join("failed.toml");
+        cfg.write_toml(&same_dir_file).unwrap();
+        let written = std::fs::read_to_string(&same_dir_file).unwrap();
+        assert_eq!(written.matches("regions.toml").count(), 1, "{written}");
+
+        // Written anywhere, the paths must be read back as the same files
+        for out_file in [same_dir_file, other_dir.join("failed.toml")] {
+            cfg.write_toml(&out_file).unwrap();
+            let reread = RunMultiConfig::from_file(&out_file).unwrap();
+            for (a, b) in reread.matchups.iter().zip(cfg.matchups.iter()) {
+                assert_eq!(a.region_options.region_file, b.region_options.region_file);
+                assert_eq!(a.region_options.region_geojson, b.region_options.region_geojson);
+            }
+        }
+
+        std::fs::remove_dir_all(&test_dir).ok();
+    }
+}
@@ -0,0 +1,249 @@
+//! Criteria for whether two soundings are close enough together to be a match.
+//!
+//! Criteria are written as `KIND:PARAMETERS`, e.g. `circle:100` or `ellipse:150,50` (see [`DistanceCriterion`]).
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::MatchupError;
+use crate::geodesy;
+use crate::utils::DEG2RAD;
+
+/// Default radius for the circle criterion, in kilometers
+pub const DEFAULT_MAX_DISTANCE_KM: f32 = 100.0;
+
+/// How to decide whether a second instrument sounding is close enough to a first instrument sounding to match.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DistanceCriterion {
+    /// Great circle distance of at most `max_km`. Written `circle:MAX_KM`.
+    Circle { max_km: f32 },
+    /// Inside an ellipse centered on the first instrument's sounding and aligned with its track, with semi-axes
+    /// `along_track_km` and `cross_track_km`. Written `ellipse:ALONG_KM,CROSS_KM`.
+    Ellipse { along_track_km: f32, cross_track_km: f32 },
+    /// Great circle distance of at most a limit that varies linearly with the absolute latitude of the first
+    /// instrument's sounding, from `equator_km` at the equator to `pole_km` at the poles. Written
+    /// `latitude-scaled:EQUATOR_KM,POLE_KM`.
+    LatitudeScaled { equator_km: f32, pole_km: f32 },
+    /// Longitude and latitude differences of at most `max_dlon_deg` and `max_dlat_deg` degrees. Written `box:DLON,DLAT`.
+    Box { max_dlon_deg: f32, max_dlat_deg: f32 },
+}
+
+impl Default for DistanceCriterion {
+    fn default() -> Self {
+        Self::Circle { max_km: DEFAULT_MAX_DISTANCE_KM }
+    }
+}
+
+impl DistanceCriterion {
+    /// Whether the criterion needs the heading of the first instrument's track at each sounding.
+    pub fn needs_heading(&self) -> bool {
+        matches!(self, Self::Ellipse { .. })
+    }
+
+    /// Whether a second instrument sounding at (`lon2`, `lat2`) matches a first instrument sounding at (`lon1`, `lat1`).
+    ///
+    /// `dist_km` must be the distance between them from the run's [`crate::geodesy::DistanceModel`], and `heading` the first instrument's track heading
+    /// in degrees clockwise from north if [`DistanceCriterion::needs_heading`] is true. Where the heading is not known
+    /// (NaN), the ellipse criterion falls back to a circle with its smaller semi-axis as the radius.
+    pub fn accepts(&self, dist_km: f32, lon1: f32, lat1: f32, lon2: f32, lat2: f32, heading: f32) -> bool {
+        match *self {
+            Self::Circle { max_km } => dist_km <= max_km,
+            Self::Ellipse { along_track_km, cross_track_km } => {
+                let max_axis = along_track_km.max(cross_track_km);
+                if dist_km > max_axis {
+                    return false;
+                }
+                if heading.is_nan() {
+                    return dist_km <= along_track_km.min(cross_track_km);
+                }
+
+                let (dx, dy) = local_offset_km(lon1, lat1, lon2, lat2);
+                let (sin_h, cos_h) = (heading * DEG2RAD).sin_cos();
+                let along = dx * sin_h + dy * cos_h;
+                let cross = dx * cos_h - dy * sin_h;
+                (along / along_track_km).powi(2) + (cross / cross_track_km).powi(2) <= 1.0
+            },
+            Self::LatitudeScaled { equator_km, pole_km } => {
+                let frac = lat1.abs().min(90.0) / 90.0;
+                dist_km <= equator_km + (pole_km - equator_km) * frac
+            },
+            Self::Box { max_dlon_deg, max_dlat_deg } => box_contains(max_dlon_deg, max_dlat_deg, lon1, lat1, lon2, lat2),
+        }
+    }
+
+    /// A quick check of whether a second instrument sounding at (`lon2`, `lat2`) could match a first instrument sounding
+    /// at (`lon1`, `lat1`), for use before computing the distance between them. The box criterion, which does not depend
+    /// on the distance, is checked completely; the others always pass here and are limited by
+    /// [`DistanceCriterion::max_distance_km`] instead.
+    pub fn may_accept(&self, lon1: f32, lat1: f32, lon2: f32, lat2: f32) -> bool {
+        match *self {
+            Self::Box { max_dlon_deg, max_dlat_deg } => box_contains(max_dlon_deg, max_dlat_deg, lon1, lat1, lon2, lat2),
+            _ => true,
+        }
+    }
+
+    /// The largest distance that can possibly match, if the criterion has one. This is the bound used to skip pairs
+    /// of soundings that are certainly too far apart before computing their distance (see
+    /// [`crate::geodesy::may_be_within`]), and is also used to describe the criterion in output files.
+    pub fn max_distance_km(&self) -> Option<f32> {
+        match *self {
+            Self::Circle { max_km } => Some(max_km),
+            Self::Ellipse { along_track_km, cross_track_km } => Some(along_track_km.max(cross_track_km)),
+            Self::LatitudeScaled { equator_km, pole_km } => Some(equator_km.max(pole_km)),
+            Self::Box { .. } => None,
+        }
+    }
+}
+
+/// Whether the second point is within `max_dlon_deg` of longitude and `max_dlat_deg` of latitude of the first.
+fn box_contains(max_dlon_deg: f32, max_dlat_deg: f32, lon1: f32, lat1: f32, lon2: f32, lat2: f32) -> bool {
+    let dlon = ((lon2 - lon1) + 180.0).rem_euclid(360.0) - 180.0;
+    dlon.abs() <= max_dlon_deg && (lat2 - lat1).abs() <= max_dlat_deg
+}
+
+/// East and north offsets in kilometers of the second point from the first, in an equirectangular projection
+/// centered between them.
+fn local_offset_km(lon1: f32, lat1: f32, lon2: f32, lat2: f32) -> (f32, f32) {
+    let (lon1, lat1, lon2, lat2) = (lon1 as f64, lat1 as f64, lon2 as f64, lat2 as f64);
+    let (dx, dy) = geodesy::equirectangular_offset_km(lon1, lat1, lon2, lat2, 0.5 * (lat1 + lat2));
+    (dx as f32, dy as f32)
+}
+
+impl FromStr for DistanceCriterion {
+    type Err = MatchupError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = s.split_once(':').unwrap_or((s, ""));
+        let params = params.split(',')
+            .filter(|p| !p.trim().is_empty())
+            .map(|p| p.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| MatchupError::ArgumentError(format!("Invalid number in distance criterion '{s}': {e}")))?;
+
+        let expect_n = |n: usize, form: &str| {
+            if params.len() != n {
+                Err(MatchupError::ArgumentError(format!("Distance criterion '{s}' must be written as {form}")))
+            } else if params.iter().any(|&p| !p.is_finite() || p <= 0.0) {
+                Err(MatchupError::ArgumentError(format!("Distance criterion '{s}' must have positive limits")))
+            } else {
+                Ok(())
+            }
+        };
+
+        match kind.trim() {
+            "circle" => {
+                expect_n(1, "circle:MAX_KM")?;
+                Ok(Self::Circle { max_km: params[0] })
+            },
+            "ellipse" => {
+                expect_n(2, "ellipse:ALONG_TRACK_KM,CROSS_TRACK_KM")?;
+                Ok(Self::Ellipse { along_track_km: params[0], cross_track_km: params[1] })
+            },
+            "latitude-scaled" => {
+                expect_n(2, "latitude-scaled:EQUATOR_KM,POLE_KM")?;
+                Ok(Self::LatitudeScaled { equator_km: params[0], pole_km: params[1] })
+            },
+            "box" => {
+                expect_n(2, "box:MAX_DLON_DEG,MAX_DLAT_DEG")?;
+                Ok(Self::Box { max_dlon_deg: params[0], max_dlat_deg: params[1] })
+            },
+            _ => Err(MatchupError::ArgumentError(format!(
+                "Unknown distance criterion '{kind}', expected one of: circle, ellipse, latitude-scaled, box"
+            )))
+        }
+    }
+}
+
+impl TryFrom<String> for DistanceCriterion {
+    type Error = MatchupError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<DistanceCriterion> for String {
+    fn from(value: DistanceCriterion) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for DistanceCriterion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Circle { max_km } => write!(f, "circle:{max_km}"),
+            Self::Ellipse { along_track_km, cross_track_km } => write!(f, "ellipse:{along_track_km},{cross_track_km}"),
+            Self::LatitudeScaled { equator_km, pole_km } => write!(f, "latitude-scaled:{equator_km},{pole_km}"),
+            Self::Box { max_dlon_deg, max_dlat_deg } => write!(f, "box:{max_dlon_deg},{max_dlat_deg}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Kilometers per degree of latitude on the sphere used by the distance models
+    const KM_PER_DEG: f32 = (geodesy::EARTH_MEAN_RADIUS_KM * std::f64::consts::PI / 180.0) as f32;
+
+    #[test]
+    fn test_circle() {
+        let crit: DistanceCriterion = "circle:100".parse().unwrap();
+        assert!(crit.accepts(99.0, 0.0, 0.0, 0.5, 0.5, f32::NAN));
+        assert!(!crit.accepts(101.0, 0.0, 0.0, 0.5, 0.5, f32::NAN));
+        assert_eq!(crit.max_distance_km(), Some(100.0));
+    }
+
+    #[test]
+    fn test_ellipse_along_and_across_track() {
+        let crit: DistanceCriterion = "ellipse:150,50".parse().unwrap();
+        assert!(crit.needs_heading());
+
+        // Heading north: 120 km north is along track, 120 km east is across track
+        let dlat = 120.0 / KM_PER_DEG;
+        assert!(crit.accepts(120.0, 10.0, 0.0, 10.0, dlat, 0.0));
+        let dlon = 120.0 / KM_PER_DEG;
+        assert!(!crit.accepts(120.0, 10.0, 0.0, 10.0 + dlon, 0.0, 0.0));
+
+        // Heading east, the same offsets swap roles
+        assert!(!crit.accepts(120.0, 10.0, 0.0, 10.0, dlat, 90.0));
+        assert!(crit.accepts(120.0, 10.0, 0.0, 10.0 + dlon, 0.0, 90.0));
+
+        // Without a heading, only the smaller semi-axis is allowed in any direction
+        assert!(crit.accepts(45.0, 10.0, 0.0, 10.0, 45.0 / KM_PER_DEG, f32::NAN));
+        assert!(!crit.accepts(120.0, 10.0, 0.0, 10.0, dlat, f32::NAN));
+        // Anything beyond the larger semi-axis is rejected regardless of direction
+        assert!(!crit.accepts(160.0, 10.0, 0.0, 10.0, 160.0 / KM_PER_DEG, 0.0));
+        assert_eq!(crit.max_distance_km(), Some(150.0));
+    }
+
+    #[test]
+    fn test_latitude_scaled() {
+        let crit: DistanceCriterion = "latitude-scaled:100,300".parse().unwrap();
+        assert!(crit.accepts(100.0, 0.0, 0.0, 0.5, 0.5, f32::NAN));
+        assert!(!crit.accepts(150.0, 0.0, 0.0, 0.5, 0.5, f32::NAN));
+        assert!(crit.accepts(190.0, 0.0, 45.0, 0.5, 45.5, f32::NAN));
+        assert!(!crit.accepts(210.0, 0.0, -45.0, 0.5, -45.5, f32::NAN));
+        assert!(crit.accepts(300.0, 0.0, 90.0, 0.5, 89.5, f32::NAN));
+        assert_eq!(crit.max_distance_km(), Some(300.0));
+    }
+
+    #[test]
+    fn test_box() {
+        let crit: DistanceCriterion = "box:2,1".parse().unwrap();
+        // The box ignores the distance entirely
+        assert!(crit.accepts(f32::INFINITY, 10.0, 5.0, 11.5, 5.5, f32::NAN));
+        assert!(!crit.accepts(0.0, 10.0, 5.0, 12.5, 5.5, f32::NAN));
+        assert!(!crit.accepts(0.0, 10.0, 5.0, 11.5, 6.5, f32::NAN));
+        // Longitude differences wrap around the dateline
+        assert!(crit.accepts(f32::INFINITY, 179.5, 0.0, -179.0, 0.5, f32::NAN));
+        assert!(!crit.accepts(f32::INFINITY, 179.5, 0.0, -178.0, 0.5, f32::NAN));
+
+        assert_eq!(crit.max_distance_km(), None);
+        assert!(crit.may_accept(179.5, 0.0, -179.0, 0.5));
+        assert!(!crit.may_accept(10.0, 5.0, 12.5, 5.5));
+        assert!("circle:100".parse::<DistanceCriterion>().unwrap().may_accept(0.0, 0.0, 90.0, 45.0));
+    }
+}
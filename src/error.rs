@@ -37,10 +37,24 @@ pub enum MatchupError {
     /// An error variant indicating a problem creating a configuration file.
     ConfigWriteError(toml::ser::Error),
 
+    /// An error variant indicating that a configuration file could be parsed, but
+    /// has one or more problems that would prevent the matchups in it from running.
+    InvalidConfig{file: PathBuf, problems: Vec<String>},
+
     /// An error variant to use when an assumption about how different parts of this
     /// program work together is broken.
     InternalError(String),
 
+    /// An error variant that records which matchup (identified by its output file) another
+    /// error occurred in, used when running many matchups at once.
+    MatchupFailed{output_file: PathBuf, error: Box<Self>},
+
+    /// An error variant indicating a problem with a file defining regions of interest.
+    RegionError{file: Option<PathBuf>, reason: String},
+
+    /// An error variant listing match files whose input lite files have changed or are missing.
+    StaleMatchFiles(Vec<PathBuf>),
+
     /// An error variant representing multiple instances of this error type, e.g. if
     /// running functions in parallel and >1 return different errors.
     MultipleErrors(Vec<Self>)
@@ -78,7 +92,11 @@ impl MatchupError {
             MatchupError::IOError(e) => Self::IOError(e),
             MatchupError::ConfigError(_) => self,
             MatchupError::ConfigWriteError(_) => self,
+            MatchupError::InvalidConfig { .. } => self,
             MatchupError::InternalError(s) => Self::InternalError(s),
+            MatchupError::MatchupFailed { output_file, error } => Self::MatchupFailed { output_file, error: Box::new(error.set_file(p)) },
+            MatchupError::RegionError { file: _, reason } => Self::RegionError { file: Some(p), reason },
+            MatchupError::StaleMatchFiles(_) => self,
             MatchupError::MultipleErrors(_) => self
         }
     }
@@ -128,7 +146,29 @@ impl Display for MatchupError {
             MatchupError::IOError(e) => write!(f, "Error reading a file: {e}"),
             MatchupError::ConfigError(e) => write!(f, "Error reading configuration: {e}"),
             MatchupError::ConfigWriteError(e) => write!(f, "Error writing configuration: {e}"),
+            MatchupError::InvalidConfig { file, problems } => {
+                writeln!(f, "Configuration {} has {} problems:", file.display(), problems.len())?;
+                for (i, p) in problems.iter().enumerate() {
+                    writeln!(f, "{}. {p}", i+1)?;
+                }
+                Ok(())
+            },
             MatchupError::InternalError(s) => write!(f, "Internal error in matchup code, cause: {s}"),
+            MatchupError::MatchupFailed { output_file, error } => write!(f, "Matchup for {} failed: {error}", output_file.display()),
+            MatchupError::RegionError { file, reason } => {
+                if let Some(p) = file {
+                    write!(f, "Error in region file {}: {reason}", p.display())
+                } else {
+                    write!(f, "Error in region definition: {reason}")
+                }
+            },
+            MatchupError::StaleMatchFiles(files) => {
+                writeln!(f, "{} match files are stale:", files.len())?;
+                for p in files.iter() {
+                    writeln!(f, "  {}", p.display())?;
+                }
+                Ok(())
+            },
             MatchupError::MultipleErrors(errs) => {
                 writeln!(f, "{} matchups had errors. The errors were:", errs.len())?;
                 for (i, e) in errs.iter().enumerate() {
@@ -0,0 +1,285 @@
+//! Distances between points on the Earth's surface.
+//!
+//! Three models are available (see [`DistanceModel`]): the haversine formula on a sphere with the Earth's mean
+//! radius, Vincenty's inverse formula on the WGS84 ellipsoid, and an equirectangular approximation that is much
+//! faster but only accurate for short distances away from the poles. All calculations are done in `f64`.
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Mean radius of the Earth (IUGG R1), in kilometers
+pub const EARTH_MEAN_RADIUS_KM: f64 = 6371.0088;
+/// WGS84 semi-major axis, in kilometers
+pub const WGS84_A_KM: f64 = 6378.137;
+/// WGS84 flattening
+pub const WGS84_F: f64 = 1.0 / 298.257223563;
+/// Smallest radius of curvature of the WGS84 ellipsoid (the meridional radius at the equator), in kilometers.
+/// No two points are closer together than this times their latitude difference in radians.
+const WGS84_MIN_RADIUS_KM: f64 = WGS84_A_KM * (1.0 - WGS84_F) * (1.0 - WGS84_F);
+/// Relative margin allowed for the error of the equirectangular approximation when prefiltering
+const PREFILTER_MARGIN: f64 = 1.05;
+/// Absolute margin in kilometers allowed for the error of the equirectangular approximation when prefiltering
+const PREFILTER_MARGIN_KM: f64 = 1.0;
+/// Poleward of this latitude, the equirectangular approximation is not used for prefiltering
+const PREFILTER_MAX_LATITUDE: f64 = 80.0;
+const VINCENTY_MAX_ITER: usize = 200;
+const VINCENTY_TOLERANCE: f64 = 1e-12;
+
+/// How to calculate the distance between two points.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DistanceModel {
+    /// Great circle distance on a sphere with the Earth's mean radius
+    #[default]
+    Haversine,
+    /// Geodesic distance on the WGS84 ellipsoid, using Vincenty's formula
+    Vincenty,
+    /// Equirectangular approximation on a sphere with the Earth's mean radius. Fast, but only accurate to within
+    /// about 1% for distances of a few hundred kilometers at latitudes below 80 degrees.
+    Equirectangular,
+}
+
+impl DistanceModel {
+    /// Distance in kilometers between two points given in degrees.
+    pub fn distance_km(&self, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+        match self {
+            Self::Haversine => haversine_km(lon1, lat1, lon2, lat2),
+            Self::Vincenty => vincenty_km(lon1, lat1, lon2, lat2),
+            Self::Equirectangular => equirectangular_km(lon1, lat1, lon2, lat2),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Haversine => "haversine",
+            Self::Vincenty => "vincenty",
+            Self::Equirectangular => "equirectangular",
+        }
+    }
+}
+
+impl Display for DistanceModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Quick check whether two points could be within `max_km` of each other by any of the distance models.
+///
+/// This uses the equirectangular approximation with a margin for its error, or, near the poles, a bound from
+/// the latitude difference alone, so it never rejects points that are actually within `max_km`. Points it
+/// accepts must still be checked with an accurate model.
+pub fn may_be_within(lon1: f64, lat1: f64, lon2: f64, lat2: f64, max_km: f64) -> bool {
+    let dlat_km = (lat2 - lat1).abs().to_radians() * WGS84_MIN_RADIUS_KM;
+    if dlat_km > max_km * PREFILTER_MARGIN {
+        return false;
+    }
+
+    if lat1.abs() > PREFILTER_MAX_LATITUDE || lat2.abs() > PREFILTER_MAX_LATITUDE {
+        return true;
+    }
+    equirectangular_km(lon1, lat1, lon2, lat2) <= max_km * PREFILTER_MARGIN + PREFILTER_MARGIN_KM
+}
+
+/// Great circle distance in kilometers between two points given in degrees, on a sphere with the Earth's mean radius.
+pub fn haversine_km(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let phi1 = lat1.to_radians();
+    let phi2 = lat2.to_radians();
+    let dphi = phi2 - phi1;
+    let dlambda = (lon2 - lon1).to_radians();
+
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_MEAN_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Approximate distance in kilometers between two points given in degrees, treating the Earth as flat around
+/// their mean latitude.
+pub fn equirectangular_km(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (x, y) = equirectangular_offset_km(lon1, lat1, lon2, lat2, 0.5 * (lat1 + lat2));
+    x.hypot(y)
+}
+
+/// East and north offsets in kilometers of the second point from the first (all in degrees), in an equirectangular
+/// projection on a sphere with the Earth's mean radius whose longitude scale is taken at `ref_lat`.
+/// See [`equirectangular_inverse`] for the reverse.
+pub fn equirectangular_offset_km(lon1: f64, lat1: f64, lon2: f64, lat2: f64, ref_lat: f64) -> (f64, f64) {
+    let dlon = wrap_longitude_difference(lon2 - lon1).to_radians();
+    let x = EARTH_MEAN_RADIUS_KM * dlon * ref_lat.to_radians().cos();
+    let y = EARTH_MEAN_RADIUS_KM * (lat2 - lat1).to_radians();
+    (x, y)
+}
+
+/// The longitude and latitude in degrees of the point `x_km` east and `y_km` north of (`lon1`, `lat1`), in the same
+/// projection as [`equirectangular_offset_km`]. The longitude is wrapped into [-180, 180).
+pub fn equirectangular_inverse(lon1: f64, lat1: f64, x_km: f64, y_km: f64, ref_lat: f64) -> (f64, f64) {
+    let lon = lon1 + (x_km / (EARTH_MEAN_RADIUS_KM * ref_lat.to_radians().cos())).to_degrees();
+    let lat = lat1 + (y_km / EARTH_MEAN_RADIUS_KM).to_degrees();
+    (wrap_longitude_difference(lon), lat)
+}
+
+/// Geodesic distance in kilometers between two points given in degrees on the WGS84 ellipsoid, using Vincenty's
+/// inverse formula. Vincenty's iteration does not converge for nearly antipodal points; for those, the haversine
+/// distance is returned instead.
+pub fn vincenty_km(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let a = WGS84_A_KM;
+    let f = WGS84_F;
+    let b = a * (1.0 - f);
+
+    let l = wrap_longitude_difference(lon2 - lon1).to_radians();
+    let u1 = ((1.0 - f) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - f) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_MAX_ITER {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2) + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2)).sqrt();
+        if sin_sigma == 0.0 {
+            // Coincident points
+            return 0.0;
+        }
+
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+        // On the equator, cos2_alpha is 0 and the midpoint term is not used
+        let cos_2sigma_m = if cos2_alpha != 0.0 { cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha } else { 0.0 };
+        let c = f / 16.0 * cos2_alpha * (4.0 + f * (4.0 - 3.0 * cos2_alpha));
+
+        let lambda_prev = lambda;
+        lambda = l + (1.0 - c) * f * sin_alpha
+            * (sigma + c * sin_sigma * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+
+        if (lambda - lambda_prev).abs() < VINCENTY_TOLERANCE {
+            let u_sq = cos2_alpha * (a * a - b * b) / (b * b);
+            let big_a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b * sin_sigma * (cos_2sigma_m + big_b / 4.0 * (
+                cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)
+                - big_b / 6.0 * cos_2sigma_m * (-3.0 + 4.0 * sin_sigma * sin_sigma) * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)
+            ));
+            return b * big_a * (sigma - delta_sigma);
+        }
+    }
+
+    log::debug!("Vincenty's formula did not converge for ({lon1}, {lat1}) to ({lon2}, {lat2}), using the haversine distance");
+    haversine_km(lon1, lat1, lon2, lat2)
+}
+
+/// Wrap a longitude difference in degrees into [-180, 180)
+fn wrap_longitude_difference(dlon: f64) -> f64 {
+    (dlon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dms(deg: f64, min: f64, sec: f64) -> f64 {
+        deg.signum() * (deg.abs() + min / 60.0 + sec / 3600.0)
+    }
+
+    #[test]
+    fn test_haversine_one_degree() {
+        // One degree of arc on a sphere of the mean radius, along the equator and along a meridian
+        let expected = EARTH_MEAN_RADIUS_KM * std::f64::consts::PI / 180.0;
+        assert!((haversine_km(0.0, 0.0, 1.0, 0.0) - expected).abs() < 1e-9);
+        assert!((haversine_km(10.0, 45.0, 10.0, 46.0) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_haversine_reference() {
+        // Paris to New York, 5837.24 km on a sphere of the mean radius
+        let d = haversine_km(2.3522, 48.8566, -74.0060, 40.7128);
+        assert!((d - 5837.24).abs() < 0.5, "got {d}");
+    }
+
+    #[test]
+    fn test_vincenty_flinders_peak_to_buninyong() {
+        // The example from Vincenty (1975) as given by Geoscience Australia: 54972.271 m
+        let lat1 = dms(-37.0, 57.0, 3.72030);
+        let lon1 = dms(144.0, 25.0, 29.52440);
+        let lat2 = dms(-37.0, 39.0, 10.15610);
+        let lon2 = dms(143.0, 55.0, 35.38390);
+        let d = vincenty_km(lon1, lat1, lon2, lat2);
+        assert!((d - 54.972271).abs() < 1e-6, "got {d}");
+    }
+
+    #[test]
+    fn test_vincenty_equator_and_meridian() {
+        // Along the equator, the geodesic is an arc of the semi-major axis
+        let d = vincenty_km(0.0, 0.0, 1.0, 0.0);
+        assert!((d - WGS84_A_KM * std::f64::consts::PI / 180.0).abs() < 1e-6, "got {d}");
+
+        // One degree of meridian arc from the equator is 110.574 km on WGS84, and from 89 to 90 degrees is 111.694 km
+        let d = vincenty_km(0.0, 0.0, 0.0, 1.0);
+        assert!((d - 110.574).abs() < 1e-3, "got {d}");
+        let d = vincenty_km(0.0, 89.0, 0.0, 90.0);
+        assert!((d - 111.694).abs() < 1e-3, "got {d}");
+    }
+
+    #[test]
+    fn test_vincenty_special_cases() {
+        assert_eq!(vincenty_km(10.0, 20.0, 10.0, 20.0), 0.0);
+        // Crossing the dateline is the same as not crossing it
+        let across = vincenty_km(179.5, 10.0, -179.5, 10.0);
+        let not_across = vincenty_km(-0.5, 10.0, 0.5, 10.0);
+        assert!((across - not_across).abs() < 1e-9);
+        // Nearly antipodal points fall back to the haversine distance rather than failing
+        let d = vincenty_km(0.0, 0.0, 179.9, 0.1);
+        assert!(d.is_finite() && d > 19_900.0, "got {d}");
+    }
+
+    #[test]
+    fn test_high_latitude_error() {
+        // The spherical models differ from the ellipsoid by less than 0.5% at any latitude, for the distances we match at
+        for lat in [0.0, 30.0, 60.0, 80.0, 89.0] {
+            let d_vincenty = vincenty_km(0.0, lat, 0.5, lat + 0.5);
+            let d_haversine = haversine_km(0.0, lat, 0.5, lat + 0.5);
+            assert!(((d_haversine - d_vincenty) / d_vincenty).abs() < 0.005, "lat {lat}: {d_haversine} vs {d_vincenty}");
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_short_distances() {
+        for (lon1, lat1, lon2, lat2) in [(0.0, 0.0, 0.5, 0.5), (100.0, 45.0, 100.8, 45.3), (179.8, -60.0, -179.7, -60.2)] {
+            let d_eq = equirectangular_km(lon1, lat1, lon2, lat2);
+            let d_hav = haversine_km(lon1, lat1, lon2, lat2);
+            assert!(((d_eq - d_hav) / d_hav).abs() < 0.001, "({lon1}, {lat1}) to ({lon2}, {lat2}): {d_eq} vs {d_hav}");
+        }
+    }
+
+    #[test]
+    fn test_equirectangular_inverse_round_trip() {
+        for (lon1, lat1, lon2, lat2) in [(0.0, 0.0, 0.5, 0.5), (100.0, 45.0, 100.8, 45.3), (179.8, -60.0, -179.7, -60.2)] {
+            let (x, y) = equirectangular_offset_km(lon1, lat1, lon2, lat2, lat1);
+            let (lon, lat) = equirectangular_inverse(lon1, lat1, x, y, lat1);
+            assert!((lon - lon2).abs() < 1e-9 && (lat - lat2).abs() < 1e-9, "({lon2}, {lat2}) came back as ({lon}, {lat})");
+        }
+    }
+
+    #[test]
+    fn test_prefilter_never_rejects_matches() {
+        let max_km = 100.0;
+        for lat in (-89..=89).step_by(4) {
+            for lon in (-180..180).step_by(30) {
+                for (dlon, dlat) in [(0.0, 0.8), (0.9, 0.0), (0.6, 0.6), (-1.5, 0.1), (5.0, 0.0), (30.0, 0.2), (180.0, 0.0)] {
+                    let (lon1, lat1) = (lon as f64, lat as f64);
+                    let (lon2, lat2) = (lon1 + dlon, (lat1 + dlat).min(90.0));
+                    let within = [DistanceModel::Haversine, DistanceModel::Vincenty]
+                        .iter()
+                        .any(|m| m.distance_km(lon1, lat1, lon2, lat2) <= max_km);
+                    if within {
+                        assert!(may_be_within(lon1, lat1, lon2, lat2, max_km), "rejected ({lon1}, {lat1}) to ({lon2}, {lat2})");
+                    }
+                }
+            }
+        }
+
+        assert!(!may_be_within(0.0, 0.0, 3.0, 0.0, max_km));
+        assert!(!may_be_within(0.0, 10.0, 0.0, 12.0, max_km));
+    }
+}
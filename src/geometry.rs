@@ -0,0 +1,224 @@
+//! Geometry of groups of matched soundings: where the two tracks cross, at what angle, and
+//! the area the group covers.
+//!
+//! Calculations are done in a local equirectangular projection centered on each group, which
+//! is accurate enough at the scale of a group (at most a few hundred kilometers).
+use itertools::Itertools;
+
+use crate::geodesy::{self, DistanceModel};
+use crate::utils::DEG2RAD;
+
+/// The position and time of one sounding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundingPoint {
+    pub longitude: f32,
+    pub latitude: f32,
+    pub timestamp: f64,
+}
+
+/// Geometry of one group of matched soundings. Values that cannot be computed (e.g. the heading of
+/// a track with only one sounding in the group) are NaN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupGeometry {
+    /// Longitude where the two tracks cross, in degrees east
+    pub crossing_longitude: f32,
+    /// Latitude where the two tracks cross, in degrees north
+    pub crossing_latitude: f32,
+    /// Heading of the first instrument's track, in degrees clockwise from north
+    pub first_heading: f32,
+    /// Heading of the second instrument's track, in degrees clockwise from north
+    pub second_heading: f32,
+    /// Angle between the two tracks' headings, in degrees between 0 and 180
+    pub crossing_angle: f32,
+    /// Westernmost longitude of the group's soundings. If the group crosses the dateline, this will be greater than `max_longitude`.
+    pub min_longitude: f32,
+    /// Easternmost longitude of the group's soundings
+    pub max_longitude: f32,
+    pub min_latitude: f32,
+    pub max_latitude: f32,
+    /// Vertices (longitude, latitude) of the convex hull of the group's soundings, counterclockwise
+    pub hull: Vec<(f32, f32)>,
+    /// Distance in kilometers between the first instrument's earliest and latest soundings in the group
+    pub first_track_length: f32,
+    /// Distance in kilometers between the second instrument's earliest and latest soundings in the group
+    pub second_track_length: f32,
+}
+
+impl GroupGeometry {
+    /// Compute the geometry of a group from the positions of its soundings from each instrument,
+    /// measuring track lengths with `model`. Neither slice may be empty.
+    pub fn from_soundings(first: &[SoundingPoint], second: &[SoundingPoint], model: DistanceModel) -> Self {
+        let proj = LocalProjection::new(first.iter().chain(second.iter()));
+
+        let first_track = Track::new(first);
+        let second_track = Track::new(second);
+        let first_heading = first_track.heading();
+        let second_heading = second_track.heading();
+        let crossing_angle = {
+            let diff = (first_heading - second_heading).abs() % 360.0;
+            if diff > 180.0 { 360.0 - diff } else { diff }
+        };
+
+        let (crossing_longitude, crossing_latitude) = first_track.crossing_point(&second_track, &proj)
+            .unwrap_or((f32::NAN, f32::NAN));
+
+        let all_xy = first.iter().chain(second.iter()).map(|p| proj.forward(p.longitude, p.latitude)).collect_vec();
+        let (min_x, max_x) = all_xy.iter().map(|&(x, _)| x).minmax_by(|a, b| a.total_cmp(b)).into_option().unwrap_or((f32::NAN, f32::NAN));
+        let (min_lat, max_lat) = first.iter().chain(second.iter()).map(|p| p.latitude)
+            .minmax_by(|a, b| a.total_cmp(b)).into_option().unwrap_or((f32::NAN, f32::NAN));
+        let (min_lon, _) = proj.inverse(min_x, 0.0);
+        let (max_lon, _) = proj.inverse(max_x, 0.0);
+
+        let hull = convex_hull(&all_xy).into_iter()
+            .map(|(x, y)| proj.inverse(x, y))
+            .collect_vec();
+
+        Self {
+            crossing_longitude,
+            crossing_latitude,
+            first_heading,
+            second_heading,
+            crossing_angle,
+            min_longitude: min_lon,
+            max_longitude: max_lon,
+            min_latitude: min_lat,
+            max_latitude: max_lat,
+            hull,
+            first_track_length: first_track.length(model),
+            second_track_length: second_track.length(model),
+        }
+    }
+}
+
+/// The earliest and latest soundings of one instrument in a group, which define its track direction.
+struct Track {
+    start: SoundingPoint,
+    end: SoundingPoint,
+}
+
+impl Track {
+    fn new(points: &[SoundingPoint]) -> Self {
+        let (start, end) = points.iter()
+            .minmax_by(|a, b| a.timestamp.total_cmp(&b.timestamp))
+            .into_option()
+            .expect("Each instrument must have at least one sounding in a group");
+        Self { start: *start, end: *end }
+    }
+
+    fn has_direction(&self) -> bool {
+        self.start.longitude != self.end.longitude || self.start.latitude != self.end.latitude
+    }
+
+    /// Initial great circle bearing from the start to the end of the track, in degrees clockwise from north
+    fn heading(&self) -> f32 {
+        if !self.has_direction() {
+            return f32::NAN;
+        }
+
+        initial_bearing(self.start.longitude, self.start.latitude, self.end.longitude, self.end.latitude)
+    }
+
+    fn length(&self, model: DistanceModel) -> f32 {
+        model.distance_km(
+            self.start.longitude as f64, self.start.latitude as f64,
+            self.end.longitude as f64, self.end.latitude as f64
+        ) as f32
+    }
+
+    /// Where the lines through this track and `other` intersect, if they are not parallel.
+    /// The lines are extended past the ends of the tracks, since the soundings in a group need not
+    /// reach the actual crossing point.
+    fn crossing_point(&self, other: &Track, proj: &LocalProjection) -> Option<(f32, f32)> {
+        if !self.has_direction() || !other.has_direction() {
+            return None;
+        }
+
+        let (ax0, ay0) = proj.forward(self.start.longitude, self.start.latitude);
+        let (ax1, ay1) = proj.forward(self.end.longitude, self.end.latitude);
+        let (bx0, by0) = proj.forward(other.start.longitude, other.start.latitude);
+        let (bx1, by1) = proj.forward(other.end.longitude, other.end.latitude);
+
+        let (dax, day) = (ax1 - ax0, ay1 - ay0);
+        let (dbx, dby) = (bx1 - bx0, by1 - by0);
+        let denom = dax * dby - day * dbx;
+        // Nearly parallel tracks have no well defined crossing point
+        if denom.abs() < 1e-6 * (dax.hypot(day) * dbx.hypot(dby)) {
+            return None;
+        }
+
+        let s = ((bx0 - ax0) * dby - (by0 - ay0) * dbx) / denom;
+        Some(proj.inverse(ax0 + s * dax, ay0 + s * day))
+    }
+}
+
+/// Initial great circle bearing from the first point to the second, in degrees clockwise from north.
+pub fn initial_bearing(lon1: f32, lat1: f32, lon2: f32, lat2: f32) -> f32 {
+    let lat1 = lat1 * DEG2RAD;
+    let lat2 = lat2 * DEG2RAD;
+    let dlon = (lon2 - lon1) * DEG2RAD;
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// An equirectangular projection to kilometers around a reference point (see [`geodesy::equirectangular_offset_km`]).
+struct LocalProjection {
+    lon0: f64,
+    lat0: f64,
+}
+
+impl LocalProjection {
+    fn new<'a, I: Iterator<Item = &'a SoundingPoint>>(points: I) -> Self {
+        let points = points.collect_vec();
+        let lon0 = points.first().map(|p| p.longitude).unwrap_or(0.0);
+        let n = points.len().max(1) as f32;
+        let lat0 = points.iter().map(|p| p.latitude).sum::<f32>() / n;
+        Self { lon0: lon0 as f64, lat0: lat0 as f64 }
+    }
+
+    fn forward(&self, lon: f32, lat: f32) -> (f32, f32) {
+        // Longitude is measured relative to the reference so that groups crossing the dateline are not split
+        let (x, y) = geodesy::equirectangular_offset_km(self.lon0, self.lat0, lon as f64, lat as f64, self.lat0);
+        (x as f32, y as f32)
+    }
+
+    fn inverse(&self, x: f32, y: f32) -> (f32, f32) {
+        let (lon, lat) = geodesy::equirectangular_inverse(self.lon0, self.lat0, x as f64, y as f64, self.lat0);
+        (lon as f32, lat as f32)
+    }
+}
+
+/// Convex hull of a set of points (Andrew's monotone chain), counterclockwise, without repeating the first point.
+fn convex_hull(points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    let pts = points.iter()
+        .copied()
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .sorted_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .dedup()
+        .collect_vec();
+
+    if pts.len() < 3 {
+        return pts;
+    }
+
+    fn cross(o: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    }
+
+    let half_hull = |it: &mut dyn Iterator<Item = &(f32, f32)>| {
+        let mut half: Vec<(f32, f32)> = Vec::new();
+        for &p in it {
+            while half.len() >= 2 && cross(half[half.len() - 2], half[half.len() - 1], p) <= 0.0 {
+                half.pop();
+            }
+            half.push(p);
+        }
+        // The last point of each half is the first point of the other
+        half.pop();
+        half
+    };
+
+    let mut hull = half_hull(&mut pts.iter());
+    hull.extend(half_hull(&mut pts.iter().rev()));
+    hull
+}
@@ -0,0 +1,382 @@
+//! How matched soundings are collected into groups.
+//!
+//! By default, groups are the connected sets of matches: any two matches that share a second instrument
+//! sounding are in the same group. This can chain together separate overpasses, so groups can instead be
+//! made per pair of passes (see [`GroupingMode`]), where a pass is a run of one instrument's soundings from
+//! the same orbit with no time gap longer than a threshold, or per pair of orbits.
+//!
+//! Orbit numbers are read from the lite files where available. Otherwise they are estimated from the sounding
+//! times (see [`estimate_orbits`]); estimated orbit numbers continue on from any orbit numbers in the same file,
+//! and are otherwise consistent within a run, but are not the official orbit numbers.
+//!
+//! Groups can also be rejected for having too few soundings, too few good quality soundings, or too much spread in
+//! XCO2 (see [`GroupingRules::rejection_reasons`]), and then either dropped or flagged (see [`RejectedGroups`]).
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use ndarray::{Array1, ArrayView1};
+use serde::{Deserialize, Serialize};
+
+use crate::config::GroupingOptions;
+use crate::error::MatchupError;
+
+/// Group containing the orbit number variable in the lite files
+pub const ORBIT_GROUP: &str = "Sounding";
+/// Name of the orbit number variable in the lite files
+pub const ORBIT_VARIABLE: &str = "orbit";
+/// Name of the XCO2 variable in the lite files, used for the group XCO2 standard deviation threshold
+pub const XCO2_VARIABLE: &str = "xco2";
+/// Orbit number used for soundings whose orbit is not known. Real orbit numbers start at 1.
+pub const UNKNOWN_ORBIT: u32 = 0;
+/// Default longest time gap within one pass, in seconds
+pub const DEFAULT_PASS_GAP_S: f64 = 60.0;
+/// Estimated orbit numbers start from here, so that they cannot be mistaken for orbit numbers read from lite files
+pub const ESTIMATED_ORBIT_BASE: u32 = 1_000_000_000;
+/// Nominal orbital period of OCO-2, in seconds
+pub const OCO2_ORBIT_PERIOD_S: f64 = 98.8 * 60.0;
+/// Nominal orbital period of the ISS, which carries OCO-3, in seconds. The actual period varies by a few tenths of a minute.
+pub const OCO3_ORBIT_PERIOD_S: f64 = 92.9 * 60.0;
+/// The instrument a lite file is from. This is known from where the file is given in a matchup (the OCO-2 file or
+/// the OCO-3 files, which are both OCO-3 for self crossings), not from the file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instrument {
+    Oco2,
+    Oco3,
+}
+
+impl Instrument {
+    /// The instrument of a matchup's first (`oco2_lite_file`) file; the second files are always OCO-3.
+    pub fn first(oco3_self_cross: bool) -> Self {
+        if oco3_self_cross { Self::Oco3 } else { Self::Oco2 }
+    }
+
+    /// The instrument's nominal orbital period in seconds
+    pub fn orbit_period_s(self) -> f64 {
+        match self {
+            Self::Oco2 => OCO2_ORBIT_PERIOD_S,
+            Self::Oco3 => OCO3_ORBIT_PERIOD_S,
+        }
+    }
+}
+
+/// When estimating orbits, soundings more than this many seconds apart are on different orbits. Both instruments
+/// only observe the day side, so consecutive orbits' soundings are separated by a gap of about half an orbit.
+const ESTIMATED_ORBIT_GAP_S: f64 = 20.0 * 60.0;
+
+/// Rejection reason bit: one of the instruments has fewer soundings in the group than the minimum
+pub const REJECT_TOO_FEW_SOUNDINGS: u8 = 1;
+/// Rejection reason bit: one of the instruments has too small a fraction of quality flag 0 soundings in the group
+pub const REJECT_LOW_FLAG0_FRACTION: u8 = 2;
+/// Rejection reason bit: the standard deviation of one of the instruments' XCO2 in the group is too large
+pub const REJECT_HIGH_XCO2_STD: u8 = 4;
+
+/// What makes a group of matched soundings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupingMode {
+    /// Matches sharing any second instrument sounding are grouped together
+    #[default]
+    Connected,
+    /// All matches between the same pass of each instrument are one group
+    Pass,
+    /// All matches between the same orbit of each instrument are one group
+    Orbit,
+}
+
+impl GroupingMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Connected => "connected",
+            Self::Pass => "pass",
+            Self::Orbit => "orbit",
+        }
+    }
+}
+
+impl Display for GroupingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What to do with groups that do not meet the group quality thresholds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RejectedGroups {
+    /// Leave rejected groups out of the output
+    #[default]
+    Drop,
+    /// Keep rejected groups, with the reasons they were rejected
+    Flag,
+}
+
+impl RejectedGroups {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Drop => "drop",
+            Self::Flag => "flag",
+        }
+    }
+}
+
+impl Display for RejectedGroups {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How to group matched soundings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupingRules {
+    pub mode: GroupingMode,
+    /// A time gap longer than this many seconds between consecutive soundings of one instrument starts a new pass
+    pub pass_gap_s: f64,
+    /// Groups with fewer soundings than this from either instrument are rejected
+    pub min_soundings: usize,
+    /// Groups where less than this fraction of either instrument's soundings have quality flag 0 are rejected
+    pub min_flag0_fraction: Option<f32>,
+    /// Groups where the standard deviation of either instrument's XCO2 is more than this many ppm are rejected
+    pub max_xco2_std_ppm: Option<f32>,
+    pub rejected: RejectedGroups,
+}
+
+impl Default for GroupingRules {
+    fn default() -> Self {
+        Self {
+            mode: GroupingMode::default(),
+            pass_gap_s: DEFAULT_PASS_GAP_S,
+            min_soundings: 1,
+            min_flag0_fraction: None,
+            max_xco2_std_ppm: None,
+            rejected: RejectedGroups::default(),
+        }
+    }
+}
+
+impl GroupingRules {
+    /// Make the grouping rules from the command line or configuration options, returning an error if the pass gap
+    /// or maximum XCO2 standard deviation is not positive or the minimum fraction of flag 0 soundings is not between 0 and 1,
+    /// or is given when only flag 0 soundings are matched (`flag0_only`), where it could never reject a group.
+    pub fn from_options(opts: &GroupingOptions, flag0_only: bool) -> Result<Self, MatchupError> {
+        if !opts.pass_gap_s.is_finite() || opts.pass_gap_s <= 0.0 {
+            return Err(MatchupError::ArgumentError(format!("--pass-gap must be positive, got {}", opts.pass_gap_s)));
+        }
+        if let Some(frac) = opts.min_flag0_fraction {
+            if !(0.0..=1.0).contains(&frac) {
+                return Err(MatchupError::ArgumentError(format!("--min-flag0-fraction must be between 0 and 1, got {frac}")));
+            }
+            if flag0_only {
+                return Err(MatchupError::ArgumentError(
+                    "--min-flag0-fraction cannot be used with --flag0-only, since all the soundings then have flag 0".to_owned()
+                ));
+            }
+        }
+        if let Some(std) = opts.max_xco2_std_ppm {
+            if !std.is_finite() || std <= 0.0 {
+                return Err(MatchupError::ArgumentError(format!("--max-xco2-std must be positive, got {std}")));
+            }
+        }
+
+        Ok(Self {
+            mode: opts.group_by,
+            pass_gap_s: opts.pass_gap_s,
+            min_soundings: opts.min_group_soundings,
+            min_flag0_fraction: opts.min_flag0_fraction,
+            max_xco2_std_ppm: opts.max_xco2_std_ppm,
+            rejected: opts.rejected_groups,
+        })
+    }
+
+    /// Whether groups are made per pair of passes rather than from connected matches.
+    pub fn by_pass(&self) -> bool {
+        self.mode == GroupingMode::Pass
+    }
+
+    /// Whether any group quality thresholds are set, so that some groups may be rejected.
+    pub fn has_thresholds(&self) -> bool {
+        self.min_soundings > 1 || self.min_flag0_fraction.is_some() || self.max_xco2_std_ppm.is_some()
+    }
+
+    /// The reasons a group is rejected, as a combination of the `REJECT_*` bits (0 if it is not rejected).
+    /// `first` and `second` are the quality flag and XCO2 of each instrument's soundings in the group. The XCO2
+    /// standard deviation is computed from the finite XCO2 values only, so a group with unknown XCO2 is not rejected for it.
+    pub fn rejection_reasons(&self, first: &[(u8, f32)], second: &[(u8, f32)]) -> u8 {
+        let mut reasons = 0;
+        for soundings in [first, second] {
+            if soundings.len() < self.min_soundings {
+                reasons |= REJECT_TOO_FEW_SOUNDINGS;
+            }
+
+            if let Some(min_frac) = self.min_flag0_fraction {
+                let n_flag0 = soundings.iter().filter(|(q, _)| *q == 0).count();
+                if (n_flag0 as f32) < min_frac * soundings.len() as f32 {
+                    reasons |= REJECT_LOW_FLAG0_FRACTION;
+                }
+            }
+
+            if let Some(max_std) = self.max_xco2_std_ppm {
+                if xco2_std(soundings.iter().map(|(_, x)| *x)).is_some_and(|std| std > max_std) {
+                    reasons |= REJECT_HIGH_XCO2_STD;
+                }
+            }
+        }
+        reasons
+    }
+
+    /// A short description of the group quality thresholds for output file metadata, e.g.
+    /// "min_soundings = 5, min_flag0_fraction = 0.8 (flag)", or `None` if there are none.
+    pub fn threshold_description(&self) -> Option<String> {
+        if !self.has_thresholds() {
+            return None;
+        }
+
+        let mut parts = vec![format!("min_soundings = {}", self.min_soundings)];
+        if let Some(frac) = self.min_flag0_fraction {
+            parts.push(format!("min_flag0_fraction = {frac}"));
+        }
+        if let Some(std) = self.max_xco2_std_ppm {
+            parts.push(format!("max_xco2_std_ppm = {std}"));
+        }
+        Some(format!("{} ({})", parts.join(", "), self.rejected))
+    }
+
+    /// The `flag_masks` and `flag_meanings` attributes for the rejection reason variable
+    pub fn rejection_flags() -> (Vec<u8>, &'static str) {
+        (
+            vec![REJECT_TOO_FEW_SOUNDINGS, REJECT_LOW_FLAG0_FRACTION, REJECT_HIGH_XCO2_STD],
+            "too_few_soundings low_flag0_fraction high_xco2_std"
+        )
+    }
+}
+
+/// The population standard deviation of the finite values in `xco2`, or `None` if there are none.
+fn xco2_std<I: Iterator<Item = f32>>(xco2: I) -> Option<f32> {
+    let values = xco2.filter(|x| x.is_finite()).collect_vec();
+    if values.is_empty() {
+        return None;
+    }
+
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / n;
+    let var = values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+    Some(var.sqrt())
+}
+
+/// Fill in the orbit number of each sounding whose `orbit` is [`UNKNOWN_ORBIT`] from its time (in seconds since 1970).
+///
+/// The soundings are split into segments wherever consecutive soundings are more than 20 minutes apart, and the
+/// segments numbered in time order: each is `round(t / period_s)` orbits after the one before, where t is the time
+/// between their middles, and at least one. This follows the actual orbits even where the orbital period drifts away
+/// from the nominal `period_s`, as long as it does not drift by half an orbit between consecutive segments.
+/// The numbering is anchored to the first segment with a known orbit number, if any; otherwise the first segment is
+/// numbered by how many `period_s` orbital periods passed between 1970 and its middle, plus [`ESTIMATED_ORBIT_BASE`].
+/// Soundings with known orbit numbers keep them.
+pub fn estimate_orbits(timestamp: ArrayView1<f64>, orbit: ArrayView1<u32>, period_s: f64) -> Array1<u32> {
+    let order = (0..timestamp.len())
+        .sorted_by(|&a, &b| timestamp[a].total_cmp(&timestamp[b]))
+        .collect_vec();
+
+    // Split into segments and number them relative to the first
+    let mut segments: Vec<&[usize]> = Vec::new();
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && timestamp[order[end]] - timestamp[order[end - 1]] <= ESTIMATED_ORBIT_GAP_S {
+            end += 1;
+        }
+        segments.push(&order[start..end]);
+        start = end;
+    }
+
+    let mid_time = |seg: &[usize]| 0.5 * (timestamp[seg[0]] + timestamp[seg[seg.len() - 1]]);
+    let mut relative = Vec::with_capacity(segments.len());
+    for (k, seg) in segments.iter().enumerate() {
+        let r = if k == 0 {
+            0
+        } else {
+            let n_orbits = ((mid_time(seg) - mid_time(segments[k - 1])) / period_s).round().max(1.0) as i64;
+            relative[k - 1] + n_orbits
+        };
+        relative.push(r);
+    }
+
+    let known = segments.iter().zip(relative.iter()).find_map(|(seg, &r)| {
+        seg.iter().map(|&i| orbit[i]).filter(|&o| o != UNKNOWN_ORBIT).min().map(|o| o as i64 - r)
+    });
+    let first_orbit = known.unwrap_or_else(|| {
+        segments.first().map(|seg| ESTIMATED_ORBIT_BASE as i64 + (mid_time(seg) / period_s).floor().max(0.0) as i64).unwrap_or(0)
+    });
+
+    let mut orbits = orbit.to_owned();
+    for (seg, r) in segments.iter().zip(relative) {
+        let estimate = (first_orbit + r).clamp(1, u32::MAX as i64) as u32;
+        for &i in seg.iter() {
+            if orbits[i] == UNKNOWN_ORBIT {
+                orbits[i] = estimate;
+            }
+        }
+    }
+    orbits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Times of `n_orbits` day-side segments of 40 soundings over 45 minutes, starting at `t0`, one every `period_s`,
+    /// skipping the orbits in `skip`
+    fn segment_times(t0: f64, period_s: f64, n_orbits: usize, skip: &[usize]) -> Array1<f64> {
+        (0..n_orbits)
+            .filter(|k| !skip.contains(k))
+            .flat_map(|k| (0..40).map(move |j| t0 + k as f64 * period_s + j as f64 * 45.0 * 60.0 / 39.0))
+            .collect()
+    }
+
+    /// The orbit number of each segment in `orbits`, in order
+    fn segment_orbits(orbits: &Array1<u32>) -> Vec<u32> {
+        orbits.iter().copied().dedup().collect()
+    }
+
+    #[test]
+    fn test_estimate_orbits_consecutive() {
+        let times = segment_times(1.6e9, OCO3_ORBIT_PERIOD_S, 15, &[]);
+        let orbits = estimate_orbits(times.view(), Array1::zeros(times.len()).view(), OCO3_ORBIT_PERIOD_S);
+        let numbers = segment_orbits(&orbits);
+        assert_eq!(numbers.len(), 15);
+        assert!(numbers[0] >= ESTIMATED_ORBIT_BASE);
+        assert!(numbers.iter().tuple_windows().all(|(a, b)| b - a == 1), "{numbers:?}");
+    }
+
+    #[test]
+    fn test_estimate_orbits_period_drift() {
+        // The actual period is 0.4 minutes longer than the nominal one, so over a day the segments drift by several
+        // minutes from where the nominal period puts them
+        let times = segment_times(1.6e9, OCO3_ORBIT_PERIOD_S + 24.0, 16, &[]);
+        let orbits = estimate_orbits(times.view(), Array1::zeros(times.len()).view(), OCO3_ORBIT_PERIOD_S);
+        let numbers = segment_orbits(&orbits);
+        assert_eq!(numbers.len(), 16);
+        assert!(numbers.iter().tuple_windows().all(|(a, b)| b - a == 1), "{numbers:?}");
+    }
+
+    #[test]
+    fn test_estimate_orbits_missing_orbit() {
+        let times = segment_times(1.6e9, OCO2_ORBIT_PERIOD_S, 6, &[2, 3]);
+        let orbits = estimate_orbits(times.view(), Array1::zeros(times.len()).view(), OCO2_ORBIT_PERIOD_S);
+        let numbers = segment_orbits(&orbits);
+        let steps = numbers.iter().tuple_windows().map(|(a, b)| b - a).collect_vec();
+        assert_eq!(steps, vec![1, 3, 1]);
+    }
+
+    #[test]
+    fn test_estimate_orbits_anchored_to_known() {
+        let times = segment_times(1.6e9, OCO2_ORBIT_PERIOD_S, 4, &[]);
+        let mut known = Array1::zeros(times.len());
+        // Only some soundings of the second segment have orbit numbers
+        for i in 40..50 {
+            known[i] = 30001;
+        }
+        let orbits = estimate_orbits(times.view(), known.view(), OCO2_ORBIT_PERIOD_S);
+        assert_eq!(segment_orbits(&orbits), vec![30000, 30001, 30002, 30003]);
+    }
+}
@@ -1,4 +1,22 @@
 pub mod error;
 pub mod utils;
 pub mod config;
-pub mod oco;
\ No newline at end of file
+pub mod oco;
+pub mod report;
+pub mod validation;
+pub mod cache;
+pub mod spill;
+pub mod checksum;
+pub mod remap;
+pub mod verify;
+pub mod provenance;
+pub mod geometry;
+pub mod timing;
+pub mod modes;
+pub mod surface;
+pub mod region;
+pub mod distance;
+pub mod geodesy;
+pub mod nearest;
+pub mod score;
+pub mod grouping;
\ No newline at end of file
@@ -1,21 +1,38 @@
-use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
 use clap::{Parser, Subcommand};
+use itertools::Itertools;
+use oco_lite_matchup::cache::{CacheReservation, LiteFileCache};
+use oco_lite_matchup::checksum::ChecksumCache;
 use oco_lite_matchup::error::{self, MatchupError};
-use oco_lite_matchup::config::{RunOneArgs, RunMultiArgs, RunMultiConfig};
-use oco_lite_matchup::oco::{self, OcoGeo};
-use oco_lite_matchup::utils::ShowProgress;
+use oco_lite_matchup::config::{self, RunOneArgs, RunMultiArgs, RunMultiConfig, RunRangeArgs, MultiRunOptions, ValidateArgs, VerifyArgs, ChecksumOptions, RemapOptions};
+use oco_lite_matchup::oco::{self, MatchCriteria, OcoGeo, PassKeys};
+use oco_lite_matchup::report::{MatchupReport, MatchupStats, MatchupStatus, RunReport};
+use oco_lite_matchup::utils::{self, ShowProgress};
+use oco_lite_matchup::modes::ModeRules;
+use oco_lite_matchup::grouping::{GroupingMode, GroupingRules, Instrument};
+use oco_lite_matchup::nearest::NearestRule;
+use oco_lite_matchup::score::MatchScore;
+use oco_lite_matchup::provenance::Provenance;
+use oco_lite_matchup::region::RegionSet;
+use oco_lite_matchup::surface::SurfaceRules;
+use oco_lite_matchup::remap::PathRemapper;
+use oco_lite_matchup::validation;
+use oco_lite_matchup::verify::{self, LiteFileStatus};
 use rayon::prelude::*;
-use serde::Serialize;
 
 
 const MIN_SELF_CROSS_DELTA_TIME_SECONDS: f64 = 2_787.0; // about half an orbit
 const MAX_DELTA_TIME_SECONDS: f64 = 43_200.0; // 12 hours
+/// Rough allowance for the memory needed by the matches of one OCO-2 sounding when matching in blocks.
+/// Most soundings have no matches, but ones at crossings can have hundreds.
+const MATCH_BYTES_PER_SOUNDING: usize = 8_000;
+const MIN_MATCH_CHUNK_SIZE: usize = 1_000;
 
 // TODO: Modify to accept multiple OCO-2 lite files (for different modes? not sure if needed)
-// TODO: Make distance and time input parameters
+// TODO: Make time input parameters
 // TODO: make the two progress bars (initial matchup and grouping) use multibar via
 //  progess_with (https://docs.rs/indicatif/latest/indicatif/trait.ParallelProgressIterator.html#tymethod.progress_with)
 fn main() -> Result<(), error::MatchupError> {
@@ -26,91 +43,252 @@ fn main() -> Result<(), error::MatchupError> {
     println!("Initializing thread pool with {} threads", args.nprocs);
     rayon::ThreadPoolBuilder::new().num_threads(args.nprocs).build_global().expect("Failed to set up the thread pool");
 
-    match args.command {
+    let checksums = ChecksumCache::from_options(&args.checksum_options)?;
+    let remapper = PathRemapper::from_options(&args.remap_options);
+
+    let res = match args.command {
         Commands::One(subargs) => {
-            driver_one_oco2_file(
-                &subargs.oco2_lite_file, 
-                &subargs.oco3_lite_files, 
-                &subargs.output_file, 
-                subargs.flag0_only,
-                subargs.oco3_self_cross, 
-                subargs.save_full_matches_as.as_deref(), 
-                subargs.read_full_matches.as_deref(),
-                ShowProgress::Yes
-            )
+            driver_one_oco2_file(&subargs, None, &checksums, &remapper, ShowProgress::Yes).map(|_| ())
         },
 
         Commands::Multi(subargs) => {
-            let mut buf = String::new();
-            let mut f = std::fs::File::open(subargs.config_file)?;
-            f.read_to_string(&mut buf)?;
-            let cfg: RunMultiConfig = toml::from_str(&buf)?;
-            driver_multi_oco2_file(&cfg.matchups)
+            let cfg = RunMultiConfig::from_file(&subargs.config_file)?;
+            driver_multi_oco2_file(&cfg, &subargs.run_options, &checksums, &remapper)
+        },
+
+        Commands::Range(subargs) => {
+            let cfg = config::matchups_for_date_range(&subargs.date_range)?;
+            if let Some(config_file) = subargs.write_config.as_deref() {
+                println!("Writing configuration for {} matchups to {}", cfg.matchups.len(), config_file.display());
+                cfg.write_toml(config_file)?;
+            }
+            driver_multi_oco2_file(&cfg, &subargs.run_options, &checksums, &remapper)
+        },
+
+        Commands::Validate(subargs) => {
+            driver_validate(&subargs.config_file)
+        },
+
+        Commands::Verify(subargs) => {
+            driver_verify(&subargs, &checksums, &remapper)
         }
-    }
-    
+    };
+
+    // Keep any checksums computed, even if some matchups failed, but report the matchup errors first
+    let save_res = checksums.save();
+    res.and(save_res)
 }
 
-fn driver_one_oco2_file<P: AsRef<Path>>(
-    oco2_lite_file: &Path,
-    oco3_lite_files: &[P],
-    output_file: &Path,
-    flag0_only: bool,
-    is_oco3_self_crossing: bool,
-    save_full_matches_as: Option<&Path>,
-    read_full_matches: Option<&Path>,
-    show_progress: ShowProgress
-) -> Result<(), MatchupError> {
-    let min_dt = if is_oco3_self_crossing { MIN_SELF_CROSS_DELTA_TIME_SECONDS } else { -0.1 };
+fn driver_one_oco2_file(args: &RunOneArgs, cache: Option<&LiteFileCache>, checksums: &ChecksumCache, remapper: &PathRemapper, show_progress: ShowProgress) -> Result<MatchupStats, MatchupError> {
+    let min_dt = if args.oco3_self_cross { MIN_SELF_CROSS_DELTA_TIME_SECONDS } else { -0.1 };
+    let criteria = MatchCriteria {
+        distance: args.distance_criterion,
+        distance_model: args.distance_model,
+        min_dt_s: min_dt,
+        max_dt_s: MAX_DELTA_TIME_SECONDS,
+        modes: ModeRules::from_options(&args.mode_options),
+        surface: SurfaceRules::from_options(&args.surface_options)?,
+        regions: RegionSet::from_options(&args.region_options)?,
+        score: MatchScore::from_options(&args.score_options)?,
+        nearest: NearestRule::from_options(&args.nearest_options)?,
+        grouping: GroupingRules::from_options(&args.grouping_options, args.flag0_only)?,
+    };
+    let mut stats = MatchupStats::default();
+    let input_files = if let Some(full_matches_in) = args.read_full_matches.as_ref() {
+        vec![full_matches_in.to_owned()]
+    } else {
+        std::iter::once(&args.oco2_lite_file).chain(args.oco3_lite_files.iter()).cloned().collect_vec()
+    };
+    let provenance = Provenance::new(criteria.clone(), args.oco3_self_cross, args.flag0_only, input_files);
 
-    let matched_soundings = if let Some(full_matches_in) = read_full_matches {
+    let groups = if let Some(full_matches_in) = args.read_full_matches.as_deref() {
         show_progress.println(format!("Reading previous matched soundings from {}", full_matches_in.display()));
         let ds = netcdf::open(full_matches_in)
             .map_err(|e| MatchupError::from_nc_error(e, full_matches_in.to_owned()))?;
         let grp = ds.group("matches")
             .map_err(|e| MatchupError::from_nc_error(e, full_matches_in.to_owned()))?
             .ok_or_else(|| MatchupError::NetcdfMissingGroup { file: Some(full_matches_in.to_owned()), grpname: "matches".to_owned() })?;
-        oco::OcoMatches::from_nc_group(&grp)?
+        let mut matched_soundings = oco::OcoMatches::from_nc_group(&grp)?;
+        if !remapper.is_empty() {
+            matched_soundings.remap_lite_files(remapper, checksums)?;
+        }
+
+        // The full match file does not have everything needed for the group geometry, so reload the lite files.
+        // Load all soundings, since we don't know whether the matches were made with --flag0-only. The matches
+        // themselves are all in the full match file, so if the lite files are no longer available, the groups
+        // can still be made, just without the details that need the lite files.
+        show_progress.println("Reading sounding locations from the matched lite files");
+        let (oco2_files, oco3_files) = matched_soundings.lite_files();
+        let first_instrument = Instrument::first(args.oco3_self_cross);
+        let locations = load_lite_files(oco2_files, first_instrument, false, None)
+            .and_then(|oco2_locs| Ok((oco2_locs, load_lite_files(oco3_files, Instrument::Oco3, false, None)?)));
+        let locations = match locations {
+            Ok((oco2_locs, oco3_locs)) => Some(filter_locations(oco2_locs, oco3_locs, &criteria)),
+            Err(e) => {
+                if let Some(reason) = lite_data_requirement(&criteria) {
+                    return Err(MatchupError::ArgumentError(format!("Cannot read the matched lite files ({e}), which are needed {reason}")));
+                }
+                show_progress.println(format!("Warning: cannot read the matched lite files ({e}), so the groups will not include their geometry, times, or other sounding details"));
+                None
+            }
+        };
+
+        // Apply the same sounding filters, scores, and nearest match rule as when matching
+        if let Some((oco2_locs, oco3_locs)) = locations.as_ref() {
+            matched_soundings.retain_soundings(oco2_locs, oco3_locs);
+            matched_soundings.retain_pairs(oco2_locs, oco3_locs, &criteria.modes);
+        }
+        matched_soundings.rescore(&criteria.score);
+        if let Some(rule) = &criteria.nearest {
+            matched_soundings.keep_nearest(rule);
+        }
+        stats.n_matched_pairs = matched_soundings.num_matched_pairs();
+
+        show_progress.println("Grouping OCO-2 and -3 matches");
+        let passes = locations.as_ref().and_then(|(oco2_locs, oco3_locs)| PassKeys::new(oco2_locs, oco3_locs, &criteria.grouping));
+        let mut groups = oco::identify_groups_from_matched_soundings(matched_soundings, passes.as_ref());
+        if let Some((oco2_locs, oco3_locs)) = locations.as_ref() {
+            groups.add_sounding_details(oco2_locs, oco3_locs, &criteria)?;
+        }
+        groups
+    } else if let Some(max_memory_mb) = args.max_memory_mb {
+        if args.save_full_matches_as.is_some() {
+            return Err(MatchupError::ArgumentError("--save-full-matches-as cannot be used with --max-memory".to_owned()));
+        }
+
+        show_progress.println("Looking for matches between OCO-2 and -3 in blocks");
+        let (oco2_locs, oco3_locs) = load_locations(&args.oco2_lite_file, &args.oco3_lite_files, Instrument::first(args.oco3_self_cross), args.flag0_only, &criteria, cache, &show_progress)?;
+        let location_copies = reserve_location_copies(cache, &[&oco2_locs, &oco3_locs])?;
+        stats.n_first_soundings = Some(oco2_locs.num_soundings());
+        stats.n_second_soundings = Some(oco3_locs.num_soundings());
+
+        let chunk_size = match_chunk_size(max_memory_mb, &oco2_locs, &oco3_locs, &show_progress);
+        let spill_file = spill_file_path(&args.output_file);
+        let spilled = oco::match_oco3_to_oco2_chunked(&oco2_locs, &oco3_locs, &criteria, chunk_size, &spill_file, show_progress.clone())?;
+
+        // Only the matched soundings are needed for grouping, so free the rest of the locations first
+        let (oco2_sids, oco3_sids) = spilled.matched_sounding_ids()?;
+        let oco2_matched = oco2_locs.retain_sounding_ids(&oco2_sids);
+        let oco3_matched = oco3_locs.retain_sounding_ids(&oco3_sids);
+        drop(oco2_locs);
+        drop(oco3_locs);
+        drop(location_copies);
+
+        stats.n_matched_pairs = spilled.num_matched_pairs();
+        show_progress.println("Grouping OCO-2 and -3 matches");
+        let passes = PassKeys::new(&oco2_matched, &oco3_matched, &criteria.grouping);
+        let mut groups = oco::identify_groups_from_spilled_matches(&spilled, passes.as_ref())?;
+        drop(passes);
+        groups.add_sounding_details(&oco2_matched, &oco3_matched, &criteria)?;
+        groups
     } else {
         show_progress.println("Looking for matches between OCO-2 and -3");
-        let full_matches = find_matches(oco2_lite_file, oco3_lite_files, flag0_only, min_dt, show_progress.clone())?;
-        if let Some(full_match_file) = save_full_matches_as {
+        let full_matches = find_matches(&args.oco2_lite_file, &args.oco3_lite_files, Instrument::first(args.oco3_self_cross), args.flag0_only, &criteria, cache, show_progress.clone())?;
+        let _location_copies = reserve_location_copies(cache, &[&full_matches.oco2_locations, &full_matches.oco3_locations])?;
+        stats.n_first_soundings = Some(full_matches.oco2_locations.num_soundings());
+        stats.n_second_soundings = Some(full_matches.oco3_locations.num_soundings());
+        if let Some(full_match_file) = args.save_full_matches_as.as_deref() {
             show_progress.println(format!("Saving full match netCDF file: {}", full_match_file.display()));
-            full_matches.save_netcdf(full_match_file)?;
+            full_matches.save_netcdf(full_match_file, &provenance)?;
         }
-        full_matches.matches
+        stats.n_matched_pairs = full_matches.matches.num_matched_pairs();
+        show_progress.println("Grouping OCO-2 and -3 matches");
+        let Output { oco2_locations, oco3_locations, matches } = full_matches;
+        let passes = PassKeys::new(&oco2_locations, &oco3_locations, &criteria.grouping);
+        let mut groups = oco::identify_groups_from_matched_soundings(matches, passes.as_ref());
+        groups.add_sounding_details(&oco2_locations, &oco3_locations, &criteria)?;
+        groups
     };
 
-    show_progress.println("Grouping OCO-2 and -3 matches");
-    matches_to_groups(matched_soundings, output_file, is_oco3_self_crossing)?;
+    stats.n_groups = write_groups(groups, &args.output_file, args.oco3_self_cross, checksums, &provenance)?;
     show_progress.println("Done grouping");
-    Ok(())
+    Ok(stats)
 }
 
-fn driver_multi_oco2_file(matchups: &[RunOneArgs]) -> Result<(), MatchupError> {
+fn driver_multi_oco2_file(cfg: &RunMultiConfig, multi_args: &MultiRunOptions, checksums: &ChecksumCache, remapper: &PathRemapper) -> Result<(), MatchupError> {
+    let matchups = &cfg.matchups;
+    let skip = matchups.iter()
+        .map(|m| {
+            if multi_args.skip_existing && m.output_file.exists() {
+                println!("Skipping matchup for {}: output file already exists", m.output_file.display());
+                true
+            } else if multi_args.resume && matchup_is_complete(m) {
+                println!("Skipping matchup for {}: output already complete", m.output_file.display());
+                true
+            } else {
+                false
+            }
+        }).collect_vec();
+
+    let n_pending = skip.iter().filter(|&&s| !s).count();
+    println!("Running {} of {} matchups", n_pending, matchups.len());
+    let cache = if multi_args.cache_memory_mb > 0 {
+        let pending = matchups.iter().zip(skip.iter()).filter_map(|(m, &s)| if s { None } else { Some(m) });
+        Some(LiteFileCache::new(pending, multi_args.cache_memory_mb * 1_000_000))
+    } else {
+        None
+    };
+
+    if checksums.is_enabled() {
+        // Most lite files are used by several matchups, so compute each checksum once up front
+        // rather than have matchups running at the same time each compute the same one.
+        let lite_files = matchups.iter().zip(skip.iter())
+            .filter(|(_, &s)| !s)
+            .flat_map(|(m, _)| std::iter::once(&m.oco2_lite_file).chain(m.oco3_lite_files.iter()))
+            .unique()
+            .collect_vec();
+        println!("Computing checksums of {} input files", lite_files.len());
+        checksums.precompute(&lite_files);
+    }
+
     let mbar = Arc::new(indicatif::MultiProgress::new());
     
-    let errs: Vec<MatchupError> = matchups.par_iter()
-        .filter_map(|m| {
-            let mbar = Arc::clone(&mbar);
-
-            let res = driver_one_oco2_file(
-                &m.oco2_lite_file, 
-                &m.oco3_lite_files, 
-                &m.output_file,
-                m.flag0_only,
-                m.oco3_self_cross,
-                m.save_full_matches_as.as_deref(),
-                m.read_full_matches.as_deref(),
-                ShowProgress::Multi(mbar)
-            );
-
-            if let Err(e) = res {
-                Some(e)
-            } else {
-                None
+    let results: Vec<(MatchupReport, Option<MatchupError>)> = matchups.par_iter()
+        .zip(skip.par_iter())
+        .map(|(m, &skip_this)| {
+            if skip_this {
+                return (MatchupReport::skipped(m.output_file.clone()), None);
             }
-        }).collect();  
+
+            let show_progress = ShowProgress::Multi(Arc::clone(&mbar));
+            let start = Instant::now();
+            let res = driver_one_oco2_file(m, cache.as_ref(), checksums, remapper, show_progress.clone());
+            let res = if let Some(cache) = cache.as_ref() {
+                // Always release this matchup's files, but do not hide the matchup's own error
+                let release_res = cache.release_matchup(m);
+                res.and_then(|stats| release_res.map(|_| stats))
+            } else {
+                res
+            };
+            let report = MatchupReport::from_result(m.output_file.clone(), start.elapsed().as_secs_f64(), &res);
+
+            let err = res.err().map(|e| {
+                show_progress.println(format!("Matchup for {} failed", m.output_file.display()));
+                MatchupError::MatchupFailed { output_file: m.output_file.clone(), error: Box::new(e) }
+            });
+            (report, err)
+        }).collect();
+
+    let (reports, errs): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+    let errs = errs.into_iter().flatten().collect_vec();
+    let report = RunReport { matchups: reports };
+    println!("{} matchups succeeded, {} failed, {} skipped",
+             report.count(MatchupStatus::Succeeded), report.count(MatchupStatus::Failed), report.count(MatchupStatus::Skipped));
+
+    if let Some(report_file) = multi_args.report.as_deref() {
+        println!("Writing run report to {}", report_file.display());
+        report.write_toml(report_file)?;
+    }
+
+    if let Some(failed_file) = multi_args.failed_config.as_deref() {
+        let failed = matchups.iter()
+            .zip(report.matchups.iter())
+            .filter_map(|(m, r)| if r.status == MatchupStatus::Failed { Some(m.clone()) } else { None })
+            .collect_vec();
+        println!("Writing configuration for {} failed matchups to {}", failed.len(), failed_file.display());
+        let failed_cfg = RunMultiConfig { defaults: cfg.defaults.clone(), output_dir: cfg.output_dir.clone(), matchups: failed };
+        failed_cfg.write_toml(failed_file)?;
+    }
 
     if errs.is_empty() {
         Ok(())
@@ -119,21 +297,181 @@ fn driver_multi_oco2_file(matchups: &[RunOneArgs]) -> Result<(), MatchupError> {
     }
 }
 
-fn find_matches<P: AsRef<Path>>(oco2_lite_file: &Path, oco3_lite_files: &[P], flag0_only: bool, min_dt: f64, show_progress: ShowProgress) -> Result<Output, MatchupError> {
-    let oco2_locs = oco::OcoGeo::load_lite_file(oco2_lite_file, flag0_only)?;
-    let oco3_locs = oco3_lite_files.iter()
-        .fold(Ok(OcoGeo::default()), |acc: Result<OcoGeo, MatchupError>, el| {
-            let acc = acc?;
-            let next_locs = oco::OcoGeo::load_lite_file(el.as_ref(), flag0_only)?;
-            Ok(acc.extend(next_locs))
-        })?;
+fn driver_validate(config_file: &Path) -> Result<(), MatchupError> {
+    let cfg = RunMultiConfig::from_file(config_file)?;
+    println!("Checking {} matchups in {}", cfg.matchups.len(), config_file.display());
+    let report = validation::validate_multi_config(&cfg);
+
+    for warning in report.warnings.iter() {
+        println!("Warning: {warning}");
+    }
+
+    if report.is_ok() {
+        println!("No problems found in {}", config_file.display());
+        Ok(())
+    } else {
+        Err(MatchupError::InvalidConfig { file: config_file.to_owned(), problems: report.errors })
+    }
+}
+
+fn driver_verify(args: &VerifyArgs, checksums: &ChecksumCache, remapper: &PathRemapper) -> Result<(), MatchupError> {
+    if !checksums.is_enabled() {
+        return Err(MatchupError::ArgumentError("--no-checksums cannot be used with the verify subcommand".to_owned()));
+    }
+
+    // Match files made in the same run share many lite files, so hash each one once up front
+    let lite_files = args.match_files.iter()
+        .filter_map(|f| verify::read_lite_file_records(f).ok())
+        .flatten()
+        .map(|(p, _)| remapper.resolve(&p))
+        .filter(|p| p.exists())
+        .unique()
+        .collect_vec();
+    println!("Computing checksums of {} lite files", lite_files.len());
+    checksums.precompute(&lite_files);
+
+    let mut stale = Vec::new();
+    let mut errs = Vec::new();
+    for match_file in args.match_files.iter() {
+        let result = match verify::verify_match_file(match_file, remapper, checksums) {
+            Ok(r) => r,
+            Err(e) => {
+                println!("ERROR: {} could not be checked: {e}", match_file.display());
+                errs.push(e);
+                continue;
+            }
+        };
+
+        println!("{}: {}", if result.is_stale() { "STALE" } else { "OK" }, match_file.display());
+        for lite_file in result.lite_files.iter() {
+            let note = match &lite_file.status {
+                LiteFileStatus::Unchanged => continue,
+                LiteFileStatus::Changed { recorded_sha256, current_sha256 } => format!("changed (recorded SHA-256 {recorded_sha256}, now {current_sha256})"),
+                LiteFileStatus::Missing => "missing".to_owned(),
+                LiteFileStatus::NoChecksum => "no checksum recorded, cannot check".to_owned(),
+            };
+            if lite_file.resolved_path == lite_file.recorded_path {
+                println!("  - {}: {note}", lite_file.recorded_path.display());
+            } else {
+                println!("  - {} (as {}): {note}", lite_file.recorded_path.display(), lite_file.resolved_path.display());
+            }
+        }
+
+        if result.is_stale() {
+            stale.push(match_file.to_owned());
+        }
+    }
+
+    println!("{} of {} match files are stale, {} could not be checked", stale.len(), args.match_files.len(), errs.len());
+    if !stale.is_empty() {
+        errs.push(MatchupError::StaleMatchFiles(stale));
+    }
+
+    match errs.len() {
+        0 => Ok(()),
+        1 => Err(errs.pop().expect("errs has one element")),
+        _ => Err(MatchupError::MultipleErrors(errs))
+    }
+}
+
+/// Check whether all of the output files a matchup would write exist and were completely written.
+fn matchup_is_complete(matchup: &RunOneArgs) -> bool {
+    let full_match_complete = matchup.save_full_matches_as.as_deref()
+        .map(utils::nc_file_is_complete)
+        .unwrap_or(true);
+    full_match_complete && utils::nc_file_is_complete(&matchup.output_file)
+}
+
+/// If grouping matches read from a full match file with `criteria` needs the lite files' data (beyond the
+/// optional group details), return the reason for the error message.
+fn lite_data_requirement(criteria: &MatchCriteria) -> Option<&'static str> {
+    if criteria.grouping.mode != GroupingMode::Connected {
+        Some("to group by pass or orbit")
+    } else if !criteria.modes.first.allows_all() || !criteria.modes.second.allows_all() {
+        Some("to apply the operation mode filters")
+    } else if !criteria.modes.pairs.is_empty() {
+        Some("to apply the operation mode pairs")
+    } else if criteria.surface.only.is_some() {
+        Some("to apply the surface class filter")
+    } else if !criteria.regions.is_empty() {
+        Some("to apply the regions of interest")
+    } else if criteria.grouping.has_thresholds() {
+        Some("to apply the group quality thresholds")
+    } else {
+        None
+    }
+}
+
+/// Load one or more lite files from `instrument` into a single [`OcoGeo`], using `cache` if given. A single file is
+/// returned as shared with the cache rather than copied.
+fn load_lite_files<P: AsRef<Path>>(lite_files: &[P], instrument: Instrument, flag0_only: bool, cache: Option<&LiteFileCache>) -> Result<Arc<OcoGeo>, MatchupError> {
+    let load = |lite_file: &Path| {
+        if let Some(cache) = cache {
+            cache.get(lite_file, instrument, flag0_only)
+        } else {
+            oco::OcoGeo::load_lite_file(lite_file, instrument, flag0_only).map(Arc::new)
+        }
+    };
+
+    let mut geos = lite_files.iter()
+        .map(|f| load(f.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if geos.len() == 1 {
+        return Ok(geos.remove(0));
+    }
+
+    let geo_refs = geos.iter().map(|g| g.as_ref()).collect_vec();
+    Ok(Arc::new(OcoGeo::concatenate(&geo_refs)))
+}
+
+/// Count the lite file data in `locations` that this matchup has its own copy of (i.e. that is not shared with `cache`,
+/// such as several OCO-3 files combined into one, or files with some soundings filtered out) against the cache's memory
+/// limit, for as long as the returned reservation is kept.
+fn reserve_location_copies<'c>(cache: Option<&'c LiteFileCache>, locations: &[&Arc<OcoGeo>]) -> Result<Option<CacheReservation<'c>>, MatchupError> {
+    let Some(cache) = cache else {
+        return Ok(None);
+    };
+
+    let nbytes = locations.iter()
+        .filter(|geo| Arc::strong_count(geo) == 1)
+        .map(|geo| geo.memory_size())
+        .sum();
+    cache.reserve(nbytes).map(Some)
+}
+
+/// Keep only the first and second instrument soundings in the operation modes, surface class, and regions allowed by `criteria`.
+fn filter_locations(oco2_locs: Arc<OcoGeo>, oco3_locs: Arc<OcoGeo>, criteria: &MatchCriteria) -> (Arc<OcoGeo>, Arc<OcoGeo>) {
+    let oco2_locs = oco2_locs
+        .filter_modes(&criteria.modes.first)
+        .filter_surface(&criteria.surface)
+        .filter_regions(&criteria.regions);
+    let oco3_locs = oco3_locs
+        .filter_modes(&criteria.modes.second)
+        .filter_surface(&criteria.surface)
+        .filter_regions(&criteria.regions);
+    (oco2_locs, oco3_locs)
+}
+
+/// Load the soundings to match from the first and second instruments' lite files, keeping only
+/// those in the operation modes, surface class, and regions allowed by `criteria`. The first file is from
+/// `first_instrument`; the others are always OCO-3 files.
+fn load_locations<P: AsRef<Path>>(oco2_lite_file: &Path, oco3_lite_files: &[P], first_instrument: Instrument, flag0_only: bool, criteria: &MatchCriteria, cache: Option<&LiteFileCache>, show_progress: &ShowProgress) -> Result<(Arc<OcoGeo>, Arc<OcoGeo>), MatchupError> {
+    let (oco2_locs, oco3_locs) = filter_locations(
+        load_lite_files(&[oco2_lite_file], first_instrument, flag0_only, cache)?,
+        load_lite_files(oco3_lite_files, Instrument::Oco3, flag0_only, cache)?,
+        criteria
+    );
 
     let n_oco3_files = oco3_locs.file_index.iter().max()
         .map(|&n| n+1).unwrap_or(0);
     show_progress.println(format!("Comparing {} OCO-2 soundings to {} OCO-3 soundings across {} files", 
              oco2_locs.num_soundings(), oco3_locs.num_soundings(), n_oco3_files));
+    Ok((oco2_locs, oco3_locs))
+}
 
-    let matches = oco::match_oco3_to_oco2_parallel(&oco2_locs, &oco3_locs, 100.0, min_dt, MAX_DELTA_TIME_SECONDS, show_progress);
+fn find_matches<P: AsRef<Path>>(oco2_lite_file: &Path, oco3_lite_files: &[P], first_instrument: Instrument, flag0_only: bool, criteria: &MatchCriteria, cache: Option<&LiteFileCache>, show_progress: ShowProgress) -> Result<Output, MatchupError> {
+    let (oco2_locs, oco3_locs) = load_locations(oco2_lite_file, oco3_lite_files, first_instrument, flag0_only, criteria, cache, &show_progress)?;
+    let matches = oco::match_oco3_to_oco2_parallel(&oco2_locs, &oco3_locs, criteria, show_progress);
     Ok(Output {
         oco2_locations: oco2_locs,
         oco3_locations: oco3_locs,
@@ -141,14 +479,46 @@ fn find_matches<P: AsRef<Path>>(oco2_lite_file: &Path, oco3_lite_files: &[P], fl
     })
 }
 
-fn matches_to_groups(matched_soundings: oco::OcoMatches, nc_file: &Path, is_oco3_self_crossing: bool) -> Result<(), MatchupError> {
-    let groups = oco::identify_groups_from_matched_soundings(matched_soundings);
-    log::debug!("Creating nc_file {}", nc_file.display());
-    let mut ds = netcdf::create(nc_file)
-        .map_err(|e| MatchupError::from_nc_error(e, nc_file.to_owned()))?;
+/// Choose how many OCO-2 soundings to match at once to stay within about `max_memory_mb` megabytes.
+/// 
+/// The memory available for matches is whatever is left after the locations loaded from the lite files,
+/// and each OCO-2 sounding is assumed to need `MATCH_BYTES_PER_SOUNDING` bytes for its matches.
+fn match_chunk_size(max_memory_mb: usize, oco2_locs: &OcoGeo, oco3_locs: &OcoGeo, show_progress: &ShowProgress) -> usize {
+    let max_bytes = max_memory_mb * 1_000_000;
+    let loc_bytes = oco2_locs.memory_size() + oco3_locs.memory_size();
+    if loc_bytes >= max_bytes {
+        show_progress.println(format!(
+            "Warning: the lite file data alone needs about {} MB, more than the --max-memory limit of {max_memory_mb} MB; matching in minimum size blocks",
+            loc_bytes / 1_000_000
+        ));
+        return MIN_MATCH_CHUNK_SIZE;
+    }
+
+    ((max_bytes - loc_bytes) / MATCH_BYTES_PER_SOUNDING).max(MIN_MATCH_CHUNK_SIZE)
+}
+
+/// Path to the temporary file that matches are written to while matching in blocks for `output_file`.
+fn spill_file_path(output_file: &Path) -> PathBuf {
+    let mut name = output_file.file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".matches.tmp");
+    output_file.with_file_name(name)
+}
+
+fn write_groups(groups: oco::OcoMatchGroups, nc_file: &Path, is_oco3_self_crossing: bool, checksums: &ChecksumCache, provenance: &Provenance) -> Result<u64, MatchupError> {
+    let n_groups = groups.num_groups();
+    let tmp_file = utils::partial_output_path(nc_file);
+    log::debug!("Creating nc_file {}", tmp_file.display());
+    let mut ds = netcdf::create(&tmp_file)
+        .map_err(|e| MatchupError::from_nc_error(e, tmp_file.clone()))?;
     log::debug!("File created successfully");
-    groups.to_nc_group(&mut ds, None, is_oco3_self_crossing)?;
-    Ok(())
+    groups.to_nc_group(&mut ds, None, is_oco3_self_crossing, checksums, provenance)?;
+    utils::mark_nc_file_complete(&mut ds)?;
+    // Close the file before moving it
+    drop(ds);
+    utils::finalize_partial_output(&tmp_file, nc_file)?;
+    Ok(n_groups)
 }
 
 #[derive(Debug, Parser)]
@@ -158,6 +528,12 @@ struct MainArgs {
     #[clap(short='n', long, default_value="8")]
     nprocs: usize,
 
+    #[command(flatten)]
+    checksum_options: ChecksumOptions,
+
+    #[command(flatten)]
+    remap_options: RemapOptions,
+
     #[command(subcommand)]
     command: Commands
 }
@@ -168,37 +544,53 @@ enum Commands {
     One(RunOneArgs),
     /// Run a matchup between multiple OCO-2 files and their corresponding OCO-3 files
     /// as specified in a TOML file.
-    Multi(RunMultiArgs)
+    Multi(RunMultiArgs),
+    /// Run daily matchups over a range of dates, finding the lite files from directory
+    /// templates in the same way as `make-oco-match-config`, without writing a TOML file first.
+    Range(RunRangeArgs),
+    /// Check a TOML file for the `multi` subcommand for problems (e.g. missing input files
+    /// or multiple matchups writing the same output) without running any matchups.
+    Validate(ValidateArgs),
+    /// Check whether the lite files that match (group) files were made from still exist and
+    /// have the same SHA-256 checksums recorded in the match files. Exits with an error if any
+    /// match file is stale.
+    Verify(VerifyArgs)
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 struct Output {
-    oco2_locations: oco::OcoGeo,
-    oco3_locations: oco::OcoGeo,
+    oco2_locations: Arc<OcoGeo>,
+    oco3_locations: Arc<OcoGeo>,
     matches: oco::OcoMatches
 }
 
 impl Output {
-    fn save_netcdf(&self, nc_file: &Path) -> Result<(), MatchupError> {
-        println!("Creating netCDF file {}", nc_file.display());
-        let mut ds = netcdf::create(nc_file)
-            .map_err(|e| MatchupError::from_nc_error(e, nc_file.to_owned()))?;
+    fn save_netcdf(&self, nc_file: &Path, provenance: &Provenance) -> Result<(), MatchupError> {
+        let tmp_file = utils::partial_output_path(nc_file);
+        println!("Creating netCDF file {}", tmp_file.display());
+        let mut ds = netcdf::create(&tmp_file)
+            .map_err(|e| MatchupError::from_nc_error(e, tmp_file.clone()))?;
+        provenance.write_global_attributes(&mut ds)
+            .map_err(|e| e.set_file(tmp_file.clone()))?;
 
         println!("Saving OCO-2 locations");
         let mut oco2_grp = ds.add_group("oco2_locations")
-            .map_err(|e| MatchupError::from_nc_error(e, nc_file.to_owned()))?;
+            .map_err(|e| MatchupError::from_nc_error(e, tmp_file.clone()))?;
         self.oco2_locations.to_nc_group(&mut oco2_grp)?;
 
         println!("Saving OCO-3 locations");
         let mut oco3_grp = ds.add_group("oco3_locations")
-            .map_err(|e| MatchupError::from_nc_error(e, nc_file.to_owned()))?;
+            .map_err(|e| MatchupError::from_nc_error(e, tmp_file.clone()))?;
         self.oco3_locations.to_nc_group(&mut oco3_grp)?;
 
         println!("Saving match groups");
         let mut match_grp = ds.add_group("matches")
-            .map_err(|e| MatchupError::from_nc_error(e, nc_file.to_owned()))?;
+            .map_err(|e| MatchupError::from_nc_error(e, tmp_file.clone()))?;
         self.matches.to_nc_group(&mut match_grp)?;
 
+        utils::mark_nc_file_complete(&mut ds)?;
+        drop(ds);
+        utils::finalize_partial_output(&tmp_file, nc_file)?;
         println!("Done saving full match file {}", nc_file.display());
         Ok(())
     }
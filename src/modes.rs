@@ -0,0 +1,213 @@
+//! Instrument operation modes (nadir, glint, target, SAM) and the rules for which modes may be matched.
+//!
+//! Lite files store the mode of each sounding as an integer code in `Sounding/operation_mode`. OCO-2
+//! uses codes 0 to 3 (nadir, glint, target, transition); OCO-3 uses the same codes plus 4 for
+//! snapshot area maps (SAMs).
+use std::fmt::Display;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ModeOptions;
+use crate::error::MatchupError;
+
+/// Group in the lite files containing the operation mode variable
+pub const OPERATION_MODE_GROUP: &str = "Sounding";
+/// Name of the operation mode variable in the lite files
+pub const OPERATION_MODE_VARIABLE: &str = "operation_mode";
+/// Code used for soundings whose operation mode is not known, e.g. from lite files without an operation mode variable
+pub const UNKNOWN_MODE_CODE: u8 = u8::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationMode {
+    Nadir,
+    Glint,
+    Target,
+    Transition,
+    /// Snapshot area map (OCO-3 only)
+    Sam,
+}
+
+impl OperationMode {
+    /// All modes, in order of their lite file codes
+    pub const ALL: [OperationMode; 5] = [Self::Nadir, Self::Glint, Self::Target, Self::Transition, Self::Sam];
+
+    /// The mode for a lite file `operation_mode` code, if it is a known one.
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+
+    /// The lite file `operation_mode` code for this mode.
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The bit representing this mode in a mode mask, see [`mode_mask`].
+    pub fn mask_bit(&self) -> u8 {
+        1 << self.code()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nadir => "nadir",
+            Self::Glint => "glint",
+            Self::Target => "target",
+            Self::Transition => "transition",
+            Self::Sam => "sam",
+        }
+    }
+
+    /// Space separated names of all the modes, in the same order as [`OperationMode::ALL`]; suitable for
+    /// a CF `flag_meanings` attribute.
+    pub fn flag_meanings() -> String {
+        Self::ALL.iter().map(|m| m.name()).join(" ")
+    }
+}
+
+impl Display for OperationMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Combine the operation mode codes of a set of soundings into a bit mask with the bit for
+/// each mode present set (see [`OperationMode::mask_bit`]). Unknown codes are ignored.
+pub fn mode_mask<I: IntoIterator<Item = u8>>(codes: I) -> u8 {
+    codes.into_iter()
+        .filter_map(OperationMode::from_code)
+        .fold(0, |mask, m| mask | m.mask_bit())
+}
+
+/// A pair of modes that may be matched: soundings of the first instrument in mode `first`
+/// with soundings of the second instrument in mode `second`. Written as "FIRST:SECOND",
+/// e.g. "nadir:sam".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModePair {
+    pub first: OperationMode,
+    pub second: OperationMode,
+}
+
+impl FromStr for ModePair {
+    type Err = MatchupError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_mode = |m: &str| {
+            OperationMode::from_str(m.trim(), true)
+                .map_err(|_| MatchupError::ArgumentError(format!(
+                    "Unknown operation mode '{m}' in mode pair '{s}', expected one of: {}",
+                    OperationMode::ALL.iter().join(", ")
+                )))
+        };
+
+        let (first, second) = s.split_once(':')
+            .ok_or_else(|| MatchupError::ArgumentError(format!("Mode pair '{s}' must be in the form FIRST:SECOND, e.g. nadir:sam")))?;
+        Ok(Self { first: parse_mode(first)?, second: parse_mode(second)? })
+    }
+}
+
+impl TryFrom<String> for ModePair {
+    type Error = MatchupError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ModePair> for String {
+    fn from(value: ModePair) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for ModePair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.first, self.second)
+    }
+}
+
+/// Which operation modes of one instrument may be matched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModeFilter {
+    /// If not empty, only these modes are allowed
+    pub include: Vec<OperationMode>,
+    /// These modes are never allowed
+    pub exclude: Vec<OperationMode>,
+}
+
+impl ModeFilter {
+    pub fn new(include: Vec<OperationMode>, exclude: Vec<OperationMode>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// Returns `true` if this filter allows every sounding.
+    pub fn allows_all(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether soundings with lite file operation mode `code` are allowed. Soundings with an unknown
+    /// mode are only allowed if there is no include list.
+    pub fn allows(&self, code: u8) -> bool {
+        match OperationMode::from_code(code) {
+            Some(mode) => (self.include.is_empty() || self.include.contains(&mode)) && !self.exclude.contains(&mode),
+            None => self.include.is_empty(),
+        }
+    }
+}
+
+impl Display for ModeFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let included = if self.include.is_empty() { "all".to_owned() } else { self.include.iter().join(" ") };
+        if self.exclude.is_empty() {
+            write!(f, "{included}")
+        } else {
+            write!(f, "{included} except {}", self.exclude.iter().join(" "))
+        }
+    }
+}
+
+/// All the operation mode restrictions for one matchup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModeRules {
+    /// Modes allowed for the first instrument (OCO-2, or the first OCO-3 pass)
+    pub first: ModeFilter,
+    /// Modes allowed for the second instrument
+    pub second: ModeFilter,
+    /// If not empty, only these pairs of modes may be matched
+    pub pairs: Vec<ModePair>,
+}
+
+impl ModeRules {
+    pub fn from_options(opts: &ModeOptions) -> Self {
+        Self {
+            first: ModeFilter::new(opts.oco2_modes.clone(), opts.exclude_oco2_modes.clone()),
+            second: ModeFilter::new(opts.oco3_modes.clone(), opts.exclude_oco3_modes.clone()),
+            pairs: opts.mode_pairs.clone(),
+        }
+    }
+
+    /// Whether a first instrument sounding with mode code `first` may be matched with a second instrument
+    /// sounding with mode code `second`. Only the pair rules are checked here; the per-instrument filters
+    /// are applied when the soundings are loaded. Soundings with unknown modes match no pair rule.
+    pub fn allows_pair(&self, first: u8, second: u8) -> bool {
+        if self.pairs.is_empty() {
+            return true;
+        }
+
+        let first = OperationMode::from_code(first);
+        let second = OperationMode::from_code(second);
+        self.pairs.iter().any(|p| Some(p.first) == first && Some(p.second) == second)
+    }
+
+    /// Description of the allowed mode pairs, for output metadata
+    pub fn pairs_description(&self) -> String {
+        if self.pairs.is_empty() {
+            "any".to_owned()
+        } else {
+            self.pairs.iter().join(" ")
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Reducing matches to one-to-one pairs of nearest soundings.
+//!
+//! Normally every second instrument sounding that meets the match criteria is matched to a first instrument
+//! sounding. With a [`NearestRule`], each first instrument sounding keeps only the match with the lowest cost
+//! (see [`NearestMetric`]), and optionally only if that second instrument sounding has no lower cost match to
+//! any other first instrument sounding.
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::NearestOptions;
+use crate::error::MatchupError;
+
+/// What "nearest" means when choosing one match for each sounding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NearestMetric {
+    /// Smallest distance
+    Distance,
+    /// Smallest absolute time difference
+    Time,
+    /// Smallest space-time score (see [`crate::score::MatchScore`])
+    SpaceTime,
+}
+
+impl NearestMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Distance => "distance",
+            Self::Time => "time",
+            Self::SpaceTime => "space-time",
+        }
+    }
+}
+
+impl Display for NearestMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How to choose a single match for each sounding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NearestRule {
+    pub metric: NearestMetric,
+    /// If true, a pair is only kept if each sounding is the other's nearest match
+    pub mutual: bool,
+}
+
+impl NearestRule {
+    /// Make the rule from the command line or configuration options. Returns `None` if every match should be kept.
+    pub fn from_options(opts: &NearestOptions) -> Result<Option<Self>, MatchupError> {
+        let Some(metric) = opts.nearest else {
+            if opts.mutual_nearest {
+                return Err(MatchupError::ArgumentError("--mutual-nearest requires --nearest".to_owned()));
+            }
+            return Ok(None);
+        };
+
+        Ok(Some(Self { metric, mutual: opts.mutual_nearest }))
+    }
+
+    /// The cost of a match with distance `dist_km`, time difference `dt_s`, and space-time score `score`;
+    /// the match with the lowest cost is the nearest.
+    pub fn cost(&self, dist_km: f32, dt_s: f32, score: f32) -> f32 {
+        match self.metric {
+            NearestMetric::Distance => dist_km,
+            NearestMetric::Time => dt_s.abs(),
+            NearestMetric::SpaceTime => score,
+        }
+    }
+
+    /// A short description of the rule for output file metadata, e.g. "space-time, mutual".
+    pub fn description(&self) -> String {
+        if self.mutual {
+            format!("{}, mutual", self.metric)
+        } else {
+            self.metric.to_string()
+        }
+    }
+}
@@ -1,24 +1,44 @@
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::{Mutex, Arc};
+use std::sync::Arc;
 
 // use indicatif::{ProgressBar, ProgressStyle, ParallelProgressIterator};
-use indicatif::ParallelProgressIterator;
 use itertools::{izip, Itertools};
-use ndarray::{Array1, Ix1, Ix2, concatenate, Axis, Array2, Array};
+use ndarray::{Array1, ArrayView1, Ix1, Ix2, concatenate, Axis, Array};
 use netcdf::extent::Extents;
 use rayon::prelude::*;
 use rayon::iter::ParallelIterator;
 use serde::Serialize;
 
+use crate::checksum::ChecksumCache;
 use crate::error::MatchupError;
-use crate::utils::{load_nc_var, write_nc_var, filter_by_quality, great_circle_distance, self, RunningMean, ShowProgress};
+use crate::distance::DistanceCriterion;
+use crate::geodesy::{self, DistanceModel};
+use crate::geometry::{self, GroupGeometry, SoundingPoint};
+use crate::grouping::{self, GroupingMode, GroupingRules, Instrument, RejectedGroups};
+use crate::modes::{self, ModeFilter, ModeRules, OperationMode};
+use crate::nearest::NearestRule;
+use crate::score::MatchScore;
+use crate::region::{RegionFilter, RegionSet};
+use crate::surface::{self, SurfaceClass, SurfaceRules};
+use crate::timing::{GroupTiming, TIME_UNITS};
+use crate::provenance::Provenance;
+use crate::remap::PathRemapper;
+use crate::spill::{MatchSpillWriter, SpilledMatches};
+use crate::utils::{load_nc_var, write_nc_var, filter_by_mask, self, RunningMean, ShowProgress};
 
 const SOUNDING_ID_UNITS: &str = "YYYYMMDDhhmmssmf";
+const SOUNDING_ID_COMMENT: &str = "Sounding ID format is YYYYMMDDhhmmssmf";
+/// CF coordinates attribute for per-sounding variables in the full match file
+const SOUNDING_COORDINATES: &str = "time latitude longitude";
 const SOUNDING_ID_DESCR_OCO2: &str = "OCO-2 sounding ID";
 const SOUNDING_ID_DESCR_OCO3: &str = "OCO-3 sounding ID";
+/// Soundings this many seconds or more before/after a sounding are used to find its track heading
+const TRACK_HEADING_MIN_DT_S: f64 = 2.0;
+/// Soundings more than this many seconds before/after a sounding are not used to find its track heading
+const TRACK_HEADING_MAX_DT_S: f64 = 10.0;
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Clone, Serialize, Default)]
 pub struct OcoGeo {
     pub lite_files: Vec<PathBuf>,
     pub file_index: Array1<u8>,
@@ -27,11 +47,46 @@ pub struct OcoGeo {
     pub timestamp: Array1<f64>,
     pub longitude: Array1<f32>,
     pub latitude: Array1<f32>,
-    pub quality: Array1<u8>
+    pub quality: Array1<u8>,
+    /// Lite file operation mode code of each sounding (see [`OperationMode`]), or [`modes::UNKNOWN_MODE_CODE`]
+    /// if the lite file does not include the operation mode
+    pub operation_mode: Array1<u8>,
+    /// Land fraction of each sounding in percent, or NaN if the lite file does not include it
+    pub land_fraction: Array1<f32>,
+    /// Orbit number of each sounding. If the lite file does not include it, this is estimated from the
+    /// sounding times (see [`grouping::estimate_orbits`]).
+    pub orbit: Array1<u32>,
+    /// XCO2 of each sounding in ppm, or NaN if the lite file does not include it
+    pub xco2: Array1<f32>,
+    /// Heading of the instrument's track at each sounding in degrees clockwise from north, or NaN if it could not
+    /// be estimated. This is computed from all the soundings in the lite file, before any are filtered out.
+    pub track_heading: Array1<f32>,
+    /// Longest time in seconds between consecutive soundings in the lite file from the previous sounding kept here
+    /// (or the previous sounding in the file, if none have been removed) up to this one, or infinity for the first
+    /// sounding of each file. This lets [`OcoGeo::pass_numbers`] find the same passes however the soundings are filtered.
+    pub time_gap_before_s: Array1<f64>,
 }
 
 impl OcoGeo {
-    pub fn load_lite_file(lite_file: &Path, flag0_only: bool) -> Result<Self, MatchupError> {
+    /// Variables that must be present in every lite file passed to [`OcoGeo::load_lite_file`]
+    pub const REQUIRED_VARIABLES: [&'static str; 5] = ["sounding_id", "time", "longitude", "latitude", "xco2_quality_flag"];
+
+    /// Check that `lite_file` can be opened as a netCDF file and has all of the variables
+    /// needed to load it, without reading any of the data.
+    pub fn check_lite_file(lite_file: &Path) -> Result<(), MatchupError> {
+        let ds = netcdf::open(lite_file)
+            .map_err(|e| MatchupError::from_nc_error(e, lite_file.to_owned()))?;
+
+        for varname in Self::REQUIRED_VARIABLES {
+            if ds.variable(varname).is_none() {
+                return Err(MatchupError::NetcdfMissingVar { file: Some(lite_file.to_owned()), varname: varname.to_owned() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the soundings from `lite_file`, which is from `instrument`, keeping only the good quality ones if `flag0_only` is true.
+    pub fn load_lite_file(lite_file: &Path, instrument: Instrument, flag0_only: bool) -> Result<Self, MatchupError> {
         let ds = netcdf::open(lite_file)
         .map_err(|e| MatchupError::from_nc_error(e, lite_file.to_owned()))?;
             
@@ -39,19 +94,202 @@ impl OcoGeo {
         let timestamp = load_nc_var(&ds, "time")?;
         let longitude = load_nc_var(&ds, "longitude")?;
         let latitude = load_nc_var(&ds, "latitude")?;
-        let quality = load_nc_var(&ds, "xco2_quality_flag")?;
+        let quality: Array1<u8> = load_nc_var(&ds, "xco2_quality_flag")?;
+        let operation_mode = Self::load_operation_mode(&ds, lite_file, timestamp.len())?;
+        let land_fraction = Self::load_land_fraction(&ds, lite_file, timestamp.len())?;
+        let orbit = Self::load_orbit(&ds, lite_file, timestamp.view(), instrument)?;
+        let xco2 = Self::load_xco2(&ds, lite_file, timestamp.len())?;
         let file_index = Array1::zeros(timestamp.len());
         let sounding_index = Array1::from_iter(0..timestamp.len());
 
+        // Lite files are in time order, so the gap before each sounding is from the one before it in the file
+        let time_gap_before_s = std::iter::once(f64::INFINITY)
+            .chain(timestamp.iter().tuple_windows().map(|(&prev, &t)| t - prev))
+            .take(timestamp.len())
+            .collect();
+
+        let mut geo = OcoGeo { lite_files: vec![lite_file.to_owned()], file_index, sounding_id, sounding_index, timestamp, longitude, latitude, quality, operation_mode, land_fraction, orbit, xco2, track_heading: Array1::zeros(0), time_gap_before_s };
+        geo.track_heading = geo.track_headings();
         if flag0_only {
-            let longitude = filter_by_quality(longitude.view(), quality.view());
-            let latitude = filter_by_quality(latitude.view(), quality.view());
-            let quality = filter_by_quality(quality.view(), quality.view());
-            Ok(OcoGeo { lite_files: vec![lite_file.to_owned()], file_index, sounding_id, sounding_index, timestamp, longitude, latitude, quality })
+            let keep = geo.quality.iter().map(|&q| q == 0).collect_vec();
+            Ok(geo.select(&keep))
         }else{
-            Ok(OcoGeo { lite_files: vec![lite_file.to_owned()], file_index, sounding_id, sounding_index, timestamp, longitude, latitude, quality })
+            Ok(geo)
+        }
+    }
+
+    /// Read the operation mode of each sounding from a lite file. Lite files without the operation
+    /// mode variable are allowed, with a warning, and all their soundings given an unknown mode.
+    fn load_operation_mode(ds: &netcdf::File, lite_file: &Path, n_soundings: usize) -> Result<Array1<u8>, MatchupError> {
+        if let Some(values) = Self::load_optional_group_var(ds, lite_file, modes::OPERATION_MODE_GROUP, modes::OPERATION_MODE_VARIABLE, n_soundings)? {
+            Ok(values)
+        } else {
+            log::warn!("{} has no {}/{} variable, its soundings' operation modes will be unknown",
+                       lite_file.display(), modes::OPERATION_MODE_GROUP, modes::OPERATION_MODE_VARIABLE);
+            Ok(Array1::from_elem(n_soundings, modes::UNKNOWN_MODE_CODE))
+        }
+    }
+
+    /// Read the land fraction (in percent) of each sounding from a lite file. If the lite file has no land fraction
+    /// variable, the surface type is used instead (treating land soundings as 100% land and ocean soundings as 0%).
+    /// If neither is present, a warning is logged and all soundings' land fractions are NaN.
+    fn load_land_fraction(ds: &netcdf::File, lite_file: &Path, n_soundings: usize) -> Result<Array1<f32>, MatchupError> {
+        if let Some(values) = Self::load_optional_group_var(ds, lite_file, surface::LAND_FRACTION_GROUP, surface::LAND_FRACTION_VARIABLE, n_soundings)? {
+            Ok(values)
+        } else if let Some(values) = Self::load_optional_group_var::<u8>(ds, lite_file, surface::SURFACE_TYPE_GROUP, surface::SURFACE_TYPE_VARIABLE, n_soundings)? {
+            log::debug!("{} has no land fraction variable, using surface type instead", lite_file.display());
+            Ok(values.mapv(surface::surface_type_to_land_fraction))
+        } else {
+            log::warn!("{} has neither {}/{} nor {}/{}, its soundings' land fractions will be unknown",
+                       lite_file.display(), surface::LAND_FRACTION_GROUP, surface::LAND_FRACTION_VARIABLE,
+                       surface::SURFACE_TYPE_GROUP, surface::SURFACE_TYPE_VARIABLE);
+            Ok(Array1::from_elem(n_soundings, f32::NAN))
+        }
+    }
+
+    /// Read the orbit number of each sounding from a lite file. Lite files without the orbit variable, or with
+    /// some soundings' orbits missing, are allowed, with a warning, and the missing orbits estimated from `timestamp`
+    /// and `instrument`'s orbital period instead.
+    fn load_orbit(ds: &netcdf::File, lite_file: &Path, timestamp: ArrayView1<f64>, instrument: Instrument) -> Result<Array1<u32>, MatchupError> {
+        if let Some(values) = Self::load_optional_group_var(ds, lite_file, grouping::ORBIT_GROUP, grouping::ORBIT_VARIABLE, timestamp.len())? {
+            if !values.iter().any(|&o| o == grouping::UNKNOWN_ORBIT) {
+                return Ok(values);
+            }
+            log::warn!("{} has soundings without an orbit number, their orbits will be estimated from their times", lite_file.display());
+            Ok(grouping::estimate_orbits(timestamp, values.view(), instrument.orbit_period_s()))
+        } else {
+            log::warn!("{} has no {}/{} variable, its soundings' orbits will be estimated from their times",
+                       lite_file.display(), grouping::ORBIT_GROUP, grouping::ORBIT_VARIABLE);
+            let unknown = Array1::from_elem(timestamp.len(), grouping::UNKNOWN_ORBIT);
+            Ok(grouping::estimate_orbits(timestamp, unknown.view(), instrument.orbit_period_s()))
+        }
+    }
+
+    /// Read the XCO2 of each sounding from a lite file. Lite files without XCO2 are allowed, with a warning,
+    /// and all their soundings given a NaN XCO2.
+    fn load_xco2(ds: &netcdf::File, lite_file: &Path, n_soundings: usize) -> Result<Array1<f32>, MatchupError> {
+        if ds.variable(grouping::XCO2_VARIABLE).is_some() {
+            let values: Array1<f32> = load_nc_var(ds, grouping::XCO2_VARIABLE)?;
+            if values.len() != n_soundings {
+                return Err(MatchupError::InternalError(format!(
+                    "{} in {} has {} values, expected {n_soundings}", grouping::XCO2_VARIABLE, lite_file.display(), values.len()
+                )));
+            }
+            Ok(values)
+        } else {
+            log::warn!("{} has no {} variable, its soundings' XCO2 will be unknown", lite_file.display(), grouping::XCO2_VARIABLE);
+            Ok(Array1::from_elem(n_soundings, f32::NAN))
+        }
+    }
+
+    /// Read a 1D per-sounding variable from a group in a lite file, returning `None` if the group or variable does not exist.
+    fn load_optional_group_var<T: netcdf::NcPutGet>(ds: &netcdf::File, lite_file: &Path, grpname: &str, varname: &str, n_soundings: usize) -> Result<Option<Array1<T>>, MatchupError> {
+        let grp = ds.group(grpname)
+            .map_err(|e| MatchupError::from_nc_error(e, lite_file.to_owned()))?;
+        let var = if let Some(var) = grp.as_ref().and_then(|grp| grp.variable(varname)) {
+            var
+        } else {
+            return Ok(None);
+        };
+
+        let varname = format!("{grpname}/{varname}");
+        let values = var.values_arr::<T, _>(Extents::All)
+            .map_err(|e| MatchupError::from_nc_error(e, lite_file.to_owned()))?
+            .into_dimensionality::<Ix1>()
+            .map_err(|e| MatchupError::from_shape_error(e, lite_file.to_owned(), varname.clone()))?;
+
+        if values.len() != n_soundings {
+            return Err(MatchupError::InternalError(format!(
+                "{varname} in {} has {} values, expected one per sounding ({n_soundings})", lite_file.display(), values.len()
+            )));
+        }
+        Ok(Some(values))
+    }
+
+    /// Keep only the soundings where `keep` is `true`. `keep` must have one element per sounding.
+    pub fn select(&self, keep: &[bool]) -> Self {
+        Self {
+            lite_files: self.lite_files.clone(),
+            file_index: filter_by_mask(self.file_index.view(), keep),
+            sounding_id: filter_by_mask(self.sounding_id.view(), keep),
+            sounding_index: filter_by_mask(self.sounding_index.view(), keep),
+            timestamp: filter_by_mask(self.timestamp.view(), keep),
+            longitude: filter_by_mask(self.longitude.view(), keep),
+            latitude: filter_by_mask(self.latitude.view(), keep),
+            quality: filter_by_mask(self.quality.view(), keep),
+            operation_mode: filter_by_mask(self.operation_mode.view(), keep),
+            land_fraction: filter_by_mask(self.land_fraction.view(), keep),
+            orbit: filter_by_mask(self.orbit.view(), keep),
+            xco2: filter_by_mask(self.xco2.view(), keep),
+            track_heading: filter_by_mask(self.track_heading.view(), keep),
+            time_gap_before_s: self.select_time_gaps(keep),
+        }
+    }
+
+    /// The `time_gap_before_s` of the soundings kept by `keep`, each including the gaps before the soundings removed
+    /// since the previous kept one.
+    fn select_time_gaps(&self, keep: &[bool]) -> Array1<f64> {
+        let mut gaps = Vec::new();
+        let mut gap_since_kept: f64 = 0.0;
+        for (&gap, &k) in self.time_gap_before_s.iter().zip(keep) {
+            gap_since_kept = gap_since_kept.max(gap);
+            if k {
+                gaps.push(gap_since_kept);
+                gap_since_kept = 0.0;
+            }
+        }
+        Array1::from_vec(gaps)
+    }
+
+    /// Keep only the soundings whose operation mode is allowed by `filter`. Like the other filters, this
+    /// takes a shared instance (e.g. from a [`crate::cache::LiteFileCache`]) and only makes a copy if
+    /// any soundings are removed.
+    pub fn filter_modes(self: Arc<Self>, filter: &ModeFilter) -> Arc<Self> {
+        if filter.allows_all() {
+            return self;
+        }
+
+        let keep = self.operation_mode.iter().map(|&m| filter.allows(m)).collect_vec();
+        Self::select_shared(self, &keep)
+    }
+
+    /// Keep only the soundings whose surface class is allowed by `rules`.
+    pub fn filter_surface(self: Arc<Self>, rules: &SurfaceRules) -> Arc<Self> {
+        if rules.only.is_none() {
+            return self;
+        }
+
+        let keep = self.land_fraction.iter().map(|&f| rules.allows(f)).collect_vec();
+        Self::select_shared(self, &keep)
+    }
+
+    /// Keep only the soundings inside at least one of `regions`, if they are to be filtered by sounding
+    /// (see [`RegionFilter`]).
+    pub fn filter_regions(self: Arc<Self>, regions: &RegionSet) -> Arc<Self> {
+        if regions.is_empty() || regions.filter != RegionFilter::Soundings {
+            return self;
         }
 
+        let keep = self.longitude.iter()
+            .zip(self.latitude.iter())
+            .map(|(&lon, &lat)| regions.any_contains(lon as f64, lat as f64))
+            .collect_vec();
+        Self::select_shared(self, &keep)
+    }
+
+    /// Keep only the soundings whose IDs are in `sounding_ids`.
+    pub fn retain_sounding_ids(&self, sounding_ids: &HashSet<u64>) -> Self {
+        let keep = self.sounding_id.iter().map(|sid| sounding_ids.contains(sid)).collect_vec();
+        self.select(&keep)
+    }
+
+    /// Like [`OcoGeo::select`], but returns `geo` itself rather than a copy if every sounding is kept.
+    fn select_shared(geo: Arc<Self>, keep: &[bool]) -> Arc<Self> {
+        if keep.iter().all(|&k| k) {
+            geo
+        } else {
+            Arc::new(geo.select(keep))
+        }
     }
 
     pub fn to_nc_group(&self, grp: &mut netcdf::GroupMut) -> Result<(), MatchupError> {
@@ -76,34 +314,188 @@ impl OcoGeo {
         println!("  -> Writing file index");
         write_nc_var(grp, self.file_index.view(), "file_index", &["sounding"], None, Some("Index of the lite_file variable that defines the path which this point came from"))?;
         println!("  -> Writing timestamps");
-        write_nc_var(grp, self.timestamp.view(), "time", &["sounding"], Some("seconds since 1970-01-01 00:00:00"), None)?;
+        write_nc_var(grp, self.timestamp.view(), "time", &["sounding"], Some(TIME_UNITS), None)?;
         println!("  -> Writing longitudes");
         write_nc_var(grp, self.longitude.view(), "longitude", &["sounding"], Some("degrees_east"), None)?;
         println!("  -> Writing latitudes");
         write_nc_var(grp, self.latitude.view(), "latitude", &["sounding"], Some("degrees_north"), None)?;
         println!("  -> Writing quality flags");
         write_nc_var(grp, self.quality.view(), "quality_flag", &["sounding"], None, Some("0 = good, 1 = bad"))?;
+        println!("  -> Writing operation modes");
+        write_nc_var(grp, self.operation_mode.view(), "operation_mode", &["sounding"], None, Some("Instrument operation mode, as given in the lite file"))?;
+        println!("  -> Writing land fractions");
+        write_nc_var(grp, self.land_fraction.view(), "land_fraction", &["sounding"], Some("percent"), Some("Percent of the sounding footprint over land"))?;
+        println!("  -> Writing orbits");
+        write_nc_var(grp, self.orbit.view(), "orbit", &["sounding"], None, Some("Orbit number; numbers from 1000000000 up were estimated from the sounding times of lite files without any orbit numbers"))?;
+        println!("  -> Writing XCO2");
+        write_nc_var(grp, self.xco2.view(), "xco2", &["sounding"], Some("ppm"), None)?;
+
+        println!("  -> Writing CF attributes");
+        utils::add_string_attributes(grp, "lite_file", &[("long_name", "source lite file path")])?;
+        utils::add_string_attributes(grp, "file_index", &[("long_name", "source lite file index"), ("coordinates", SOUNDING_COORDINATES)])?;
+        utils::add_string_attributes(grp, "time", &[("standard_name", "time"), ("long_name", "sounding time")])?;
+        utils::add_string_attributes(grp, "longitude", &[("standard_name", "longitude"), ("long_name", "sounding longitude")])?;
+        utils::add_string_attributes(grp, "latitude", &[("standard_name", "latitude"), ("long_name", "sounding latitude")])?;
+        utils::add_string_attributes(grp, "quality_flag", &[("long_name", "XCO2 quality flag"), ("flag_meanings", "good bad"), ("coordinates", SOUNDING_COORDINATES)])?;
+        grp.variable_mut("quality_flag")
+            .ok_or_else(|| MatchupError::NetcdfMissingVar { file: None, varname: "quality_flag".to_owned() })?
+            .add_attribute("flag_values", vec![0u8, 1u8])?;
+        utils::add_string_attributes(grp, "operation_mode", &[("long_name", "operation mode"), ("flag_meanings", &OperationMode::flag_meanings()), ("coordinates", SOUNDING_COORDINATES)])?;
+        let mut var = grp.variable_mut("operation_mode")
+            .ok_or_else(|| MatchupError::NetcdfMissingVar { file: None, varname: "operation_mode".to_owned() })?;
+        var.add_attribute("flag_values", OperationMode::ALL.iter().map(|m| m.code()).collect_vec())?;
+        var.add_attribute("missing_value", modes::UNKNOWN_MODE_CODE)?;
+        utils::add_string_attributes(grp, "land_fraction", &[("long_name", "sounding land fraction"), ("coordinates", SOUNDING_COORDINATES)])?;
+        utils::add_string_attributes(grp, "orbit", &[("long_name", "orbit number"), ("coordinates", SOUNDING_COORDINATES)])?;
+        utils::add_string_attributes(grp, "xco2", &[("long_name", "column-averaged dry air mole fraction of CO2"), ("coordinates", SOUNDING_COORDINATES)])?;
 
         Ok(())
     }
 
-    pub fn extend(mut self, other: Self) -> Self {
-        let curr_n_files = self.lite_files.len() as u8;
-        self.lite_files.extend(other.lite_files);
-        self.file_index = concatenate![Axis(0), self.file_index, other.file_index + curr_n_files];
-        self.sounding_id = concatenate![Axis(0), self.sounding_id, other.sounding_id];
-        self.sounding_index = concatenate![Axis(0), self.sounding_index, other.sounding_index];
-        self.timestamp = concatenate![Axis(0), self.timestamp, other.timestamp];
-        self.longitude = concatenate![Axis(0), self.longitude, other.longitude];
-        self.latitude = concatenate![Axis(0), self.latitude, other.latitude];
-        self.quality = concatenate![Axis(0), self.quality, other.quality];
+    pub fn extend(self, other: Self) -> Self {
+        Self::concatenate(&[&self, &other])
+    }
+
+    /// Combine the soundings from several instances into one, in order, without copying any of them first.
+    pub fn concatenate(geos: &[&Self]) -> Self {
+        fn cat<T: Clone>(geos: &[&OcoGeo], get: impl Fn(&OcoGeo) -> ArrayView1<T>) -> Array1<T> {
+            let views = geos.iter().map(|g| get(g)).collect_vec();
+            concatenate(Axis(0), &views).expect("1D arrays can always be concatenated")
+        }
+
+        if geos.is_empty() {
+            return Self::default();
+        }
+
+        let mut lite_files = Vec::new();
+        let mut file_indices = Vec::with_capacity(geos.len());
+        for geo in geos {
+            let offset = lite_files.len() as u8;
+            lite_files.extend(geo.lite_files.iter().cloned());
+            file_indices.push(&geo.file_index + offset);
+        }
+        let file_index_views = file_indices.iter().map(|a| a.view()).collect_vec();
+
+        Self {
+            lite_files,
+            file_index: concatenate(Axis(0), &file_index_views).expect("1D arrays can always be concatenated"),
+            sounding_id: cat(geos, |g| g.sounding_id.view()),
+            sounding_index: cat(geos, |g| g.sounding_index.view()),
+            timestamp: cat(geos, |g| g.timestamp.view()),
+            longitude: cat(geos, |g| g.longitude.view()),
+            latitude: cat(geos, |g| g.latitude.view()),
+            quality: cat(geos, |g| g.quality.view()),
+            operation_mode: cat(geos, |g| g.operation_mode.view()),
+            land_fraction: cat(geos, |g| g.land_fraction.view()),
+            orbit: cat(geos, |g| g.orbit.view()),
+            xco2: cat(geos, |g| g.xco2.view()),
+            track_heading: cat(geos, |g| g.track_heading.view()),
+            time_gap_before_s: cat(geos, |g| g.time_gap_before_s.view()),
+        }
+    }
+
+    /// Estimate the heading of the instrument's track at each sounding, in degrees clockwise from north.
+    ///
+    /// Soundings in the same frame are spread across the track, so the heading is taken from the mean positions
+    /// of the soundings from the same file between `TRACK_HEADING_MIN_DT_S` and `TRACK_HEADING_MAX_DT_S` seconds
+    /// before and after each sounding. Soundings with no others in either window have a NaN heading.
+    /// This is used to fill in `track_heading` when a lite file is loaded, so that removing soundings afterwards
+    /// does not change the headings of the rest.
+    fn track_headings(&self) -> Array1<f32> {
+        let mut headings = Array1::from_elem(self.timestamp.len(), f32::NAN);
+        let by_file = (0..self.timestamp.len()).into_group_map_by(|&i| self.file_index[i]);
+
+        for (_, mut inds) in by_file {
+            inds.sort_by(|&a, &b| self.timestamp[a].total_cmp(&self.timestamp[b]));
+            let times = inds.iter().map(|&i| self.timestamp[i]).collect_vec();
+
+            for (k, &i) in inds.iter().enumerate() {
+                let t = times[k];
+                let window = |start: f64, end: f64| {
+                    let lo = times.partition_point(|&x| x < start);
+                    let hi = times.partition_point(|&x| x <= end);
+                    &inds[lo..hi]
+                };
+                let mean_pos = |window: &[usize]| {
+                    if window.is_empty() {
+                        return (self.longitude[i], self.latitude[i]);
+                    }
+                    // Average longitude offsets from this sounding, so that windows crossing the dateline are not split
+                    let n = window.len() as f32;
+                    let dlon = window.iter().map(|&j| ((self.longitude[j] - self.longitude[i]) + 180.0).rem_euclid(360.0) - 180.0).sum::<f32>() / n;
+                    let lat = window.iter().map(|&j| self.latitude[j]).sum::<f32>() / n;
+                    (self.longitude[i] + dlon, lat)
+                };
+
+                let before = window(t - TRACK_HEADING_MAX_DT_S, t - TRACK_HEADING_MIN_DT_S);
+                let after = window(t + TRACK_HEADING_MIN_DT_S, t + TRACK_HEADING_MAX_DT_S);
+                if before.is_empty() && after.is_empty() {
+                    continue;
+                }
+                let (lon0, lat0) = mean_pos(before);
+                let (lon1, lat1) = mean_pos(after);
+                headings[i] = geometry::initial_bearing(lon0, lat0, lon1, lat1);
+            }
+        }
+
+        headings
+    }
+
+    /// Number each sounding's pass, in time order: a new pass starts whenever consecutive soundings are more than
+    /// `max_gap_s` seconds apart or from different orbits (if both orbits are known). For consecutive soundings from
+    /// the same lite file, the gap is the longest one between them in the file (see `time_gap_before_s`), so soundings
+    /// that were filtered out still count toward a pass.
+    pub fn pass_numbers(&self, max_gap_s: f64) -> Array1<u32> {
+        let mut order = (0..self.timestamp.len()).collect_vec();
+        order.sort_by(|&a, &b| self.timestamp[a].total_cmp(&self.timestamp[b]));
+
+        let mut passes = Array1::zeros(self.timestamp.len());
+        let mut pass = 0;
+        for (&prev, &i) in order.iter().tuple_windows() {
+            let orbit_changed = self.orbit[i] != self.orbit[prev]
+                && self.orbit[i] != grouping::UNKNOWN_ORBIT
+                && self.orbit[prev] != grouping::UNKNOWN_ORBIT;
+            let gap = if self.file_index[i] == self.file_index[prev] {
+                self.time_gap_before_s[i]
+            } else {
+                self.timestamp[i] - self.timestamp[prev]
+            };
+            if orbit_changed || gap > max_gap_s {
+                pass += 1;
+            }
+            passes[i] = pass;
+        }
+        passes
+    }
+
+    /// Map each sounding ID to its index in this instance's arrays.
+    pub fn sounding_lookup(&self) -> HashMap<u64, usize> {
+        self.sounding_id.iter()
+            .enumerate()
+            .map(|(i, &sid)| (sid, i))
+            .collect()
+    }
 
-        self
+    /// The position and time of the sounding at index `i` in this instance's arrays.
+    pub fn sounding_point(&self, i: usize) -> SoundingPoint {
+        SoundingPoint { longitude: self.longitude[i], latitude: self.latitude[i], timestamp: self.timestamp[i] }
     }
 
     pub fn num_soundings(&self) -> u64 {
         self.longitude.len() as u64
     }
+
+    /// Approximate number of bytes of memory used by the data arrays in this instance.
+    pub fn memory_size(&self) -> usize {
+        fn nbytes<T>(arr: &Array1<T>) -> usize {
+            arr.len() * std::mem::size_of::<T>()
+        }
+
+        nbytes(&self.file_index) + nbytes(&self.sounding_id) + nbytes(&self.sounding_index)
+            + nbytes(&self.timestamp) + nbytes(&self.longitude) + nbytes(&self.latitude)
+            + nbytes(&self.quality) + nbytes(&self.operation_mode) + nbytes(&self.land_fraction)
+            + nbytes(&self.orbit) + nbytes(&self.xco2) + nbytes(&self.track_heading) + nbytes(&self.time_gap_before_s)
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -149,6 +541,86 @@ impl OcoMatches {
         "time_difference"
     }
 
+    fn score_varname() -> &'static str {
+        "score"
+    }
+
+    fn row_size_varname() -> &'static str {
+        "row_size"
+    }
+
+    /// Total number of matched pairs of soundings between the two instruments.
+    pub fn num_matched_pairs(&self) -> u64 {
+        self.matches.iter().map(|m| m.oco3_sounding_ids.len() as u64).sum()
+    }
+
+    /// The lite files from the first and second instruments that these matches refer to.
+    pub fn lite_files(&self) -> (&[PathBuf], &[PathBuf]) {
+        (&self.oco2_files, &self.oco3_files)
+    }
+
+    /// Replace the recorded paths of the lite files these matches came from with where those files are
+    /// on this system, as found by `remapper`. Full match files do not record checksums of their lite
+    /// files, so files found in a search directory are matched by name only.
+    pub fn remap_lite_files(&mut self, remapper: &PathRemapper, checksums: &ChecksumCache) -> Result<(), MatchupError> {
+        self.oco2_files = remapper.locate_all(&self.oco2_files, None, checksums)?;
+        self.oco3_files = remapper.locate_all(&self.oco3_files, None, checksums)?;
+        Ok(())
+    }
+
+    /// Reduce the matches to the nearest OCO-3 sounding for each OCO-2 sounding, as when matching with
+    /// [`MatchCriteria::nearest`] set. Use this to apply the rule to matches read from a full match file.
+    pub fn keep_nearest(&mut self, rule: &NearestRule) {
+        let matches = std::mem::take(&mut self.matches);
+        let nearest_first = rule.mutual.then(|| nearest_first_soundings(&matches, rule, HashMap::new()));
+        self.matches = keep_nearest_matches(matches, rule, nearest_first.as_ref());
+    }
+
+    /// Keep only the matched pairs where the first sounding is in `first` and the second in `second`. Use this to
+    /// apply the sounding filters (e.g. [`OcoGeo::filter_regions`]) to matches read from a full match file.
+    pub fn retain_soundings(&mut self, first: &OcoGeo, second: &OcoGeo) {
+        let first_sids: HashSet<u64> = first.sounding_id.iter().copied().collect();
+        let second_sids: HashSet<u64> = second.sounding_id.iter().copied().collect();
+        self.matches.retain(|m| first_sids.contains(&m.oco2_sounding_id));
+        for m in self.matches.iter_mut() {
+            let keep = m.oco3_sounding_ids.iter().map(|sid| second_sids.contains(sid)).collect_vec();
+            m.retain_oco3(&keep);
+        }
+        self.matches.retain(|m| !m.is_empty());
+    }
+
+    /// Keep only the matched pairs whose operation modes are allowed by the pair rules in `modes`, looking up each
+    /// sounding's mode in `first` and `second`. Use this to apply the mode pairs to matches read from a full match
+    /// file; pairs with a sounding not in `first` or `second` are removed.
+    pub fn retain_pairs(&mut self, first: &OcoGeo, second: &OcoGeo, modes: &ModeRules) {
+        if modes.pairs.is_empty() {
+            return;
+        }
+
+        let first_modes: HashMap<u64, u8> = first.sounding_id.iter().copied().zip(first.operation_mode.iter().copied()).collect();
+        let second_modes: HashMap<u64, u8> = second.sounding_id.iter().copied().zip(second.operation_mode.iter().copied()).collect();
+        for m in self.matches.iter_mut() {
+            let keep = if let Some(&first_mode) = first_modes.get(&m.oco2_sounding_id) {
+                m.oco3_sounding_ids.iter()
+                    .map(|sid| second_modes.get(sid).is_some_and(|&second_mode| modes.allows_pair(first_mode, second_mode)))
+                    .collect_vec()
+            } else {
+                vec![false; m.oco3_sounding_ids.len()]
+            };
+            m.retain_oco3(&keep);
+        }
+        self.matches.retain(|m| !m.is_empty());
+    }
+
+    /// Recompute the score of every matched pair with `score` and remove pairs it does not accept. Use this on
+    /// matches read from a full match file, which may have been scored differently or not at all.
+    pub fn rescore(&mut self, score: &MatchScore) {
+        for m in self.matches.iter_mut() {
+            m.rescore(score);
+        }
+        self.matches.retain(|m| !m.is_empty());
+    }
+
     fn from_matches(mut sounding_matches: Vec<Match2to3>, oco2_files: Vec<PathBuf>, oco3_files: Vec<PathBuf>) -> Self {
         // Ensure that the matches are ordered by OCO-2 sounding ID, this avoids issues with groups of matches getting
         // split up because we examine them out of order
@@ -190,42 +662,64 @@ impl OcoMatches {
             Ok(vec_out)
         }
 
-        fn load_string_var(grp: &netcdf::Group, varname: &str) -> Result<Vec<String>, MatchupError> {
-            let mut v = Vec::new();
-            let var = grp.variable(varname)
-                .ok_or_else(|| MatchupError::NetcdfMissingVar { file: None, varname: varname.to_owned() })?;
-
-            let n = if let [dim] = var.dimensions() {
-                dim.len()
-            } else {
-                return Err(MatchupError::InternalError("Expected a full match file to have only string variables with 1 dimension".to_owned()));
-            };
-
-            for i in 0..n {
-                let s = var.string_value(i)?;
-                v.push(s);
+        fn load_ragged_var<T: netcdf::NcPutGet + std::cmp::PartialEq + Clone>(grp: &netcdf::Group, varname: &str, row_sizes: &[u32]) -> Result<Vec<Vec<T>>, MatchupError> {
+            let flat = load_1d_var::<T>(grp, varname)?;
+            let n_expected: usize = row_sizes.iter().map(|&n| n as usize).sum();
+            if flat.len() != n_expected {
+                return Err(MatchupError::InternalError(format!(
+                    "Ragged variable {varname} has {} elements, but the row sizes add up to {n_expected}", flat.len()
+                )));
             }
-            
 
-            Ok(v)
+            let mut it = flat.into_iter();
+            let vec_out = row_sizes.iter()
+                .map(|&n| it.by_ref().take(n as usize).collect())
+                .collect();
+            Ok(vec_out)
         }
 
-        let oco2_files = load_string_var(grp, "oco2_file")?
+        let oco2_files = utils::read_string_nc_var(grp, "oco2_file")?
             .iter()
             .map(PathBuf::from)
             .collect_vec();
-        let oco3_files = load_string_var(grp, "oco3_file")?
+        let oco3_files = utils::read_string_nc_var(grp, "oco3_file")?
             .iter()
             .map(PathBuf::from)
             .collect_vec();
         let oco2_file_indices = load_1d_var::<u8>(grp, Self::oco2_fileindex_varname())?;
         let oco2_sounding_indices = load_1d_var::<u64>(grp, Self::oco2_index_varname())?;
         let oco2_sounding_ids = load_1d_var::<u64>(grp, Self::oco2_sounding_id_varname())?;
-        let oco3_file_indices = load_2d_var::<u8>(grp, Self::oco3_fileindex_varname())?;
-        let oco3_sounding_indices = load_2d_var::<u64>(grp, Self::oco3_index_varname())?;
-        let oco3_sounding_ids = load_2d_var::<u64>(grp, Self::oco3_sounding_id_varname())?;
-        let distances = load_2d_var::<f32>(grp, Self::dist_varname())?;
-        let time_diffs = load_2d_var::<f32>(grp, Self::time_diff_varname())?;
+
+        // Files written by older versions stored the OCO-3 matches as 2D arrays padded with fill values,
+        // newer ones use a contiguous ragged array with a row size variable. Support reading both.
+        // Files written before pairs were scored have no score variable; their scores are NaN until rescored.
+        let (oco3_file_indices, oco3_sounding_indices, oco3_sounding_ids, distances, time_diffs, scores) = if grp.variable(Self::row_size_varname()).is_some() {
+            let row_sizes = load_1d_var::<u32>(grp, Self::row_size_varname())?;
+            let scores = if grp.variable(Self::score_varname()).is_some() {
+                load_ragged_var::<f32>(grp, Self::score_varname(), &row_sizes)?
+            } else {
+                row_sizes.iter().map(|&n| vec![f32::NAN; n as usize]).collect()
+            };
+            (
+                load_ragged_var::<u8>(grp, Self::oco3_fileindex_varname(), &row_sizes)?,
+                load_ragged_var::<u64>(grp, Self::oco3_index_varname(), &row_sizes)?,
+                load_ragged_var::<u64>(grp, Self::oco3_sounding_id_varname(), &row_sizes)?,
+                load_ragged_var::<f32>(grp, Self::dist_varname(), &row_sizes)?,
+                load_ragged_var::<f32>(grp, Self::time_diff_varname(), &row_sizes)?,
+                scores,
+            )
+        } else {
+            let distances = load_2d_var::<f32>(grp, Self::dist_varname())?;
+            let scores = distances.iter().map(|row| vec![f32::NAN; row.len()]).collect();
+            (
+                load_2d_var::<u8>(grp, Self::oco3_fileindex_varname())?,
+                load_2d_var::<u64>(grp, Self::oco3_index_varname())?,
+                load_2d_var::<u64>(grp, Self::oco3_sounding_id_varname())?,
+                distances,
+                load_2d_var::<f32>(grp, Self::time_diff_varname())?,
+                scores,
+            )
+        };
 
         let it = izip!(
             oco2_file_indices.into_iter(),
@@ -235,15 +729,16 @@ impl OcoMatches {
             oco3_sounding_indices.into_iter(),
             oco3_sounding_ids.into_iter(),
             distances.into_iter(),
-            time_diffs.into_iter()
+            time_diffs.into_iter(),
+            scores.into_iter()
         );
 
         let oco_matches: Vec<Match2to3> = it
-            .map(|(oco2_fi, oco2_i, oco2_sid, oco3_fi, oco3_i, oco3_sid, dist, dt)| {
+            .map(|(oco2_fi, oco2_i, oco2_sid, oco3_fi, oco3_i, oco3_sid, dist, dt, score)| {
                 Match2to3 { 
                     oco2_file_index: oco2_fi, oco2_sounding_index: oco2_i, oco2_sounding_id: oco2_sid,
                     oco3_file_indices: oco3_fi, oco3_sounding_indices: oco3_i, oco3_sounding_ids: oco3_sid,
-                    distance_km: dist, time_diff_s: dt
+                    distance_km: dist, time_diff_s: dt, score
                 }
             }).collect();
 
@@ -253,16 +748,20 @@ impl OcoMatches {
     }
 
     pub fn to_nc_group(&self, grp: &mut netcdf::GroupMut) -> Result<(), MatchupError> {
-        // Vlen types have weird lifetime issues, so we're doing 2D arrays.
+        // Vlen types have weird lifetime issues, and padding each OCO-2 sounding's matches out to the longest
+        // row wastes a lot of space, so the OCO-3 matches are stored as a CF contiguous ragged array: the
+        // matches for all OCO-2 soundings are concatenated along the match_pair dimension, and row_size gives
+        // how many belong to each OCO-2 sounding.
         
         let n_oco2 = self.matches.len();
-        let max_oco3 = self.calc_match_dim()?;
+        let row_sizes = self.calc_row_sizes()?;
+        let n_pairs: usize = row_sizes.iter().map(|&n| n as usize).sum();
 
         println!("  -> Adding dimensions");
         grp.add_dimension("oco2_file", self.oco2_files.len())?;
         grp.add_dimension("oco3_file", self.oco3_files.len())?;
         grp.add_dimension("oco2_match", n_oco2)?;
-        grp.add_dimension("oco3_match", max_oco3)?;
+        grp.add_dimension("match_pair", n_pairs)?;
 
         println!("  -> Writing the OCO -2 and -3 file paths");
         Self::write_paths_variable(grp, &self.oco2_files, "oco2_file", "oco2_file", Some("Paths to the OCO-2 lite files used in this matchup"))?;
@@ -272,11 +771,32 @@ impl OcoMatches {
         self.write_1d_variable(grp, Self::oco2_index_varname(), None, Some("0-based index of the sounding within its lite file"), |m| m.oco2_sounding_index, u64::MAX)?;
         self.write_1d_variable(grp, Self::oco2_sounding_id_varname(), Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO2), |m| m.oco2_sounding_id, u64::MAX)?;
 
-        self.write_2d_variable(grp, Self::oco3_fileindex_varname(), None, Some("0-based index of the file from the oco2_file variable that this sounding came from"), |m| m.oco3_file_indices.as_slice(), u8::MAX)?;
-        self.write_2d_variable(grp, Self::oco3_index_varname(), None, Some("0-based index of the sounding within its lite file"), |m| m.oco3_sounding_indices.as_slice(), u64::MAX)?;
-        self.write_2d_variable(grp, Self::oco3_sounding_id_varname(), Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO3), |m| m.oco3_sounding_ids.as_slice(), u64::MAX)?;
-        self.write_2d_variable(grp, Self::dist_varname(), Some("km"), Some("Distance between the OCO-2 and OCO-3 sounding"), |m| m.distance_km.as_slice(), f32::MAX)?;
-        self.write_2d_variable(grp, Self::time_diff_varname(), Some("s"), Some("Time difference between the OCO-2 and OCO-3 sounding in seconds"), |m| m.time_diff_s.as_slice(), f32::MAX)?;
+        println!("  -> Writing row size variable");
+        let mut var = grp.add_variable::<u32>(Self::row_size_varname(), &["oco2_match"])?;
+        var.compression(9, true)?;
+        var.put_values(&row_sizes, Extents::All)?;
+        var.add_attribute("long_name", "number of matched OCO-3 soundings for this OCO-2 sounding")?;
+        var.add_attribute("sample_dimension", "match_pair")?;
+
+        self.write_ragged_variable(grp, Self::oco3_fileindex_varname(), None, Some("0-based index of the file from the oco3_file variable that this sounding came from"), |m| m.oco3_file_indices.as_slice(), u8::MAX)?;
+        self.write_ragged_variable(grp, Self::oco3_index_varname(), None, Some("0-based index of the sounding within its lite file"), |m| m.oco3_sounding_indices.as_slice(), u64::MAX)?;
+        self.write_ragged_variable(grp, Self::oco3_sounding_id_varname(), Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO3), |m| m.oco3_sounding_ids.as_slice(), u64::MAX)?;
+        self.write_ragged_variable(grp, Self::dist_varname(), Some("km"), Some("Distance between the OCO-2 and OCO-3 sounding"), |m| m.distance_km.as_slice(), f32::MAX)?;
+        self.write_ragged_variable(grp, Self::time_diff_varname(), Some("s"), Some("Time difference between the OCO-2 and OCO-3 sounding in seconds"), |m| m.time_diff_s.as_slice(), f32::MAX)?;
+        self.write_ragged_variable(grp, Self::score_varname(), Some("1"), Some("Space-time score of the OCO-2 and OCO-3 sounding pair; lower is closer. The formula is given by the match_score_formula global attribute."), |m| m.score.as_slice(), f32::NAN)?;
+
+        println!("  -> Writing CF attributes");
+        utils::add_string_attributes(grp, "oco2_file", &[("long_name", "OCO-2 lite file path")])?;
+        utils::add_string_attributes(grp, "oco3_file", &[("long_name", "OCO-3 lite file path")])?;
+        utils::add_string_attributes(grp, Self::oco2_fileindex_varname(), &[("long_name", "OCO-2 lite file index")])?;
+        utils::add_string_attributes(grp, Self::oco2_index_varname(), &[("long_name", "OCO-2 sounding index")])?;
+        utils::add_string_attributes(grp, Self::oco2_sounding_id_varname(), &[("long_name", "OCO-2 sounding ID"), ("comment", SOUNDING_ID_COMMENT)])?;
+        utils::add_string_attributes(grp, Self::oco3_fileindex_varname(), &[("long_name", "OCO-3 lite file index")])?;
+        utils::add_string_attributes(grp, Self::oco3_index_varname(), &[("long_name", "OCO-3 sounding index")])?;
+        utils::add_string_attributes(grp, Self::oco3_sounding_id_varname(), &[("long_name", "OCO-3 sounding ID"), ("comment", SOUNDING_ID_COMMENT)])?;
+        utils::add_string_attributes(grp, Self::dist_varname(), &[("long_name", "OCO-2 to OCO-3 sounding distance")])?;
+        utils::add_string_attributes(grp, Self::time_diff_varname(), &[("long_name", "OCO-2 minus OCO-3 sounding time")])?;
+        utils::add_string_attributes(grp, Self::score_varname(), &[("long_name", "OCO-2 to OCO-3 sounding space-time score")])?;
         Ok(())
     }
 
@@ -291,17 +811,13 @@ impl OcoMatches {
         arr
     }
 
-    fn get_match_2d_array<F, T>(&self, get_row: F, row_length: usize, fill_value: T) -> Array2<T>
+    fn get_match_ragged_array<F, T>(&self, get_row: F) -> Vec<T>
     where F: Fn(&Match2to3) -> &[T],
           T: Clone + Copy
     {
-        let mut arr: Array2<T> = Array2::from_elem((self.matches.len(), row_length), fill_value);
-        for (i, m) in self.matches.iter().enumerate() {
-            for (j, &v) in get_row(m).iter().enumerate() {
-                arr[[i, j]] = v;
-            }
-        }
-        arr
+        self.matches.iter()
+            .flat_map(|m| get_row(m).iter().copied())
+            .collect()
     }
 
     fn write_paths_variable(grp: &mut netcdf::GroupMut, paths: &[PathBuf], varname: &str, dim: &str, description: Option<&str>) -> Result<(), MatchupError>{
@@ -339,7 +855,7 @@ impl OcoMatches {
         Ok(())
     }
 
-    fn write_2d_variable<T: netcdf::NcPutGet + Clone + Copy, F: Fn(&Match2to3) -> &[T]>(
+    fn write_ragged_variable<T: netcdf::NcPutGet + Clone + Copy, F: Fn(&Match2to3) -> &[T]>(
         &self,
         grp: &mut netcdf::GroupMut,
         varname: &str,
@@ -348,18 +864,13 @@ impl OcoMatches {
         get_row: F,
         fill_value: T
     ) -> Result<(), MatchupError>{
-        println!("  -> Writing 2D variable {varname}");
-        let row_length = grp.dimension("oco3_match")
-            .expect("oco3_match dimension must be created before calling `write_2d_variable`")
-            .len();
-
-        let mut var = grp.add_variable::<T>(varname, &["oco2_match", "oco3_match"])?;
+        println!("  -> Writing ragged variable {varname}");
+        let mut var = grp.add_variable::<T>(varname, &["match_pair"])?;
         var.set_fill_value(fill_value)?;
         var.compression(9, true)?;
 
-
-        let arr = self.get_match_2d_array(get_row, row_length, fill_value);
-        var.put_values(arr.as_slice().unwrap(), Extents::All)?;
+        let data = self.get_match_ragged_array(get_row);
+        var.put_values(&data, Extents::All)?;
 
         println!("  -> Writing attributes");
         if let Some(units) = units {
@@ -374,33 +885,89 @@ impl OcoMatches {
         Ok(())
     }
 
-    fn calc_match_dim(&self) -> Result<usize, MatchupError> {        
-        let ninds: Result<Vec<usize>, MatchupError> = self.matches
+    fn calc_row_sizes(&self) -> Result<Vec<u32>, MatchupError> {        
+        self.matches
             .iter()
             .map(|v| {
                 let n_fi = v.oco3_file_indices.len();
                 let n_i = v.oco3_sounding_indices.len();
                 let n_sid = v.oco3_sounding_ids.len();
                 let n_dist = v.distance_km.len();
+                let n_dt = v.time_diff_s.len();
+                let n_score = v.score.len();
 
-                if n_fi == n_i && n_fi == n_sid && n_fi == n_dist {
-                    Ok(n_fi)
+                if n_fi == n_i && n_fi == n_sid && n_fi == n_dist && n_fi == n_dt && n_fi == n_score {
+                    Ok(n_fi as u32)
                 }else{
                     Err(MatchupError::InternalError(format!(
-                        "Inconsistent lengths of OCO-3 match values. File indices = {}, sounding indices = {}, sounding IDs = {}, distance = {}",
-                        n_fi, n_i, n_sid, n_dist
+                        "Inconsistent lengths of OCO-3 match values. File indices = {}, sounding indices = {}, sounding IDs = {}, distance = {}, time difference = {}, score = {}",
+                        n_fi, n_i, n_sid, n_dist, n_dt, n_score
                     )))
                 }
             })
-            .collect();
+            .collect()
+    }
+    
+}
 
-        let max_ninds = ninds?.into_iter().max().unwrap_or(0);
-        Ok(max_ninds)
 
+/// Number of match groups per chunk in the group output variables
+const GROUP_CHUNK_SIZE: usize = 4096;
+const GROUP_DEFLATE_LEVEL: i32 = 4;
+
+/// Information about a group computed from the locations and times of its soundings
+struct GroupDetails {
+    geometry: GroupGeometry,
+    timing: GroupTiming,
+    /// Mask of the operation modes of the first instrument's soundings, see [`modes::mode_mask`]
+    first_modes: u8,
+    /// Mask of the operation modes of the second instrument's soundings
+    second_modes: u8,
+    /// Mean land fraction (percent) of the first instrument's soundings
+    first_land_fraction: f32,
+    /// Mean land fraction (percent) of the second instrument's soundings
+    second_land_fraction: f32,
+    /// Surface class of all the group's soundings, if known
+    surface_class: Option<SurfaceClass>,
+    /// Whether any of the group's soundings are in each of the regions of interest, in the same order as the regions
+    in_regions: Vec<bool>,
+    /// Lowest and highest known orbit number of the first instrument's soundings, or [`grouping::UNKNOWN_ORBIT`] if none are known
+    first_orbits: [u32; 2],
+    /// Lowest and highest known orbit number of the second instrument's soundings
+    second_orbits: [u32; 2],
+    /// Why the group fails the group quality thresholds, as a combination of the `grouping::REJECT_*` bits (0 if it passes)
+    rejection_reasons: u8,
+}
+
+/// Running means over the matched pairs of soundings in one group
+#[derive(Debug, Clone, Copy, Default)]
+struct GroupPairStats {
+    distance: RunningMean<f32>,
+    time_diff: RunningMean<f32>,
+    score: RunningMean<f32>,
+}
+
+impl GroupPairStats {
+    fn add_pair(&mut self, dist_km: f32, dt_s: f32, score: f32) {
+        self.distance.add_value(dist_km);
+        self.time_diff.add_value(dt_s);
+        self.score.add_value(score);
     }
-    
 }
 
+/// The values written to the output file for one group of matched soundings,
+/// see [`OcoMatchGroups::to_nc_group`]. Each array is the (start, end) of the group.
+struct GroupSummary {
+    oco2_sounding_ids: [u64; 2],
+    oco2_sounding_indices: [u64; 2],
+    oco2_file_indices: [u64; 2],
+    oco3_sounding_ids: [u64; 2],
+    oco3_sounding_indices: [u64; 2],
+    oco3_file_indices: [u64; 2],
+    mean_distance: f32,
+    mean_time_diff: f32,
+    mean_score: f32,
+}
 
 pub struct OcoMatchGroups {
     oco2_lite_files: Vec<PathBuf>,
@@ -409,122 +976,568 @@ pub struct OcoMatchGroups {
     match_sets: Vec<(HashSet<u64>, HashSet<u64>)>,
     oco2_sounding_indices: HashMap<u64, (u8, u64)>,
     oco3_sounding_indices: HashMap<u64, (u8, u64)>,
-    distances: HashMap<u64, RunningMean<f32>>,
-    time_diffs: HashMap<u64, RunningMean<f32>>
+    /// Statistics of the matched pairs in each group, in the same order as `match_sets`
+    pair_stats: Vec<GroupPairStats>,
+    /// The geometry and times of each group, in the same order as `match_sets`, if they have been computed by `add_sounding_details`
+    details: Option<Vec<GroupDetails>>,
 }
 
 impl OcoMatchGroups {
-    pub fn to_nc_group(&self, ds: &mut netcdf::MutableFile, group_name: Option<&str>, is_oco3_self_crossing: bool) -> Result<(), MatchupError> {
+    /// Number of groups of matched soundings.
+    pub fn num_groups(&self) -> u64 {
+        self.match_sets.len() as u64
+    }
+
+    /// Compute the geometry (crossing point, track headings, extent, etc.; see [`GroupGeometry`]), times
+    /// (see [`GroupTiming`]), operation modes, and land fractions of each group from the soundings in `first`
+    /// (the OCO-2 or first OCO-3 pass soundings) and `second` (the OCO-3 soundings). These must include all
+    /// of the soundings in the groups. Groups are classified as land, ocean, or mixed by `criteria.surface` and
+    /// tagged with the regions in `criteria.regions` they fall in. If the regions filter by group, groups outside
+    /// every region are removed.
+    pub fn add_sounding_details(&mut self, first: &OcoGeo, second: &OcoGeo, criteria: &MatchCriteria) -> Result<(), MatchupError> {
+        let surface = &criteria.surface;
+        let regions = &criteria.regions;
+        let first_lookup = first.sounding_lookup();
+        let second_lookup = second.sounding_lookup();
+
+        let indices = |lookup: &HashMap<u64, usize>, sids: &HashSet<u64>| {
+            sids.iter().map(|sid| {
+                lookup.get(sid)
+                    .copied()
+                    .ok_or_else(|| MatchupError::InternalError(format!("Sounding ID {sid} in a match group is not in the loaded lite file data")))
+            }).collect::<Result<Vec<_>, _>>()
+        };
+
+        let details = self.match_sets.par_iter()
+            .map(|(first_sids, second_sids)| {
+                let first_inds = indices(&first_lookup, first_sids)?;
+                let second_inds = indices(&second_lookup, second_sids)?;
+                let first_points = first_inds.iter().map(|&i| first.sounding_point(i)).collect_vec();
+                let second_points = second_inds.iter().map(|&i| second.sounding_point(i)).collect_vec();
+                let geometry = GroupGeometry::from_soundings(&first_points, &second_points, criteria.distance_model);
+                let timing = GroupTiming::from_soundings(&first_points, &second_points, &geometry);
+                let first_modes = modes::mode_mask(first_inds.iter().map(|&i| first.operation_mode[i]));
+                let second_modes = modes::mode_mask(second_inds.iter().map(|&i| second.operation_mode[i]));
+                let mean_land_fraction = |geo: &OcoGeo, inds: &[usize]| {
+                    inds.iter().map(|&i| geo.land_fraction[i]).sum::<f32>() / inds.len() as f32
+                };
+                let first_land_fraction = mean_land_fraction(first, &first_inds);
+                let second_land_fraction = mean_land_fraction(second, &second_inds);
+                let surface_class = surface.classify_group(
+                    first_inds.iter().map(|&i| first.land_fraction[i]).chain(second_inds.iter().map(|&i| second.land_fraction[i]))
+                );
+                let in_regions = regions.regions.iter().map(|r| {
+                    first_points.iter().chain(second_points.iter())
+                        .any(|p| r.contains(p.longitude as f64, p.latitude as f64))
+                }).collect_vec();
+                let orbit_range = |geo: &OcoGeo, inds: &[usize]| {
+                    inds.iter().map(|&i| geo.orbit[i])
+                        .filter(|&o| o != grouping::UNKNOWN_ORBIT)
+                        .minmax()
+                        .into_option()
+                        .map(|(lo, hi)| [lo, hi])
+                        .unwrap_or([grouping::UNKNOWN_ORBIT; 2])
+                };
+                let first_orbits = orbit_range(first, &first_inds);
+                let second_orbits = orbit_range(second, &second_inds);
+                let quality_and_xco2 = |geo: &OcoGeo, inds: &[usize]| {
+                    inds.iter().map(|&i| (geo.quality[i], geo.xco2[i])).collect_vec()
+                };
+                let rejection_reasons = criteria.grouping.rejection_reasons(
+                    &quality_and_xco2(first, &first_inds), &quality_and_xco2(second, &second_inds)
+                );
+                Ok(GroupDetails {
+                    geometry, timing, first_modes, second_modes, first_land_fraction, second_land_fraction, surface_class, in_regions,
+                    first_orbits, second_orbits, rejection_reasons
+                })
+            }).collect::<Result<Vec<_>, MatchupError>>()?;
+
+        let keep = if regions.filter == RegionFilter::Groups && !regions.is_empty() {
+            Some(details.iter().map(|d| d.in_regions.iter().any(|&inside| inside)).collect_vec())
+        } else {
+            None
+        };
+        self.details = Some(details);
+        if let Some(keep) = keep {
+            let n_before = self.match_sets.len();
+            self.retain_groups(&keep);
+            log::debug!("Kept {} of {n_before} groups inside the regions of interest", self.match_sets.len());
+        }
+
+        if criteria.grouping.has_thresholds() {
+            let passed = self.details.as_deref().unwrap_or_default()
+                .iter()
+                .map(|d| d.rejection_reasons == 0)
+                .collect_vec();
+            let n_rejected = passed.iter().filter(|&&p| !p).count();
+            log::info!("{n_rejected} of {} groups failed the group quality thresholds", passed.len());
+            if criteria.grouping.rejected == RejectedGroups::Drop {
+                self.retain_groups(&passed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep only the groups where `keep` is `true`. `keep` must have one element per group.
+    fn retain_groups(&mut self, keep: &[bool]) {
+        retain_by_mask(&mut self.match_sets, keep);
+        retain_by_mask(&mut self.pair_stats, keep);
+        if let Some(details) = self.details.as_mut() {
+            retain_by_mask(details, keep);
+        }
+    }
+
+    /// Write the match groups to `ds`, in the root group if `group_name` is `None`, along with
+    /// global attributes from `provenance` describing how they were made.
+    pub fn to_nc_group(&self, ds: &mut netcdf::MutableFile, group_name: Option<&str>, is_oco3_self_crossing: bool, checksums: &ChecksumCache, provenance: &Provenance) -> Result<(), MatchupError> {
         let (instr_a, instr_b) = Self::instr_names(is_oco3_self_crossing);
 
+        // Compute everything we need to write for each group first, so that each variable can be written in one call
+        log::debug!("Summarizing {} match groups", self.match_sets.len());
+        let summaries = self.match_sets.par_iter()
+            .zip(self.pair_stats.par_iter())
+            .map(|((oco2_sids, oco3_sids), stats)| self.summarize_group(oco2_sids, oco3_sids, stats))
+            .collect::<Result<Vec<_>, _>>()?;
+
         let out_file = utils::nc_file(ds);
+        provenance.write_global_attributes(ds)
+            .map_err(|e| e.set_file(out_file.clone()))?;
         let mut grp = self.setup_nc_group(ds, group_name, is_oco3_self_crossing)?;
 
-        self.write_file_variables(&mut grp, is_oco3_self_crossing)?;
-
-        for (i, (oco2_inds, oco3_inds)) in self.match_sets.iter().enumerate() {
-            log::debug!("Saving match set {i}");
-            // Calculate the first and last sounding ID for each group
-            let oco2_sid_min = *oco2_inds.iter().min().expect("Expected at least one first instrument index in every hash set");
-            let oco2_sid_max = *oco2_inds.iter().max().expect("Expected at least one first instrument index in every hash set");
-            let oco3_sid_min = *oco3_inds.iter().min().expect("Expected at least one second instrument index in every hash set");
-            let oco3_sid_max = *oco3_inds.iter().max().expect("Expected at least one second instrument index in every hash set");
-
-            // Calculate the mean distance and time difference between OCO-2 and -3
-            let group_mean_dist = oco2_inds.iter()
-                .try_fold(RunningMean::new(), |mut acc, k| {
-                    let dx = self.distances.get(k)
-                        .ok_or_else(|| MatchupError::InternalError(format!("First instrument sounding ID {k} not stored in the distance hash map")))?;
-                    acc += *dx;
-                    Ok::<RunningMean<f32>, MatchupError>(acc)
-                })?.mean().unwrap_or(f32::NAN);
-
-            let group_mean_dt = oco2_inds.iter()
-                .try_fold(RunningMean::new(), |mut acc, k| {
-                    let dx = self.time_diffs.get(k)
-                        .ok_or_else(|| MatchupError::InternalError(format!("First instrument sounding ID {k} not stored in the time difference hash map")))?;
-                    acc += *dx;
-                    Ok::<RunningMean<f32>, MatchupError>(acc)
-                })?.mean().unwrap_or(f32::NAN);
-
-            // Get the corresponding file and sounding indices
-            let &(oco2_fid_min, oco2_idx_min) = self.oco2_sounding_indices.get(&oco2_sid_min)
-                .ok_or_else(|| MatchupError::InternalError(format!("First instrument sounding ID {oco2_sid_min} not stored in the index hashmap")))?;
-            let &(oco2_fid_max, oco2_idx_max) = self.oco2_sounding_indices.get(&oco2_sid_max)
-                .ok_or_else(|| MatchupError::InternalError(format!("First instrument sounding ID {oco2_sid_max} not stored in the index hashmap")))?;
-            let &(oco3_fid_min, oco3_idx_min) = self.oco3_sounding_indices.get(&oco3_sid_min)
-                .ok_or_else(|| MatchupError::InternalError(format!("Second instrument sounding ID {oco3_sid_min} not stored in the index hashmap")))?;
-            let &(oco3_fid_max, oco3_idx_max) = self.oco3_sounding_indices.get(&oco3_sid_max)
-                .ok_or_else(|| MatchupError::InternalError(format!("Second instrument sounding ID {oco3_sid_max} not stored in the index hashmap")))?;
-
-            let scalar_extents: Extents = [i].into();
-            let extents: Extents = [i..i+1, 0..2].into();
-
-            // Writing OCO-2 variables
-            log::debug!("(Writing OCO-2 variables)");
-            {
-                grp.variable_mut(&Self::sounding_id_varname(instr_a)).expect("First instrument sounding ID variable must be initialized first")
-                .put_values(&[oco2_sid_min, oco2_sid_max], &extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+        self.write_file_variables(&mut grp, is_oco3_self_crossing, checksums)?;
+
+        if let Some(details) = self.details.as_deref() {
+            if !provenance.criteria.regions.is_empty() {
+                log::debug!("(Writing region variables)");
+                Self::write_region_variables(&mut grp, details, &provenance.criteria.regions, &out_file)?;
             }
-            
-            {
-                grp.variable_mut(&Self::sounding_index_varname(instr_a)).expect("First instrument sounding index variable must be initialized first")
-                .put_values(&[oco2_idx_min, oco2_idx_max], &extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+            log::debug!("(Writing geometry variables)");
+            Self::write_geometry_variables(&mut grp, instr_a, instr_b, details, &out_file)?;
+            log::debug!("(Writing time variables)");
+            Self::write_time_variables(&mut grp, instr_a, instr_b, details, &out_file)?;
+            log::debug!("(Writing operation mode variables)");
+            Self::write_mode_variables(&mut grp, instr_a, instr_b, details, &out_file)?;
+            log::debug!("(Writing surface variables)");
+            Self::write_surface_variables(&mut grp, instr_a, instr_b, details, &out_file)?;
+            log::debug!("(Writing orbit variables)");
+            Self::write_orbit_variables(&mut grp, instr_a, instr_b, details, &out_file)?;
+            let grouping = &provenance.criteria.grouping;
+            if grouping.has_thresholds() && grouping.rejected == RejectedGroups::Flag {
+                log::debug!("(Writing rejection reasons)");
+                Self::write_rejection_variable(&mut grp, details, &out_file)?;
             }
+        }
 
-            {
-                grp.variable_mut(&Self::file_index_varname(instr_a)).expect("First instrument file index variable must be initialized first")
-                .put_values(&[oco2_fid_min, oco2_fid_max], &extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+        if summaries.is_empty() {
+            // Nothing to write, and netCDF does not always handle writing 0-length arrays well
+            return Ok(());
+        }
+
+        log::debug!("(Writing first instrument variables)");
+        let flat_values = |get: fn(&GroupSummary) -> [u64; 2]| summaries.iter().flat_map(get).collect_vec();
+        Self::put_group_values(&mut grp, &Self::sounding_id_varname(instr_a), &flat_values(|s| s.oco2_sounding_ids), &out_file)?;
+        Self::put_group_values(&mut grp, &Self::sounding_index_varname(instr_a), &flat_values(|s| s.oco2_sounding_indices), &out_file)?;
+        Self::put_group_values(&mut grp, &Self::file_index_varname(instr_a), &flat_values(|s| s.oco2_file_indices), &out_file)?;
+
+        log::debug!("(Writing second instrument variables)");
+        Self::put_group_values(&mut grp, &Self::sounding_id_varname(instr_b), &flat_values(|s| s.oco3_sounding_ids), &out_file)?;
+        Self::put_group_values(&mut grp, &Self::sounding_index_varname(instr_b), &flat_values(|s| s.oco3_sounding_indices), &out_file)?;
+        Self::put_group_values(&mut grp, &Self::file_index_varname(instr_b), &flat_values(|s| s.oco3_file_indices), &out_file)?;
+
+        log::debug!("(Writing distance & time variables)");
+        let mean_dists = summaries.iter().map(|s| s.mean_distance).collect_vec();
+        Self::put_group_values(&mut grp, Self::distance_varname(), &mean_dists, &out_file)?;
+        let mean_dts = summaries.iter().map(|s| s.mean_time_diff).collect_vec();
+        Self::put_group_values(&mut grp, Self::time_diff_varname(), &mean_dts, &out_file)?;
+        let mean_scores = summaries.iter().map(|s| s.mean_score).collect_vec();
+        Self::put_group_values(&mut grp, Self::score_varname(), &mean_scores, &out_file)?;
+
+        Ok(())
+    }
+
+    /// Calculate the values written to the output file for one group of matched soundings.
+    fn summarize_group(&self, oco2_sids: &HashSet<u64>, oco3_sids: &HashSet<u64>, stats: &GroupPairStats) -> Result<GroupSummary, MatchupError> {
+        // Calculate the first and last sounding ID for each group
+        let (oco2_sid_min, oco2_sid_max) = oco2_sids.iter().copied().minmax().into_option()
+            .ok_or_else(|| MatchupError::InternalError("Expected at least one first instrument sounding ID in every match group".to_owned()))?;
+        let (oco3_sid_min, oco3_sid_max) = oco3_sids.iter().copied().minmax().into_option()
+            .ok_or_else(|| MatchupError::InternalError("Expected at least one second instrument sounding ID in every match group".to_owned()))?;
+
+        // The mean distance, time difference, and score between OCO-2 and -3
+        let mean_distance = stats.distance.mean().unwrap_or(f32::NAN);
+        let mean_time_diff = stats.time_diff.mean().unwrap_or(f32::NAN);
+        let mean_score = stats.score.mean().unwrap_or(f32::NAN);
+
+        // Get the corresponding file and sounding indices
+        let lookup = |indices: &HashMap<u64, (u8, u64)>, sid: u64, instr: &str| {
+            indices.get(&sid)
+                .map(|&(fid, idx)| (fid as u64, idx))
+                .ok_or_else(|| MatchupError::InternalError(format!("{instr} instrument sounding ID {sid} not stored in the index hashmap")))
+        };
+        let (oco2_fid_min, oco2_idx_min) = lookup(&self.oco2_sounding_indices, oco2_sid_min, "First")?;
+        let (oco2_fid_max, oco2_idx_max) = lookup(&self.oco2_sounding_indices, oco2_sid_max, "First")?;
+        let (oco3_fid_min, oco3_idx_min) = lookup(&self.oco3_sounding_indices, oco3_sid_min, "Second")?;
+        let (oco3_fid_max, oco3_idx_max) = lookup(&self.oco3_sounding_indices, oco3_sid_max, "Second")?;
+
+        Ok(GroupSummary {
+            oco2_sounding_ids: [oco2_sid_min, oco2_sid_max],
+            oco2_sounding_indices: [oco2_idx_min, oco2_idx_max],
+            oco2_file_indices: [oco2_fid_min, oco2_fid_max],
+            oco3_sounding_ids: [oco3_sid_min, oco3_sid_max],
+            oco3_sounding_indices: [oco3_idx_min, oco3_idx_max],
+            oco3_file_indices: [oco3_fid_min, oco3_fid_max],
+            mean_distance,
+            mean_time_diff,
+            mean_score,
+        })
+    }
+
+    /// Define and write the variables describing each group's geometry. Unlike the other per-group variables,
+    /// these are only written if the geometry was computed, so they are not defined in `setup_nc_group`.
+    fn write_geometry_variables(grp: &mut netcdf::GroupMut, instr_a: &str, instr_b: &str, details: &[GroupDetails], out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let geometry = details.iter().map(|d| &d.geometry).collect_vec();
+        let group_chunk = geometry.len().clamp(1, GROUP_CHUNK_SIZE);
+        let name_a = Self::instr_long_name(instr_a);
+        let name_b = Self::instr_long_name(instr_b);
+
+        type GetGeom = fn(&GroupGeometry) -> f32;
+        let var_info: [(String, &str, Option<&str>, String, GetGeom); 11] = [
+            (Self::crossing_lon_varname().to_owned(), "degrees_east", Some("longitude"), "crossing point longitude".to_owned(), |g| g.crossing_longitude),
+            (Self::crossing_lat_varname().to_owned(), "degrees_north", Some("latitude"), "crossing point latitude".to_owned(), |g| g.crossing_latitude),
+            (format!("oco{instr_a}_track_heading"), "degree", None, format!("{name_a} track heading (clockwise from north)"), |g| g.first_heading),
+            (format!("oco{instr_b}_track_heading"), "degree", None, format!("{name_b} track heading (clockwise from north)"), |g| g.second_heading),
+            ("crossing_angle".to_owned(), "degree", None, "angle between the track headings".to_owned(), |g| g.crossing_angle),
+            ("group_min_longitude".to_owned(), "degrees_east", None, "westernmost sounding longitude".to_owned(), |g| g.min_longitude),
+            ("group_max_longitude".to_owned(), "degrees_east", None, "easternmost sounding longitude".to_owned(), |g| g.max_longitude),
+            ("group_min_latitude".to_owned(), "degrees_north", None, "southernmost sounding latitude".to_owned(), |g| g.min_latitude),
+            ("group_max_latitude".to_owned(), "degrees_north", None, "northernmost sounding latitude".to_owned(), |g| g.max_latitude),
+            (format!("oco{instr_a}_track_length"), "km", None, format!("{name_a} along-track length"), |g| g.first_track_length),
+            (format!("oco{instr_b}_track_length"), "km", None, format!("{name_b} along-track length"), |g| g.second_track_length),
+        ];
+
+        for (varname, units, standard_name, long_name, get) in var_info {
+            let mut var = grp.add_variable::<f32>(&varname, &[Self::match_group_dim()]).map_err(nc_err)?;
+            var.set_fill_value(f32::NAN).map_err(nc_err)?;
+            var.chunking(&[group_chunk]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("units", units).map_err(nc_err)?;
+            if let Some(standard_name) = standard_name {
+                var.add_attribute("standard_name", standard_name).map_err(nc_err)?;
+            }
+            var.add_attribute("long_name", long_name).map_err(nc_err)?;
+            if standard_name.is_none() {
+                var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
             }
 
-            // Writing OCO-3 variables
-            log::debug!("(Writing OCO-3 variables)");
-            {
-                grp.variable_mut(&Self::sounding_id_varname(instr_b)).expect("Second instrument sounding ID variable must be initialized first")
-                .put_values(&[oco3_sid_min, oco3_sid_max], &extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+            if !geometry.is_empty() {
+                let values = geometry.iter().map(|g| get(g)).collect_vec();
+                var.put_values(&values, Extents::All).map_err(nc_err)?;
             }
-            
-            {
-                grp.variable_mut(&Self::sounding_index_varname(instr_b)).expect("Second instrument sounding index variable must be initialized first")
-                .put_values(&[oco3_idx_min, oco3_idx_max], &extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+        }
+
+        // The convex hulls have a different number of vertices for each group, so are stored as a contiguous ragged array
+        let hull_sizes = geometry.iter().map(|g| g.hull.len() as u32).collect_vec();
+        let hull_lons = geometry.iter().flat_map(|g| g.hull.iter().map(|&(lon, _)| lon)).collect_vec();
+        let hull_lats = geometry.iter().flat_map(|g| g.hull.iter().map(|&(_, lat)| lat)).collect_vec();
+        grp.add_dimension(Self::hull_vertex_dim(), hull_lons.len()).map_err(nc_err)?;
+
+        let mut var = grp.add_variable::<u32>("hull_size", &[Self::match_group_dim()]).map_err(nc_err)?;
+        var.chunking(&[group_chunk]).map_err(nc_err)?;
+        var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+        var.add_attribute("long_name", "number of convex hull vertices").map_err(nc_err)?;
+        var.add_attribute("sample_dimension", Self::hull_vertex_dim()).map_err(nc_err)?;
+        var.add_attribute("description", "Number of vertices in the hull_longitude and hull_latitude variables that make up the convex hull of each group's soundings").map_err(nc_err)?;
+        if !hull_sizes.is_empty() {
+            var.put_values(&hull_sizes, Extents::All).map_err(nc_err)?;
+        }
+
+        let vertex_chunk = hull_lons.len().clamp(1, GROUP_CHUNK_SIZE);
+        for (varname, units, standard_name, values) in [("hull_longitude", "degrees_east", "longitude", &hull_lons), ("hull_latitude", "degrees_north", "latitude", &hull_lats)] {
+            let mut var = grp.add_variable::<f32>(varname, &[Self::hull_vertex_dim()]).map_err(nc_err)?;
+            var.chunking(&[vertex_chunk]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("units", units).map_err(nc_err)?;
+            var.add_attribute("standard_name", standard_name).map_err(nc_err)?;
+            var.add_attribute("long_name", format!("group convex hull vertex {standard_name}")).map_err(nc_err)?;
+            if !values.is_empty() {
+                var.put_values(values, Extents::All).map_err(nc_err)?;
             }
+        }
 
-            {
-                grp.variable_mut(&Self::file_index_varname(instr_b)).expect("Second instrument file index variable must be initialized first")
-                .put_values(&[oco3_fid_min, oco3_fid_max], &extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+        // Now that there are coordinates for the groups, attach them to the other per-group variables
+        utils::add_string_attributes(grp, Self::distance_varname(), &[("coordinates", Self::group_coordinates())])?;
+        utils::add_string_attributes(grp, Self::time_diff_varname(), &[("coordinates", Self::group_coordinates())])?;
+        utils::add_string_attributes(grp, Self::score_varname(), &[("coordinates", Self::group_coordinates())])?;
+        Ok(())
+    }
+
+    /// Define and write the per-group time variables. Like the geometry variables, these are only written if
+    /// the sounding details were computed.
+    fn write_time_variables(grp: &mut netcdf::GroupMut, instr_a: &str, instr_b: &str, details: &[GroupDetails], out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let group_chunk = details.len().clamp(1, GROUP_CHUNK_SIZE);
+        let coordinates = Self::group_coordinates();
+
+        type GetStartEnd = fn(&GroupTiming) -> [f64; 2];
+        let start_end_info: [(String, String, GetStartEnd); 2] = [
+            (format!("oco{instr_a}_start_end_time"), format!("{} group start and end time", Self::instr_long_name(instr_a)), |t| t.first_start_end),
+            (format!("oco{instr_b}_start_end_time"), format!("{} group start and end time", Self::instr_long_name(instr_b)), |t| t.second_start_end),
+        ];
+        for (varname, long_name, get) in start_end_info {
+            let mut var = grp.add_variable::<f64>(&varname, &[Self::match_group_dim(), Self::start_end_dim()]).map_err(nc_err)?;
+            var.set_fill_value(f64::NAN).map_err(nc_err)?;
+            var.chunking(&[group_chunk, 2]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("units", TIME_UNITS).map_err(nc_err)?;
+            var.add_attribute("calendar", "standard").map_err(nc_err)?;
+            var.add_attribute("standard_name", "time").map_err(nc_err)?;
+            var.add_attribute("long_name", long_name).map_err(nc_err)?;
+            var.add_attribute("coordinates", coordinates).map_err(nc_err)?;
+            if !details.is_empty() {
+                let values = details.iter().flat_map(|d| get(&d.timing)).collect_vec();
+                var.put_values(&values, Extents::All).map_err(nc_err)?;
             }
+        }
 
-            // Writing distance and time variables
-            log::debug!("(Writing distance & time variables)");
-            {
-                grp.variable_mut(Self::distance_varname()).expect("Distance variable must be initialized first")
-                .put_values(&[group_mean_dist], &scalar_extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+        let mut var = grp.add_variable::<f64>("crossing_time", &[Self::match_group_dim()]).map_err(nc_err)?;
+        var.set_fill_value(f64::NAN).map_err(nc_err)?;
+        var.chunking(&[group_chunk]).map_err(nc_err)?;
+        var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+        var.add_attribute("units", TIME_UNITS).map_err(nc_err)?;
+        var.add_attribute("calendar", "standard").map_err(nc_err)?;
+        var.add_attribute("standard_name", "time").map_err(nc_err)?;
+        var.add_attribute("long_name", "midpoint of the two instruments' mean sounding times").map_err(nc_err)?;
+        var.add_attribute("description", "Halfway between the mean time of each instrument's soundings in the group, so it does not depend on how many soundings each instrument has. When the instruments observed the crossing at different times, neither may have been observing at this time; see the per-instrument start/end times.").map_err(nc_err)?;
+        var.add_attribute("coordinates", coordinates).map_err(nc_err)?;
+        if !details.is_empty() {
+            let values = details.iter().map(|d| d.timing.crossing_time).collect_vec();
+            var.put_values(&values, Extents::All).map_err(nc_err)?;
+        }
+
+        type GetLst = fn(&GroupTiming) -> f32;
+        let lst_info: [(String, String, GetLst); 3] = [
+            ("crossing_local_solar_time".to_owned(), "local solar time at the crossing point at the crossing time".to_owned(), |t| t.crossing_local_solar_time),
+            (format!("oco{instr_a}_local_solar_time"), format!("local solar time at the crossing point at the mean {} sounding time", Self::instr_long_name(instr_a)), |t| t.first_local_solar_time),
+            (format!("oco{instr_b}_local_solar_time"), format!("local solar time at the crossing point at the mean {} sounding time", Self::instr_long_name(instr_b)), |t| t.second_local_solar_time),
+        ];
+        for (varname, long_name, get) in lst_info {
+            let mut var = grp.add_variable::<f32>(&varname, &[Self::match_group_dim()]).map_err(nc_err)?;
+            var.set_fill_value(f32::NAN).map_err(nc_err)?;
+            var.chunking(&[group_chunk]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("units", "hours").map_err(nc_err)?;
+            var.add_attribute("long_name", long_name).map_err(nc_err)?;
+            var.add_attribute("coordinates", coordinates).map_err(nc_err)?;
+            var.add_attribute("description", "Apparent local solar time (0 to 24 hours), including the equation of time. Calculated at the center of the group's bounding box if the crossing point is not defined.").map_err(nc_err)?;
+            if !details.is_empty() {
+                let values = details.iter().map(|d| get(&d.timing)).collect_vec();
+                var.put_values(&values, Extents::All).map_err(nc_err)?;
             }
+        }
 
-            {
-                grp.variable_mut(Self::time_diff_varname()).expect("Time difference variable must be initialized first")
-                .put_values(&[group_mean_dt], &scalar_extents)
-                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+        Ok(())
+    }
+
+    /// Define and write the per-group operation mode masks. Like the geometry variables, these are only written
+    /// if the sounding details were computed.
+    fn write_mode_variables(grp: &mut netcdf::GroupMut, instr_a: &str, instr_b: &str, details: &[GroupDetails], out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let group_chunk = details.len().clamp(1, GROUP_CHUNK_SIZE);
+        let flag_masks = OperationMode::ALL.iter().map(|m| m.mask_bit()).collect_vec();
+
+        type GetModes = fn(&GroupDetails) -> u8;
+        let var_info: [(String, &str, GetModes); 2] = [
+            (format!("oco{instr_a}_operation_modes"), Self::instr_long_name(instr_a), |d| d.first_modes),
+            (format!("oco{instr_b}_operation_modes"), Self::instr_long_name(instr_b), |d| d.second_modes),
+        ];
+        for (varname, instr_name, get) in var_info {
+            let mut var = grp.add_variable::<u8>(&varname, &[Self::match_group_dim()]).map_err(nc_err)?;
+            var.chunking(&[group_chunk]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("long_name", format!("{instr_name} operation modes in group")).map_err(nc_err)?;
+            var.add_attribute("flag_masks", flag_masks.clone()).map_err(nc_err)?;
+            var.add_attribute("flag_meanings", OperationMode::flag_meanings()).map_err(nc_err)?;
+            var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
+            var.add_attribute("description", "Bit mask of the operation modes of this instrument's soundings in the group; a group with soundings in more than one mode has more than one bit set. 0 means the modes are unknown.").map_err(nc_err)?;
+            if !details.is_empty() {
+                let values = details.iter().map(get).collect_vec();
+                var.put_values(&values, Extents::All).map_err(nc_err)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Define and write the per-group land fractions and surface classes. Like the geometry variables, these are
+    /// only written if the sounding details were computed.
+    fn write_surface_variables(grp: &mut netcdf::GroupMut, instr_a: &str, instr_b: &str, details: &[GroupDetails], out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let group_chunk = details.len().clamp(1, GROUP_CHUNK_SIZE);
+
+        type GetLandFrac = fn(&GroupDetails) -> f32;
+        let frac_info: [(String, &str, GetLandFrac); 2] = [
+            (format!("oco{instr_a}_land_fraction"), Self::instr_long_name(instr_a), |d| d.first_land_fraction),
+            (format!("oco{instr_b}_land_fraction"), Self::instr_long_name(instr_b), |d| d.second_land_fraction),
+        ];
+        for (varname, instr_name, get) in frac_info {
+            let mut var = grp.add_variable::<f32>(&varname, &[Self::match_group_dim()]).map_err(nc_err)?;
+            var.set_fill_value(f32::NAN).map_err(nc_err)?;
+            var.chunking(&[group_chunk]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("units", "percent").map_err(nc_err)?;
+            var.add_attribute("long_name", format!("{instr_name} mean land fraction in group")).map_err(nc_err)?;
+            var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
+            if !details.is_empty() {
+                let values = details.iter().map(get).collect_vec();
+                var.put_values(&values, Extents::All).map_err(nc_err)?;
+            }
+        }
+
+        let mut var = grp.add_variable::<u8>("surface_class", &[Self::match_group_dim()]).map_err(nc_err)?;
+        var.set_fill_value(surface::UNKNOWN_SURFACE_CODE).map_err(nc_err)?;
+        var.chunking(&[group_chunk]).map_err(nc_err)?;
+        var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+        var.add_attribute("long_name", "group surface class").map_err(nc_err)?;
+        var.add_attribute("flag_values", SurfaceClass::ALL.iter().map(|c| c.code()).collect_vec()).map_err(nc_err)?;
+        var.add_attribute("flag_meanings", SurfaceClass::ALL.iter().map(|c| c.name()).join(" ")).map_err(nc_err)?;
+        var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
+        var.add_attribute("description", "Land or ocean if all of the group's soundings from both instruments are, mixed otherwise. See the land_threshold_percent and ocean_threshold_percent global attributes for how soundings are classified.").map_err(nc_err)?;
+        if !details.is_empty() {
+            let values = details.iter()
+                .map(|d| d.surface_class.map(|c| c.code()).unwrap_or(surface::UNKNOWN_SURFACE_CODE))
+                .collect_vec();
+            var.put_values(&values, Extents::All).map_err(nc_err)?;
+        }
+
+        Ok(())
+    }
+
+    /// Define and write the range of orbits of each instrument's soundings in each group. Like the other
+    /// per-group detail variables, these are only written if the sounding details were computed.
+    fn write_orbit_variables(grp: &mut netcdf::GroupMut, instr_a: &str, instr_b: &str, details: &[GroupDetails], out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let group_chunk = details.len().clamp(1, GROUP_CHUNK_SIZE);
+
+        type GetOrbits = fn(&GroupDetails) -> [u32; 2];
+        let orbit_info: [(String, &str, GetOrbits); 2] = [
+            (Self::orbit_varname(instr_a), Self::instr_long_name(instr_a), |d| d.first_orbits),
+            (Self::orbit_varname(instr_b), Self::instr_long_name(instr_b), |d| d.second_orbits),
+        ];
+        for (varname, instr_name, get) in orbit_info {
+            let mut var = grp.add_variable::<u32>(&varname, &[Self::match_group_dim(), Self::start_end_dim()]).map_err(nc_err)?;
+            var.set_fill_value(grouping::UNKNOWN_ORBIT).map_err(nc_err)?;
+            var.chunking(&[group_chunk, 2]).map_err(nc_err)?;
+            var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+            var.add_attribute("long_name", format!("{instr_name} first and last orbit in group")).map_err(nc_err)?;
+            var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
+            var.add_attribute("description", "Lowest and highest orbit numbers of the instrument's soundings in the group; these are the same unless the group spans several orbits. Fill values mean the lite files did not give the orbit.").map_err(nc_err)?;
+            if !details.is_empty() {
+                let values = details.iter().flat_map(get).collect_vec();
+                var.put_values(&values, Extents::All).map_err(nc_err)?;
             }
         }
 
+        Ok(())
+    }
+
+    /// Define and write why each group failed the group quality thresholds, with 0 for groups that passed.
+    /// This is only written if there are thresholds and rejected groups are flagged rather than dropped.
+    fn write_rejection_variable(grp: &mut netcdf::GroupMut, details: &[GroupDetails], out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let group_chunk = details.len().clamp(1, GROUP_CHUNK_SIZE);
+        let (flag_masks, flag_meanings) = GroupingRules::rejection_flags();
+
+        let mut var = grp.add_variable::<u8>(Self::rejection_varname(), &[Self::match_group_dim()]).map_err(nc_err)?;
+        var.chunking(&[group_chunk]).map_err(nc_err)?;
+        var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+        var.add_attribute("long_name", "reasons the group failed the group quality thresholds").map_err(nc_err)?;
+        var.add_attribute("flag_masks", flag_masks).map_err(nc_err)?;
+        var.add_attribute("flag_meanings", flag_meanings).map_err(nc_err)?;
+        var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
+        var.add_attribute("description", "Bit mask of the thresholds the group failed: too few soundings from either instrument, too small a fraction of either instrument's soundings with quality flag 0, or too large a standard deviation of either instrument's XCO2. 0 means the group passed.").map_err(nc_err)?;
+        if !details.is_empty() {
+            let values = details.iter().map(|d| d.rejection_reasons).collect_vec();
+            var.put_values(&values, Extents::All).map_err(nc_err)?;
+        }
+
+        Ok(())
+    }
+
+    /// Define and write which regions of interest each group is in. This is only written if there are regions
+    /// and the sounding details were computed.
+    fn write_region_variables(grp: &mut netcdf::GroupMut, details: &[GroupDetails], regions: &RegionSet, out_file: &Path) -> Result<(), MatchupError> {
+        let nc_err = |e| MatchupError::from_nc_error(e, out_file.to_owned());
+        let group_chunk = details.len().clamp(1, GROUP_CHUNK_SIZE);
+        let n_regions = regions.regions.len();
+
+        let names = regions.regions.iter().map(|r| r.name.as_str()).collect_vec();
+        utils::write_string_nc_var(grp, &names, "region_name", Self::region_dim(), None, Some("Names of the regions of interest given when matching"))?;
+        utils::add_string_attributes(grp, "region_name", &[("long_name", "region of interest name")])?;
+
+        let mut var = grp.add_variable::<u8>("in_region", &[Self::match_group_dim(), Self::region_dim()]).map_err(nc_err)?;
+        var.chunking(&[group_chunk, n_regions]).map_err(nc_err)?;
+        var.compression(GROUP_DEFLATE_LEVEL, true).map_err(nc_err)?;
+        var.add_attribute("long_name", "group is in region of interest").map_err(nc_err)?;
+        var.add_attribute("flag_values", vec![0u8, 1u8]).map_err(nc_err)?;
+        var.add_attribute("flag_meanings", "outside inside").map_err(nc_err)?;
+        var.add_attribute("coordinates", Self::group_coordinates()).map_err(nc_err)?;
+        var.add_attribute("description", "1 if any of the group's soundings from either instrument are inside the region named by region_name, 0 otherwise").map_err(nc_err)?;
+        if !details.is_empty() {
+            let values = details.iter().flat_map(|d| d.in_regions.iter().map(|&inside| inside as u8)).collect_vec();
+            var.put_values(&values, Extents::All).map_err(nc_err)?;
+        }
 
         Ok(())
     }
 
+    /// Write all the values of one of the per-group variables created by `setup_nc_group`. 2D
+    /// variables (with a start/end dimension) must be given flattened in row-major order.
+    fn put_group_values<T: netcdf::NcPutGet>(grp: &mut netcdf::GroupMut, varname: &str, values: &[T], out_file: &Path) -> Result<(), MatchupError> {
+        grp.variable_mut(varname)
+            .unwrap_or_else(|| panic!("Variable {varname} must be initialized first"))
+            .put_values(values, Extents::All)
+            .map_err(|e| MatchupError::from_nc_error(e, out_file.to_owned()))?;
+        Ok(())
+    }
+
     fn instr_names(is_oco3_self_crossing: bool) -> (&'static str, &'static str) {
         let instr_a = if is_oco3_self_crossing { "3a" } else { "2" };
         let instr_b = if is_oco3_self_crossing { "3b" } else { "3" };
         (instr_a, instr_b)
     }
 
+    fn instr_long_name(instrument: &str) -> &'static str {
+        match instrument {
+            "2" => "OCO-2",
+            "3" => "OCO-3",
+            "3a" => "OCO-3 (first pass)",
+            "3b" => "OCO-3 (second pass)",
+            _ => "unknown instrument",
+        }
+    }
+
+    fn region_dim() -> &'static str {
+        "region"
+    }
+
+    fn hull_vertex_dim() -> &'static str {
+        "hull_vertex"
+    }
+
+    fn crossing_lon_varname() -> &'static str {
+        "crossing_longitude"
+    }
+
+    fn crossing_lat_varname() -> &'static str {
+        "crossing_latitude"
+    }
+
+    /// CF coordinates attribute for per-group variables, when the geometry is written
+    fn group_coordinates() -> &'static str {
+        "crossing_latitude crossing_longitude"
+    }
+
     fn match_group_dim() -> &'static str {
         "match_group"
     }
@@ -553,6 +1566,14 @@ impl OcoMatchGroups {
         format!("oco{instrument}_file_index")
     }
 
+    fn rejection_varname() -> &'static str {
+        "rejection_reason"
+    }
+
+    fn orbit_varname(instrument: &str) -> String {
+        format!("oco{instrument}_orbit")
+    }
+
     fn distance_varname() -> &'static str {
         "mean_inter_orbit_distance"
     }
@@ -561,6 +1582,10 @@ impl OcoMatchGroups {
         "mean_inter_orbit_time_difference"
     }
 
+    fn score_varname() -> &'static str {
+        "mean_match_score"
+    }
+
     fn setup_nc_group<'f>(&'f self, ds: &'f mut netcdf::MutableFile, group_name: Option<&str>, is_oco3_self_crossing: bool) -> Result<netcdf::GroupMut, MatchupError> {
         let (instr_a, instr_b) = Self::instr_names(is_oco3_self_crossing);
         log::debug!("Setting up netCDF group for {instr_a} and {instr_b}");
@@ -585,20 +1610,25 @@ impl OcoMatchGroups {
 
         let dims1 = vec![Self::match_group_dim()];
         let dims2 = vec![Self::match_group_dim(), Self::start_end_dim()];
+        // Chunk sizes must be at least 1, even if there are no groups
+        let group_chunk = n_groups.clamp(1, GROUP_CHUNK_SIZE);
 
         // TODO: update variable descriptions to instruct 2/3 or 3a/3b
+        let name_a = Self::instr_long_name(instr_a);
+        let name_b = Self::instr_long_name(instr_b);
         let var_info = [
-            (Self::sounding_id_varname(instr_a), &dims2, false, Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO2)),
-            (Self::file_index_varname(instr_a), &dims2, false, None, Some("0-based index for the OCO-2 lite file name variable")),
-            (Self::sounding_index_varname(instr_a), &dims2, false, None, Some("0-based index for the sounding in the OCO-2 lite file")),
-            (Self::sounding_id_varname(instr_b), &dims2, false, Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO3)),
-            (Self::file_index_varname(instr_b), &dims2, false, None, Some("0-based index for the OCO-3 lite file name variable")),
-            (Self::sounding_index_varname(instr_b), &dims2, false, None, Some("0-based index for the sounding in the OCO-3 lite file")),
-            (Self::distance_varname().to_owned(), &dims1, true, Some("km"), Some("Mean distance between the matched OCO-2 and -3 soundings. Note that this is only calculated for soundings meeting the coincidence criteria, which may not be all soundings in the group.")),
-            (Self::time_diff_varname().to_owned(), &dims1, true, Some("s"), Some("Mean time difference (in seconds) between the matched OCO-2 and -3 soundings. Note that this is only calculated for soundings meeting the coincidence criteria, which may not be all soundings in the group.")),
+            (Self::sounding_id_varname(instr_a), &dims2, false, Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO2), format!("{name_a} group start and end sounding ID"), Some(SOUNDING_ID_COMMENT)),
+            (Self::file_index_varname(instr_a), &dims2, false, None, Some("0-based index for the OCO-2 lite file name variable"), format!("{name_a} group start and end lite file index"), None),
+            (Self::sounding_index_varname(instr_a), &dims2, false, None, Some("0-based index for the sounding in the OCO-2 lite file"), format!("{name_a} group start and end sounding index"), None),
+            (Self::sounding_id_varname(instr_b), &dims2, false, Some(SOUNDING_ID_UNITS), Some(SOUNDING_ID_DESCR_OCO3), format!("{name_b} group start and end sounding ID"), Some(SOUNDING_ID_COMMENT)),
+            (Self::file_index_varname(instr_b), &dims2, false, None, Some("0-based index for the OCO-3 lite file name variable"), format!("{name_b} group start and end lite file index"), None),
+            (Self::sounding_index_varname(instr_b), &dims2, false, None, Some("0-based index for the sounding in the OCO-3 lite file"), format!("{name_b} group start and end sounding index"), None),
+            (Self::distance_varname().to_owned(), &dims1, true, Some("km"), Some("Mean distance between the matched OCO-2 and -3 soundings. Note that this is only calculated for soundings meeting the coincidence criteria, which may not be all soundings in the group."), "mean distance between matched soundings".to_owned(), None),
+            (Self::time_diff_varname().to_owned(), &dims1, true, Some("s"), Some("Mean time difference (in seconds) between the matched OCO-2 and -3 soundings. Note that this is only calculated for soundings meeting the coincidence criteria, which may not be all soundings in the group."), "mean time difference between matched soundings".to_owned(), None),
+            (Self::score_varname().to_owned(), &dims1, true, Some("1"), Some("Mean space-time score of the matched OCO-2 and -3 sounding pairs; lower is closer. The formula is given by the match_score_formula global attribute. Like the mean distance, this only includes pairs meeting the coincidence criteria."), "mean space-time score of matched soundings".to_owned(), None),
         ];
 
-        for (varname, dims, is_float, units, descr) in var_info {
+        for (varname, dims, is_float, units, descr, long_name, comment) in var_info {
             log::debug!("Adding variable {varname}");
             let mut var = if !is_float {
                 grp.add_variable::<u64>(&varname, dims)
@@ -608,11 +1638,31 @@ impl OcoMatchGroups {
                 .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?
             };
 
+            if !is_float {
+                var.set_fill_value(u64::MAX)
+            } else {
+                var.set_fill_value(f32::NAN)
+            }.map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+
+            let chunks = if dims.len() == 1 { vec![group_chunk] } else { vec![group_chunk, 2] };
+            var.chunking(&chunks)
+                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+            var.compression(GROUP_DEFLATE_LEVEL, true)
+                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+
             if let Some(units) = units {
                 var.add_attribute("units", units)
                     .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
             }
 
+            var.add_attribute("long_name", long_name)
+                .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+
+            if let Some(comment) = comment {
+                var.add_attribute("comment", comment)
+                    .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
+            }
+
             if let Some(descr) = descr {
                 var.add_attribute("description", descr)
                     .map_err(|e| MatchupError::from_nc_error(e, out_file.clone()))?;
@@ -623,118 +1673,185 @@ impl OcoMatchGroups {
         Ok(grp)
     }
 
-    fn write_file_variables(&self, grp: &mut netcdf::GroupMut, is_oco3_self_crossing: bool) -> Result<(), MatchupError> { 
+    fn write_file_variables(&self, grp: &mut netcdf::GroupMut, is_oco3_self_crossing: bool, checksums: &ChecksumCache) -> Result<(), MatchupError> { 
         log::debug!("Writing file variables");
         let (instr_a, instr_b) = Self::instr_names(is_oco3_self_crossing);
 
         let oco2_lite_files = self.oco2_lite_files.iter().map(|p| p.display().to_string()).collect_vec();
-        let oco2_file_sha256 = self.oco2_lite_files.iter().map(|p| utils::file_sha256(p)).collect::<Result<Vec<String>,_>>()?;
         let oco3_lite_files = self.oco3_lite_files.iter().map(|p| p.display().to_string()).collect_vec();
-        let oco3_file_sha256 = self.oco3_lite_files.iter().map(|p| utils::file_sha256(p)).collect::<Result<Vec<String>,_>>()?;
-
         utils::write_string_nc_var(grp, &oco2_lite_files, &Self::lite_file_varname(instr_a), "oco2_lite_file", None, Some("Paths to OCO-2 lite files"))?;
-        utils::write_string_nc_var(grp, &oco2_file_sha256, &Self::lite_file_sha256_varname(instr_a), "oco2_lite_file", None, Some("SHA-256 checksums of OCO-2 lite files"))?;
         utils::write_string_nc_var(grp, &oco3_lite_files, &Self::lite_file_varname(instr_b), "oco3_lite_file", None, Some("Paths to OCO-3 lite files"))?;
-        utils::write_string_nc_var(grp, &oco3_file_sha256, &Self::lite_file_sha256_varname(instr_b), "oco3_lite_file", None, Some("SHA-256 checksums of OCO-3 lite files"))?;
+
+        utils::add_string_attributes(grp, &Self::lite_file_varname(instr_a), &[("long_name", &format!("{} lite file path", Self::instr_long_name(instr_a)))])?;
+        utils::add_string_attributes(grp, &Self::lite_file_varname(instr_b), &[("long_name", &format!("{} lite file path", Self::instr_long_name(instr_b)))])?;
+
+        if let Some(oco2_file_sha256) = checksums.checksums(&self.oco2_lite_files)? {
+            utils::write_string_nc_var(grp, &oco2_file_sha256, &Self::lite_file_sha256_varname(instr_a), "oco2_lite_file", None, Some("SHA-256 checksums of OCO-2 lite files"))?;
+        }
+        if let Some(oco3_file_sha256) = checksums.checksums(&self.oco3_lite_files)? {
+            utils::write_string_nc_var(grp, &oco3_file_sha256, &Self::lite_file_sha256_varname(instr_b), "oco3_lite_file", None, Some("SHA-256 checksums of OCO-3 lite files"))?;
+        }
 
         log::debug!("File variables written.");
         Ok(())
     }
 }
 
-pub fn match_oco3_to_oco2_parallel(oco2: &OcoGeo, oco3: &OcoGeo, max_dist: f32, min_dt: f64, max_dt: f64, show_progress: ShowProgress) -> OcoMatches {
-    let n_oco2 = oco2.longitude.len();
-    let oco2_inds = Array1::from_iter(0..n_oco2);
+/// The criteria that a pair of soundings must meet to be considered a match.
+#[derive(Debug, Clone)]
+pub struct MatchCriteria {
+    /// How close together the soundings must be
+    pub distance: DistanceCriterion,
+    /// How to calculate distances between soundings, both for matching and for group geometry
+    pub distance_model: DistanceModel,
+    /// Minimum absolute time difference between the soundings in seconds. Set to a negative
+    /// value to allow soundings with no time difference to match.
+    pub min_dt_s: f64,
+    /// Maximum absolute time difference between the soundings in seconds (exclusive).
+    pub max_dt_s: f64,
+    /// Which operation modes may be matched. The per-instrument filters must be applied when
+    /// the soundings are loaded (see [`OcoGeo::filter_modes`]); the pair rules are checked for each match.
+    pub modes: ModeRules,
+    /// How to classify soundings and groups as land or ocean, and which surface class may be matched. Like the
+    /// per-instrument mode filters, the surface restriction is applied when the soundings are loaded (see [`OcoGeo::filter_surface`]).
+    pub surface: SurfaceRules,
+    /// Regions of interest. If filtering by sounding, this must be applied when the soundings are loaded
+    /// (see [`OcoGeo::filter_regions`]); if filtering by group, it is applied by [`OcoMatchGroups::add_sounding_details`].
+    pub regions: RegionSet,
+    /// How to score matched pairs, and the highest score that may match
+    pub score: MatchScore,
+    /// If given, each first instrument sounding keeps only its nearest match (see [`keep_nearest_matches`])
+    pub nearest: Option<NearestRule>,
+    /// How the matches are grouped
+    pub grouping: GroupingRules,
+}
+
+pub fn match_oco3_to_oco2_parallel(oco2: &OcoGeo, oco3: &OcoGeo, criteria: &MatchCriteria, show_progress: ShowProgress) -> OcoMatches {
+    let oco2_inds = (0..oco2.longitude.len()).collect_vec();
+    let pb = setup_match_progress_bar(oco2, &show_progress);
+    let mut matchups = match_oco2_soundings(oco2, oco3, &oco2_inds, criteria, &pb);
+    finish_match_progress_bar(pb, &show_progress);
+    if let Some(rule) = &criteria.nearest {
+        let nearest_first = rule.mutual.then(|| nearest_first_soundings(&matchups, rule, HashMap::new()));
+        matchups = keep_nearest_matches(matchups, rule, nearest_first.as_ref());
+    }
+
+    println!("Number of matchups = {}", matchups.len());
     
-    let mut matchups: Vec<Match2to3> = Vec::new();
+    OcoMatches::from_matches(matchups, oco2.lite_files.clone(), oco3.lite_files.clone())
+}
 
-    let par_it = ndarray::Zip::from(&oco2_inds)
-        .and(&oco2.file_index)
-        .and(&oco2.sounding_id)
-        .and(&oco2.longitude)
-        .and(&oco2.latitude)
-        .and(&oco2.timestamp)
-        .into_par_iter();
+/// Find matches between OCO-2 and -3 soundings in blocks, writing each block to a spill file.
+/// 
+/// This computes the same matches as [`match_oco3_to_oco2_parallel`], but only `chunk_size`
+/// OCO-2 soundings are matched at a time and their matches written to `spill_file` before the
+/// next block is started, so at most one block's matches are held in memory. The OCO-2 soundings
+/// are processed in order of sounding ID, so the spilled matches are ordered as required by
+/// [`identify_groups_from_spilled_matches`].
+///
+/// If the criteria require mutual nearest matches, the matches are computed twice: once to find the nearest
+/// OCO-2 match of each OCO-3 sounding over all blocks, then again to keep only the mutually nearest pairs.
+pub fn match_oco3_to_oco2_chunked(
+    oco2: &OcoGeo,
+    oco3: &OcoGeo,
+    criteria: &MatchCriteria,
+    chunk_size: usize,
+    spill_file: &Path,
+    show_progress: ShowProgress
+) -> Result<SpilledMatches, MatchupError> {
+    let mut oco2_inds = (0..oco2.longitude.len()).collect_vec();
+    oco2_inds.sort_by_key(|&i| oco2.sounding_id[i]);
+
+    let nearest_first = match &criteria.nearest {
+        Some(rule) if rule.mutual => {
+            let pb = setup_match_progress_bar(oco2, &show_progress);
+            let mut nearest_first = HashMap::new();
+            for chunk in oco2_inds.chunks(chunk_size.max(1)) {
+                let matchups = match_oco2_soundings(oco2, oco3, chunk, criteria, &pb);
+                nearest_first = nearest_first_soundings(&matchups, rule, nearest_first);
+            }
+            finish_match_progress_bar(pb, &show_progress);
+            Some(nearest_first)
+        },
+        _ => None
+    };
+
+    let mut writer = MatchSpillWriter::create(spill_file, oco2.lite_files.clone(), oco3.lite_files.clone())?;
+    let pb = setup_match_progress_bar(oco2, &show_progress);
+    for chunk in oco2_inds.chunks(chunk_size.max(1)) {
+        let mut matchups = match_oco2_soundings(oco2, oco3, chunk, criteria, &pb);
+        if let Some(rule) = &criteria.nearest {
+            matchups = keep_nearest_matches(matchups, rule, nearest_first.as_ref());
+        }
+        matchups.sort_by_key(|m| m.oco2_sounding_id);
+        writer.write_matches(&matchups)?;
+    }
+    finish_match_progress_bar(pb, &show_progress);
 
+    let spilled = writer.finish()?;
+    println!("Number of matchups = {}", spilled.num_records());
+    Ok(spilled)
+}
 
+fn setup_match_progress_bar(oco2: &OcoGeo, show_progress: &ShowProgress) -> indicatif::ProgressBar {
     let pbsty = indicatif::ProgressStyle::with_template(
         "{msg} {bar} {human_pos}/{human_len}"
     ).unwrap();
-    let pb = indicatif::ProgressBar::new(n_oco2 as u64);
+    let pb = indicatif::ProgressBar::new(oco2.longitude.len() as u64);
     pb.set_style(pbsty);
     let sid0 = oco2.sounding_id
         .first()
         .copied()
         .unwrap_or(19930101); // use the TAI93 epoch as the default value
     pb.set_message(format!("Matching {} OCO-2 soundings", utils::sid_to_date(sid0).unwrap_or_default()));
-    
 
     match show_progress {
-        ShowProgress::Yes =>  {
-            matchups.par_extend(
-                par_it
-                .progress_with(pb)
-                .filter_map(|tup| { 
-                    parallel_helper(tup, max_dist, min_dt, max_dt, oco3)
-                }
-            ));
-        },
-        ShowProgress::No => {
-            matchups.par_extend(
-                par_it
-                .filter_map(|tup| { 
-                    parallel_helper(tup, max_dist, min_dt, max_dt, oco3)
-                }
-            ));
-        },
-        ShowProgress::Multi(mbar) => {
-            let pb = Arc::new(Mutex::from(mbar.add(pb)));
-            matchups.par_extend(
-                par_it
-                .filter_map(|tup| { 
-                    let res = parallel_helper(tup, max_dist, min_dt, max_dt, oco3);
-                    if let Ok(pb) = pb.lock() {
-                        pb.inc(1);
-                    }
-                    res
-                }
-            ));
-            
-            if let Ok(pb) = pb.lock() {
-                pb.finish_and_clear();
-            };
-        },
+        ShowProgress::Yes => pb,
+        ShowProgress::No => indicatif::ProgressBar::hidden(),
+        ShowProgress::Multi(mbar) => mbar.add(pb),
     }
-
-    println!("Number of matchups = {}", matchups.len());
-    
-    OcoMatches::from_matches(matchups, oco2.lite_files.clone(), oco3.lite_files.clone())
 }
 
-fn parallel_helper(tup: (&usize, &u8, &u64, &f32, &f32, &f64), max_dist: f32, min_dt: f64, max_dt: f64, oco3: &OcoGeo) -> Option<Match2to3> {
-    let (&i_oco2, &fi_oco2, &sid_oco2, &lon_oco2, &lat_oco2, &ts_oco2) = tup;
-    let this_result = make_one_oco_match_vec(fi_oco2, i_oco2, sid_oco2, lon_oco2, lat_oco2, ts_oco2, oco3, max_dist, min_dt, max_dt);
-    if this_result.is_empty() {
-        None
-    }else{
-        Some(this_result)
+fn finish_match_progress_bar(pb: indicatif::ProgressBar, show_progress: &ShowProgress) {
+    if let ShowProgress::Multi(_) = show_progress {
+        pb.finish_and_clear();
+    } else {
+        pb.finish();
     }
 }
 
+/// Match the OCO-2 soundings at positions `oco2_inds` in `oco2` to all the soundings in `oco3`,
+/// in parallel. Returns one [`Match2to3`] for each OCO-2 sounding with at least one match.
+fn match_oco2_soundings(oco2: &OcoGeo, oco3: &OcoGeo, oco2_inds: &[usize], criteria: &MatchCriteria, pb: &indicatif::ProgressBar) -> Vec<Match2to3> {
+    oco2_inds.par_iter()
+        .filter_map(|&i| {
+            let heading = if criteria.distance.needs_heading() { oco2.track_heading[i] } else { f32::NAN };
+            let this_result = make_one_oco_match_vec(oco2, i, heading, oco3, criteria);
+            pb.inc(1);
+
+            if this_result.is_empty() {
+                None
+            }else{
+                Some(this_result)
+            }
+        }).collect()
+}
+
 #[derive(Debug, Serialize)]
-struct Match2to3 {
-    oco2_file_index: u8,
-    oco2_sounding_index: u64,
-    oco2_sounding_id: u64,
-    oco3_file_indices: Vec<u8>,
-    oco3_sounding_indices: Vec<u64>,
-    oco3_sounding_ids: Vec<u64>,
-    distance_km: Vec<f32>,
-    time_diff_s: Vec<f32>
+pub(crate) struct Match2to3 {
+    pub(crate) oco2_file_index: u8,
+    pub(crate) oco2_sounding_index: u64,
+    pub(crate) oco2_sounding_id: u64,
+    pub(crate) oco3_file_indices: Vec<u8>,
+    pub(crate) oco3_sounding_indices: Vec<u64>,
+    pub(crate) oco3_sounding_ids: Vec<u64>,
+    pub(crate) distance_km: Vec<f32>,
+    pub(crate) time_diff_s: Vec<f32>,
+    pub(crate) score: Vec<f32>
 }
 
 impl Match2to3 {
-    fn new(oco2_file_index: u8, oco2_sounding_index: u64, oco2_sounding_id: u64) -> Self {
+    pub(crate) fn new(oco2_file_index: u8, oco2_sounding_index: u64, oco2_sounding_id: u64) -> Self {
         Self { 
             oco2_file_index, 
             oco2_sounding_index, 
@@ -743,35 +1860,117 @@ impl Match2to3 {
             oco3_sounding_indices: Vec::new(), 
             oco3_sounding_ids: Vec::new(), 
             distance_km: Vec::new(),
-            time_diff_s: Vec::new()
+            time_diff_s: Vec::new(),
+            score: Vec::new()
         }
     }
 
-    fn add_oco3_match(&mut self, file_idx_oco3: u8, idx_oco3: usize, sid_oco3: u64, dist: f32, dt_sec: f32) {
+    pub(crate) fn add_oco3_match(&mut self, file_idx_oco3: u8, idx_oco3: usize, sid_oco3: u64, dist: f32, dt_sec: f32, score: f32) {
         self.oco3_file_indices.push(file_idx_oco3);
         self.oco3_sounding_indices.push(idx_oco3 as u64);
         self.oco3_sounding_ids.push(sid_oco3);
         self.distance_km.push(dist);
         self.time_diff_s.push(dt_sec);
+        self.score.push(score);
     }
 
     fn is_empty(&self) -> bool {
         self.oco3_sounding_ids.is_empty()
     }
+
+    /// Position of the lowest cost match under `rule`. Ties go to the lowest OCO-3 sounding ID, so that
+    /// the choice does not depend on the order the matches were found in.
+    fn nearest_match(&self, rule: &NearestRule) -> Option<usize> {
+        (0..self.oco3_sounding_ids.len())
+            .min_by(|&a, &b| {
+                rule.cost(self.distance_km[a], self.time_diff_s[a], self.score[a])
+                    .total_cmp(&rule.cost(self.distance_km[b], self.time_diff_s[b], self.score[b]))
+                    .then(self.oco3_sounding_ids[a].cmp(&self.oco3_sounding_ids[b]))
+            })
+    }
+
+    /// Keep only the match at position `i`
+    fn retain_only(&mut self, i: usize) {
+        self.oco3_file_indices = vec![self.oco3_file_indices[i]];
+        self.oco3_sounding_indices = vec![self.oco3_sounding_indices[i]];
+        self.oco3_sounding_ids = vec![self.oco3_sounding_ids[i]];
+        self.distance_km = vec![self.distance_km[i]];
+        self.time_diff_s = vec![self.time_diff_s[i]];
+        self.score = vec![self.score[i]];
+    }
+
+    /// Recompute the scores of the matches with `score`, removing any that it does not accept
+    fn rescore(&mut self, score: &MatchScore) {
+        for (s, &dist, &dt) in izip!(self.score.iter_mut(), self.distance_km.iter(), self.time_diff_s.iter()) {
+            *s = score.score(dist, dt);
+        }
+
+        let keep = self.score.iter().map(|&s| score.accepts(s)).collect_vec();
+        self.retain_oco3(&keep);
+    }
+
+    /// Keep only the OCO-3 matches where `keep` is true. `keep` must have one element per OCO-3 match.
+    fn retain_oco3(&mut self, keep: &[bool]) {
+        retain_by_mask(&mut self.oco3_file_indices, keep);
+        retain_by_mask(&mut self.oco3_sounding_indices, keep);
+        retain_by_mask(&mut self.oco3_sounding_ids, keep);
+        retain_by_mask(&mut self.distance_km, keep);
+        retain_by_mask(&mut self.time_diff_s, keep);
+        retain_by_mask(&mut self.score, keep);
+    }
+}
+
+/// Keep the elements of `values` where `keep` is true
+fn retain_by_mask<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut keep = keep.iter();
+    values.retain(|_| keep.next().copied().unwrap_or(false));
 }
 
-fn make_one_oco_match_vec(file_idx_oco2: u8, 
-                          idx_oco2: usize, 
-                          sid_oco2: u64, 
-                          lon_oco2: f32, 
-                          lat_oco2: f32, 
-                          ts_oco2: f64, 
-                          oco3: &OcoGeo, 
-                          max_dist: f32,
-                          min_dt: f64, 
-                          max_dt: f64) 
-    -> Match2to3 {
-    let mut oco3_matches = Match2to3::new(file_idx_oco2, idx_oco2 as u64, sid_oco2);
+/// Add the nearest OCO-2 sounding under `rule` for each OCO-3 sounding in `matches` to `nearest`, which maps
+/// OCO-3 sounding IDs to the cost and sounding ID of their nearest OCO-2 sounding so far. Returns the updated map.
+/// Ties go to the lowest OCO-2 sounding ID.
+fn nearest_first_soundings(matches: &[Match2to3], rule: &NearestRule, mut nearest: HashMap<u64, (f32, u64)>) -> HashMap<u64, (f32, u64)> {
+    for m in matches {
+        for (&sid3, &dist, &dt, &score) in izip!(m.oco3_sounding_ids.iter(), m.distance_km.iter(), m.time_diff_s.iter(), m.score.iter()) {
+            let cost = rule.cost(dist, dt, score);
+            let entry = nearest.entry(sid3).or_insert((cost, m.oco2_sounding_id));
+            if cost.total_cmp(&entry.0).then(m.oco2_sounding_id.cmp(&entry.1)).is_lt() {
+                *entry = (cost, m.oco2_sounding_id);
+            }
+        }
+    }
+    nearest
+}
+
+/// Reduce each OCO-2 sounding's matches to its nearest OCO-3 sounding under `rule`.
+///
+/// If `nearest_first` is given (from [`nearest_first_soundings`] over all the matches), a pair is only kept if
+/// the OCO-2 sounding is also the nearest match of the OCO-3 sounding; OCO-2 soundings left with no match are removed.
+fn keep_nearest_matches(matches: Vec<Match2to3>, rule: &NearestRule, nearest_first: Option<&HashMap<u64, (f32, u64)>>) -> Vec<Match2to3> {
+    matches.into_iter()
+        .filter_map(|mut m| {
+            let i = m.nearest_match(rule)?;
+            if let Some(nearest_first) = nearest_first {
+                let is_mutual = nearest_first.get(&m.oco3_sounding_ids[i])
+                    .is_some_and(|&(_, sid2)| sid2 == m.oco2_sounding_id);
+                if !is_mutual {
+                    return None;
+                }
+            }
+            m.retain_only(i);
+            Some(m)
+        }).collect()
+}
+
+/// Find all the soundings in `oco3` that match the sounding at position `i` in `oco2`, whose
+/// track heading is `heading_oco2` (NaN if not known or not needed).
+fn make_one_oco_match_vec(oco2: &OcoGeo, i: usize, heading_oco2: f32, oco3: &OcoGeo, criteria: &MatchCriteria) -> Match2to3 {
+    let lon_oco2 = oco2.longitude[i];
+    let lat_oco2 = oco2.latitude[i];
+    let ts_oco2 = oco2.timestamp[i];
+    let mode_oco2 = oco2.operation_mode[i];
+    // The sounding index is the position in the lite file, which may differ from `i` if soundings were filtered out
+    let mut oco3_matches = Match2to3::new(oco2.file_index[i], oco2.sounding_index[i] as u64, oco2.sounding_id[i]);
 
     let it = izip!(
         oco3.sounding_index.iter(),
@@ -779,15 +1978,39 @@ fn make_one_oco_match_vec(file_idx_oco2: u8,
         oco3.sounding_id.iter(),
         oco3.longitude.iter(),
         oco3.latitude.iter(),
-        oco3.timestamp.iter()
+        oco3.timestamp.iter(),
+        oco3.operation_mode.iter()
     );
 
-    for (&idx_oco3, &file_idx_oco3, &sid_oco3, &lon_oco3, &lat_oco3, &ts_oco3) in it {
-        let this_dist = great_circle_distance(lon_oco2, lat_oco2, lon_oco3, lat_oco3);
+    // Soundings that are certainly too far apart are skipped with a fast approximation before computing the
+    // distance with the requested model. The box criterion has no distance limit, but is tested directly instead.
+    let prefilter_km = criteria.distance.max_distance_km().map(|d| d as f64);
+    let (lon_oco2_f64, lat_oco2_f64) = (lon_oco2 as f64, lat_oco2 as f64);
+
+    for (&idx_oco3, &file_idx_oco3, &sid_oco3, &lon_oco3, &lat_oco3, &ts_oco3, &mode_oco3) in it {
         let this_delta_time = ts_oco2 - ts_oco3;
+        if this_delta_time.abs() < criteria.min_dt_s || this_delta_time.abs() >= criteria.max_dt_s || !criteria.modes.allows_pair(mode_oco2, mode_oco3) {
+            continue;
+        }
+
+        if !criteria.distance.may_accept(lon_oco2, lat_oco2, lon_oco3, lat_oco3) {
+            continue;
+        }
+        let (lon_oco3_f64, lat_oco3_f64) = (lon_oco3 as f64, lat_oco3 as f64);
+        if let Some(max_km) = prefilter_km {
+            if !geodesy::may_be_within(lon_oco2_f64, lat_oco2_f64, lon_oco3_f64, lat_oco3_f64, max_km) {
+                continue;
+            }
+        }
+
+        let this_dist = criteria.distance_model.distance_km(lon_oco2_f64, lat_oco2_f64, lon_oco3_f64, lat_oco3_f64) as f32;
+        if !criteria.distance.accepts(this_dist, lon_oco2, lat_oco2, lon_oco3, lat_oco3, heading_oco2) {
+            continue;
+        }
 
-        if this_dist <= max_dist && this_delta_time.abs() >= min_dt && this_delta_time.abs() < max_dt {
-            oco3_matches.add_oco3_match(file_idx_oco3, idx_oco3, sid_oco3, this_dist, this_delta_time as f32);
+        let this_score = criteria.score.score(this_dist, this_delta_time as f32);
+        if criteria.score.accepts(this_score) {
+            oco3_matches.add_oco3_match(file_idx_oco3, idx_oco3, sid_oco3, this_dist, this_delta_time as f32, this_score);
         }
     }
 
@@ -804,27 +2027,81 @@ fn make_one_oco_match_vec(file_idx_oco2: u8,
 //     pb
 // }
 
-pub fn identify_groups_from_matched_soundings(matched_soundings: OcoMatches) -> OcoMatchGroups {
+/// Group matched soundings. If `passes` is given (see [`PassKeys::new`]), matches are grouped by the pair of
+/// passes or orbits of their soundings, and it must include every matched sounding from each instrument. If it
+/// is `None`, matches that share a second instrument sounding are grouped together.
+pub fn identify_groups_from_matched_soundings(matched_soundings: OcoMatches, passes: Option<&PassKeys>) -> OcoMatchGroups {
+    group_matches(matched_soundings.matches, matched_soundings.oco2_files, matched_soundings.oco3_files, passes)
+}
+
+/// Group matched soundings read back from a spill file written by [`match_oco3_to_oco2_chunked`].
+/// 
+/// This gives the same groups as [`identify_groups_from_matched_soundings`] would for the same
+/// matches and `passes`, but only one match is read into memory at a time.
+pub fn identify_groups_from_spilled_matches(spilled: &SpilledMatches, passes: Option<&PassKeys>) -> Result<OcoMatchGroups, MatchupError> {
+    let mut read_err = None;
+    let matches = spilled.iter()?
+        .map_while(|res| res.map_err(|e| read_err = Some(e)).ok());
+    let groups = group_matches(matches, spilled.oco2_files.clone(), spilled.oco3_files.clone(), passes);
+
+    if let Some(e) = read_err {
+        Err(e)
+    } else {
+        Ok(groups)
+    }
+}
+
+/// The pass number (see [`OcoGeo::pass_numbers`]) or orbit number of each sounding from each instrument, by sounding ID,
+/// used to group matches by pass or orbit.
+pub struct PassKeys {
+    first: HashMap<u64, u32>,
+    second: HashMap<u64, u32>,
+}
+
+impl PassKeys {
+    /// Number the passes of `first` and `second` if `rules` groups by pass, or take their orbit numbers if it
+    /// groups by orbit; return `None` if it groups connected matches.
+    pub fn new(first: &OcoGeo, second: &OcoGeo, rules: &GroupingRules) -> Option<Self> {
+        let keys = |geo: &OcoGeo| {
+            let numbers = match rules.mode {
+                GroupingMode::Connected => return None,
+                GroupingMode::Pass => geo.pass_numbers(rules.pass_gap_s),
+                GroupingMode::Orbit => geo.orbit.clone(),
+            };
+            Some(geo.sounding_id.iter().copied().zip(numbers).collect::<HashMap<_, _>>())
+        };
+        Some(Self { first: keys(first)?, second: keys(second)? })
+    }
+
+    /// The pair of passes of a first and second instrument sounding. Soundings not in the loaded data (which should
+    /// not happen) share a pass number that no real pass uses.
+    fn pass_pair(&self, first_sid: u64, second_sid: u64) -> (u32, u32) {
+        (
+            self.first.get(&first_sid).copied().unwrap_or(u32::MAX),
+            self.second.get(&second_sid).copied().unwrap_or(u32::MAX),
+        )
+    }
+}
+
+/// Group `matches`. If `passes` is given, each pair of passes (or orbits) is one group; otherwise, matches that share a
+/// second instrument sounding are grouped together.
+fn group_matches<I: IntoIterator<Item = Match2to3>>(matches: I, oco2_files: Vec<PathBuf>, oco3_files: Vec<PathBuf>, passes: Option<&PassKeys>) -> OcoMatchGroups {
     fn update_sounding_inds(
         this_match: &Match2to3, 
         oco2_inds: &mut HashMap<u64, (u8, u64)>, 
         oco3_inds: &mut HashMap<u64, (u8, u64)>, 
-        dist_mean: &mut HashMap<u64, RunningMean<f32>>,
-        dt_mean: &mut HashMap<u64, RunningMean<f32>>
     ) {
         oco2_inds.insert(this_match.oco2_sounding_id, (this_match.oco2_file_index, this_match.oco2_sounding_index));
-        dist_mean.insert(this_match.oco2_sounding_id, RunningMean::from_slice(&this_match.distance_km));
-        dt_mean.insert(this_match.oco2_sounding_id, RunningMean::from_slice(&this_match.time_diff_s));
         for (&sid, &fid, &idx) in izip!(this_match.oco3_sounding_ids.iter(), this_match.oco3_file_indices.iter(), this_match.oco3_sounding_indices.iter()) {
             oco3_inds.insert(sid, (fid, idx));
         }
     }
 
     let mut match_sets: Vec<(HashSet<u64>, HashSet<u64>)> = Vec::new();
+    let mut pair_stats: Vec<GroupPairStats> = Vec::new();
     let mut oco2_sounding_indices = HashMap::new();
     let mut oco3_sounding_indices = HashMap::new();
-    let mut mean_dists = HashMap::new();
-    let mut mean_time_diffs = HashMap::new();
+    let mut pass_groups: HashMap<(u32, u32), usize> = HashMap::new();
 
     // It's important to iterate over ordered keys: when I let this be unordered, some groups that
     // should be one got split up, I think because the (non-overlapping) ends got put into separate 
@@ -832,34 +2109,165 @@ pub fn identify_groups_from_matched_soundings(matched_soundings: OcoMatches) ->
     // the OcoMatches instance with `from_matches` that enforces ordering by OCO-2 sounding ID.
 
     // let pb = setup_progress_bar(matched_soundings.matches.len() as u64, "match vectors grouped");
-    for m in matched_soundings.matches {
+    for m in matches {
         // pb.inc(1);
         let oco3_row = &m.oco3_sounding_ids;
-        let mut matched = false;
-        for (oco2_idx_set, oco3_idx_set) in match_sets.iter_mut() {
-            if oco3_row.iter().any(|i| oco3_idx_set.contains(i)) {
+        if let Some(passes) = passes {
+            // A first instrument sounding can match second instrument soundings from more than one pass,
+            // in which case it is in more than one group.
+            for (&sid3, &dist, &dt, &score) in izip!(oco3_row.iter(), m.distance_km.iter(), m.time_diff_s.iter(), m.score.iter()) {
+                let i_group = *pass_groups.entry(passes.pass_pair(m.oco2_sounding_id, sid3))
+                    .or_insert_with(|| {
+                        match_sets.push((HashSet::new(), HashSet::new()));
+                        pair_stats.push(GroupPairStats::default());
+                        match_sets.len() - 1
+                    });
+                match_sets[i_group].0.insert(m.oco2_sounding_id);
+                match_sets[i_group].1.insert(sid3);
+                pair_stats[i_group].add_pair(dist, dt, score);
+            }
+        } else {
+            let i_group = match_sets.iter()
+                .position(|(_, oco3_idx_set)| oco3_row.iter().any(|i| oco3_idx_set.contains(i)));
+            let i_group = if let Some(i) = i_group {
+                let (oco2_idx_set, oco3_idx_set) = &mut match_sets[i];
                 oco2_idx_set.insert(m.oco2_sounding_id);
                 oco3_idx_set.extend(oco3_row.iter());
-                matched = true;
-                break;
-            }
-        }
+                i
+            } else {
+                match_sets.push((
+                    HashSet::from([m.oco2_sounding_id]), HashSet::from_iter(oco3_row.iter().copied())
+                ));
+                pair_stats.push(GroupPairStats::default());
+                match_sets.len() - 1
+            };
 
-        if !matched {
-            match_sets.push((
-                HashSet::from([m.oco2_sounding_id]), HashSet::from_iter(oco3_row.iter().copied())
-            ));
+            for (&dist, &dt, &score) in izip!(m.distance_km.iter(), m.time_diff_s.iter(), m.score.iter()) {
+                pair_stats[i_group].add_pair(dist, dt, score);
+            }
         }
 
-        update_sounding_inds(&m, &mut oco2_sounding_indices, &mut oco3_sounding_indices, &mut mean_dists, &mut mean_time_diffs);
+        update_sounding_inds(&m, &mut oco2_sounding_indices, &mut oco3_sounding_indices);
     }
     // pb.finish_with_message("  -> All matches grouped.");
 
-    OcoMatchGroups { oco2_lite_files: matched_soundings.oco2_files,
-                     oco3_lite_files: matched_soundings.oco3_files,
+    OcoMatchGroups { oco2_lite_files: oco2_files,
+                     oco3_lite_files: oco3_files,
                      match_sets,
                      oco2_sounding_indices,
                      oco3_sounding_indices,
-                     distances: mean_dists,
-                     time_diffs: mean_time_diffs }
+                     pair_stats,
+                     details: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory for a test netCDF file, unique to this test process
+    fn test_nc_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oco_lite_matchup_test_{}_{name}.nc", std::process::id()))
+    }
+
+    /// A match of one OCO-2 sounding to OCO-3 soundings given as (file index, sounding index, sounding ID, distance, time difference, score)
+    fn test_match(oco2_sid: u64, oco3: &[(u8, u64, u64, f32, f32, f32)]) -> Match2to3 {
+        let mut m = Match2to3::new(0, oco2_sid % 1000, oco2_sid);
+        for &(fi, i, sid, dist, dt, score) in oco3 {
+            m.oco3_file_indices.push(fi);
+            m.oco3_sounding_indices.push(i);
+            m.oco3_sounding_ids.push(sid);
+            m.distance_km.push(dist);
+            m.time_diff_s.push(dt);
+            m.score.push(score);
+        }
+        m
+    }
+
+    fn test_matches() -> OcoMatches {
+        let matches = vec![
+            test_match(2020010112000001, &[(0, 10, 2020010112100001, 12.5, -300.0, 0.2), (1, 11, 2020010112100002, 80.0, 250.0, 0.9)]),
+            test_match(2020010112000002, &[(1, 12, 2020010112100003, 40.0, 10.0, 0.4)]),
+        ];
+        OcoMatches::from_matches(matches, vec![PathBuf::from("oco2_a.nc4")], vec![PathBuf::from("oco3_a.nc4"), PathBuf::from("oco3_b.nc4")])
+    }
+
+    /// Check that `actual` has the same files and matches as `expected`, ignoring the scores if `check_scores` is false
+    fn assert_same_matches(actual: &OcoMatches, expected: &OcoMatches, check_scores: bool) {
+        assert_eq!(actual.oco2_files, expected.oco2_files);
+        assert_eq!(actual.oco3_files, expected.oco3_files);
+        assert_eq!(actual.matches.len(), expected.matches.len());
+        for (a, e) in actual.matches.iter().zip(expected.matches.iter()) {
+            assert_eq!(a.oco2_file_index, e.oco2_file_index);
+            assert_eq!(a.oco2_sounding_index, e.oco2_sounding_index);
+            assert_eq!(a.oco2_sounding_id, e.oco2_sounding_id);
+            assert_eq!(a.oco3_file_indices, e.oco3_file_indices);
+            assert_eq!(a.oco3_sounding_indices, e.oco3_sounding_indices);
+            assert_eq!(a.oco3_sounding_ids, e.oco3_sounding_ids);
+            assert_eq!(a.distance_km, e.distance_km);
+            assert_eq!(a.time_diff_s, e.time_diff_s);
+            if check_scores {
+                assert_eq!(a.score, e.score);
+            } else {
+                assert!(a.score.iter().all(|s| s.is_nan()) && a.score.len() == e.score.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_matches_ragged_round_trip() {
+        let expected = test_matches();
+        let nc_file = test_nc_path("ragged");
+        {
+            let mut ds = netcdf::create(&nc_file).unwrap();
+            let mut grp = ds.add_group("matches").unwrap();
+            expected.to_nc_group(&mut grp).unwrap();
+        }
+
+        let ds = netcdf::open(&nc_file).unwrap();
+        let grp = ds.group("matches").unwrap().unwrap();
+        let row_sizes: Vec<u32> = grp.variable("row_size").unwrap().values_arr::<u32, _>(Extents::All).unwrap().into_raw_vec();
+        assert_eq!(row_sizes, vec![2, 1]);
+        assert_eq!(grp.variable("distance").unwrap().dimensions().iter().map(|d| d.name()).collect_vec(), vec!["match_pair"]);
+
+        let actual = OcoMatches::from_nc_group(&grp).unwrap();
+        std::fs::remove_file(&nc_file).ok();
+        assert_same_matches(&actual, &expected, true);
+    }
+
+    #[test]
+    fn test_full_matches_read_2d_layout() {
+        // Write the matches the way older versions did: OCO-3 values in 2D arrays padded with fill values, and no scores
+        let expected = test_matches();
+        let nc_file = test_nc_path("2d");
+        {
+            let mut ds = netcdf::create(&nc_file).unwrap();
+            let mut grp = ds.add_group("matches").unwrap();
+            grp.add_dimension("oco2_file", 1).unwrap();
+            grp.add_dimension("oco3_file", 2).unwrap();
+            grp.add_dimension("oco2_match", 2).unwrap();
+            grp.add_dimension("oco3_match", 2).unwrap();
+            utils::write_string_nc_var(&mut grp, &["oco2_a.nc4"], "oco2_file", "oco2_file", None, None).unwrap();
+            utils::write_string_nc_var(&mut grp, &["oco3_a.nc4", "oco3_b.nc4"], "oco3_file", "oco3_file", None, None).unwrap();
+
+            fn put<T: netcdf::NcPutGet + Copy>(grp: &mut netcdf::GroupMut, name: &str, dims: &[&str], values: &[T], fill: T) {
+                let mut var = grp.add_variable::<T>(name, dims).unwrap();
+                var.set_fill_value(fill).unwrap();
+                var.put_values(values, Extents::All).unwrap();
+            }
+            put(&mut grp, "oco2_file_index", &["oco2_match"], &[0u8, 0], u8::MAX);
+            put(&mut grp, "oco2_index", &["oco2_match"], &[1u64, 2], u64::MAX);
+            put(&mut grp, "oco2_sounding_id", &["oco2_match"], &[2020010112000001u64, 2020010112000002], u64::MAX);
+            put(&mut grp, "oco3_file_index", &["oco2_match", "oco3_match"], &[0u8, 1, 1, u8::MAX], u8::MAX);
+            put(&mut grp, "oco3_index", &["oco2_match", "oco3_match"], &[10u64, 11, 12, u64::MAX], u64::MAX);
+            put(&mut grp, "oco3_sounding_id", &["oco2_match", "oco3_match"], &[2020010112100001u64, 2020010112100002, 2020010112100003, u64::MAX], u64::MAX);
+            put(&mut grp, "distance", &["oco2_match", "oco3_match"], &[12.5f32, 80.0, 40.0, f32::MAX], f32::MAX);
+            put(&mut grp, "time_difference", &["oco2_match", "oco3_match"], &[-300.0f32, 250.0, 10.0, f32::MAX], f32::MAX);
+        }
+
+        let ds = netcdf::open(&nc_file).unwrap();
+        let grp = ds.group("matches").unwrap().unwrap();
+        let actual = OcoMatches::from_nc_group(&grp).unwrap();
+        std::fs::remove_file(&nc_file).ok();
+        assert_same_matches(&actual, &expected, false);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use itertools::Itertools;
+
+use crate::error::MatchupError;
+use crate::oco::MatchCriteria;
+
+/// Version of the CF conventions that output files follow
+pub const CF_CONVENTIONS: &str = "CF-1.8";
+
+/// Information about how an output file was made, written as global attributes.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    /// The criteria used to match soundings
+    pub criteria: MatchCriteria,
+    /// Whether this was a matchup of OCO-3 against itself
+    pub oco3_self_cross: bool,
+    /// Whether only soundings with `xco2_quality_flag == 0` were used
+    pub flag0_only: bool,
+    /// The files read to make the output file
+    pub input_files: Vec<PathBuf>,
+}
+
+impl Provenance {
+    pub fn new(criteria: MatchCriteria, oco3_self_cross: bool, flag0_only: bool, input_files: Vec<PathBuf>) -> Self {
+        Self { criteria, oco3_self_cross, flag0_only, input_files }
+    }
+
+    /// Write the global attributes describing this output file to `ds`.
+    ///
+    /// Besides the fields of this struct, this records the command line of the current process (as the
+    /// CF `history` attribute), the program version and git commit, the creation time, and the host name.
+    pub fn write_global_attributes(&self, ds: &mut netcdf::MutableFile) -> Result<(), MatchupError> {
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+
+        ds.add_attribute("Conventions", CF_CONVENTIONS)?;
+        ds.add_attribute("title", if self.oco3_self_cross { "OCO-3 self crossings" } else { "OCO-2/OCO-3 crossings" })?;
+        ds.add_attribute("history", format!("{now}: {}", command_line()))?;
+        ds.add_attribute("date_created", now)?;
+        ds.add_attribute("program", env!("CARGO_PKG_NAME"))?;
+        ds.add_attribute("program_version", env!("CARGO_PKG_VERSION"))?;
+        ds.add_attribute("program_git_hash", option_env!("OCO_MATCHUP_GIT_HASH").unwrap_or("unknown"))?;
+        ds.add_attribute("host", host_name())?;
+
+        ds.add_attribute("match_distance_criterion", self.criteria.distance.to_string())?;
+        ds.add_attribute("match_distance_model", self.criteria.distance_model.name())?;
+        if let Some(max_dist) = self.criteria.distance.max_distance_km() {
+            ds.add_attribute("match_max_distance_km", max_dist)?;
+        }
+        // A negative minimum time difference means there is no minimum
+        ds.add_attribute("match_min_time_difference_s", self.criteria.min_dt_s.max(0.0))?;
+        ds.add_attribute("match_max_time_difference_s", self.criteria.max_dt_s)?;
+        ds.add_attribute("match_first_instrument_modes", self.criteria.modes.first.to_string())?;
+        ds.add_attribute("match_second_instrument_modes", self.criteria.modes.second.to_string())?;
+        ds.add_attribute("match_mode_pairs", self.criteria.modes.pairs_description())?;
+        ds.add_attribute("land_threshold_percent", self.criteria.surface.land_threshold)?;
+        ds.add_attribute("ocean_threshold_percent", self.criteria.surface.ocean_threshold)?;
+        ds.add_attribute("match_surface_class", self.criteria.surface.only.map(|c| c.name()).unwrap_or("any"))?;
+        ds.add_attribute("match_score_formula", self.criteria.score.formula())?;
+        if let Some(max_score) = self.criteria.score.max_score {
+            ds.add_attribute("match_max_score", max_score)?;
+        }
+        ds.add_attribute("match_nearest", self.criteria.nearest.map(|n| n.description()).unwrap_or_else(|| "all".to_owned()))?;
+        ds.add_attribute("group_by", self.criteria.grouping.mode.name())?;
+        if self.criteria.grouping.by_pass() {
+            ds.add_attribute("pass_gap_s", self.criteria.grouping.pass_gap_s)?;
+        }
+        if let Some(thresholds) = self.criteria.grouping.threshold_description() {
+            ds.add_attribute("group_quality_thresholds", thresholds)?;
+        }
+        if !self.criteria.regions.is_empty() {
+            ds.add_attribute("regions", self.criteria.regions.names())?;
+            ds.add_attribute("region_filter", self.criteria.regions.filter.name())?;
+        }
+        ds.add_attribute("oco3_self_crossing", self.oco3_self_cross as u8)?;
+        ds.add_attribute("flag0_only", self.flag0_only as u8)?;
+
+        let inputs = self.input_files.iter().map(|p| p.display().to_string()).join("\n");
+        ds.add_attribute("source_files", inputs)?;
+        Ok(())
+    }
+}
+
+/// The command line of the current process, with arguments containing spaces quoted.
+fn command_line() -> String {
+    std::env::args_os()
+        .map(|a| {
+            let a = a.to_string_lossy().into_owned();
+            if a.contains(char::is_whitespace) { format!("'{a}'") } else { a }
+        })
+        .join(" ")
+}
+
+fn host_name() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
@@ -0,0 +1,472 @@
+//! Regions of interest that soundings or groups can be restricted to, and that groups are tagged with.
+//!
+//! Regions can be given as bounding boxes, as polygons read from GeoJSON files, or by name from a
+//! TOML file listing regions (see [`RegionSet::from_options`]).
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::config::RegionOptions;
+use crate::error::MatchupError;
+
+/// How regions are used to restrict the matches found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionFilter {
+    /// Only match soundings inside at least one region
+    #[default]
+    Soundings,
+    /// Match all soundings, but only keep groups with at least one sounding inside a region
+    Groups,
+    /// Do not filter, only record which regions each group falls in
+    Tag,
+}
+
+impl RegionFilter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Soundings => "soundings",
+            Self::Groups => "groups",
+            Self::Tag => "tag",
+        }
+    }
+}
+
+/// A bounding box given on the command line as `[NAME=]WEST,SOUTH,EAST,NORTH`, in degrees.
+/// If WEST is greater than EAST, the box crosses the dateline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RegionBox {
+    pub name: Option<String>,
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl FromStr for RegionBox {
+    type Err = MatchupError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, coords) = match s.split_once('=') {
+            Some((name, coords)) => (Some(name.trim().to_owned()), coords),
+            None => (None, s),
+        };
+
+        let coords = coords.split(',')
+            .map(|c| c.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| MatchupError::ArgumentError(format!("Invalid number in bounding box '{s}': {e}")))?;
+        if coords.len() != 4 {
+            return Err(MatchupError::ArgumentError(format!("Bounding box '{s}' must have four values: WEST,SOUTH,EAST,NORTH")));
+        }
+
+        let bbox = Self { name, west: coords[0], south: coords[1], east: coords[2], north: coords[3] };
+        BoundingBox::new(bbox.west, bbox.south, bbox.east, bbox.north)?;
+        Ok(bbox)
+    }
+}
+
+impl TryFrom<String> for RegionBox {
+    type Error = MatchupError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<RegionBox> for String {
+    fn from(value: RegionBox) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for RegionBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{name}=")?;
+        }
+        write!(f, "{},{},{},{}", self.west, self.south, self.east, self.north)
+    }
+}
+
+/// A named area on the Earth's surface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub name: String,
+    shape: RegionShape,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RegionShape {
+    BoundingBox(BoundingBox),
+    /// The region is the union of these polygons
+    Polygons(Vec<Polygon>),
+}
+
+impl Region {
+    pub fn from_bbox(name: String, west: f64, south: f64, east: f64, north: f64) -> Result<Self, MatchupError> {
+        Ok(Self { name, shape: RegionShape::BoundingBox(BoundingBox::new(west, south, east, north)?) })
+    }
+
+    /// Whether the point at `lon`, `lat` (in degrees) is inside this region.
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        match &self.shape {
+            RegionShape::BoundingBox(bbox) => bbox.contains(lon, lat),
+            RegionShape::Polygons(polys) => polys.iter().any(|p| p.contains(lon, lat)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BoundingBox {
+    west: f64,
+    south: f64,
+    east: f64,
+    north: f64,
+}
+
+impl BoundingBox {
+    fn new(west: f64, south: f64, east: f64, north: f64) -> Result<Self, MatchupError> {
+        if south > north {
+            return Err(MatchupError::ArgumentError(format!("Bounding box south edge ({south}) is north of its north edge ({north})")));
+        }
+        if !(-90.0..=90.0).contains(&south) || !(-90.0..=90.0).contains(&north) {
+            return Err(MatchupError::ArgumentError("Bounding box latitudes must be between -90 and 90".to_owned()));
+        }
+        Ok(Self { west, south, east, north })
+    }
+
+    fn contains(&self, lon: f64, lat: f64) -> bool {
+        if lat < self.south || lat > self.north {
+            return false;
+        }
+
+        let lon = lon.rem_euclid(360.0);
+        let west = self.west.rem_euclid(360.0);
+        let east = self.east.rem_euclid(360.0);
+        if self.east - self.west >= 360.0 {
+            true
+        } else if west <= east {
+            lon >= west && lon <= east
+        } else {
+            // Crosses the dateline (or prime meridian, after wrapping to [0, 360))
+            lon >= west || lon <= east
+        }
+    }
+}
+
+/// A polygon with an exterior ring and optional holes, as in GeoJSON. Rings are lists of (lon, lat) vertices.
+/// Polygons must not cross the dateline; split them into one polygon on each side instead.
+#[derive(Debug, Clone, PartialEq)]
+struct Polygon {
+    exterior: Vec<(f64, f64)>,
+    holes: Vec<Vec<(f64, f64)>>,
+    /// Bounding box of the exterior ring, to quickly reject points far from the polygon
+    extent: (f64, f64, f64, f64),
+}
+
+impl Polygon {
+    fn new(exterior: Vec<(f64, f64)>, holes: Vec<Vec<(f64, f64)>>) -> Result<Self, String> {
+        if exterior.len() < 3 {
+            return Err(format!("polygon has {} vertices, at least 3 are needed", exterior.len()));
+        }
+
+        let (west, east) = exterior.iter().map(|p| p.0).minmax_by(|a, b| a.total_cmp(b)).into_option().unwrap_or_default();
+        let (south, north) = exterior.iter().map(|p| p.1).minmax_by(|a, b| a.total_cmp(b)).into_option().unwrap_or_default();
+        Ok(Self { exterior, holes, extent: (west, south, east, north) })
+    }
+
+    fn contains(&self, lon: f64, lat: f64) -> bool {
+        let (west, south, east, north) = self.extent;
+        if lat < south || lat > north {
+            return false;
+        }
+
+        // Try the longitude in the same range as the polygon's vertices
+        let lon = [lon, lon - 360.0, lon + 360.0].into_iter()
+            .find(|l| *l >= west && *l <= east);
+        let lon = if let Some(l) = lon { l } else { return false };
+
+        ring_contains(&self.exterior, lon, lat) && !self.holes.iter().any(|h| ring_contains(h, lon, lat))
+    }
+}
+
+/// Even-odd rule test for whether a point is inside a closed ring (the last vertex need not repeat the first).
+fn ring_contains(ring: &[(f64, f64)], x: f64, y: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (xi, yi) = ring[i];
+        let (xj, yj) = ring[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// The regions used by one matchup and how they restrict the matches.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegionSet {
+    pub regions: Vec<Region>,
+    pub filter: RegionFilter,
+}
+
+/// The on-disk form of a named regions file.
+#[derive(Debug, Deserialize)]
+struct RegionFile {
+    regions: Vec<RegionFileEntry>,
+}
+
+/// One region in a named regions file, given by exactly one of a bounding box, a polygon, or a GeoJSON file.
+#[derive(Debug, Deserialize)]
+struct RegionFileEntry {
+    name: String,
+    /// [west, south, east, north]
+    bbox: Option<[f64; 4]>,
+    /// List of [lon, lat] vertices
+    polygon: Option<Vec<[f64; 2]>>,
+    /// Path to a GeoJSON file, relative to the regions file; all the polygons in it make up this region
+    geojson: Option<PathBuf>,
+}
+
+impl RegionSet {
+    /// Build the regions requested by the command line or configuration options, in the order: bounding
+    /// boxes, GeoJSON features, named regions.
+    pub fn from_options(opts: &RegionOptions) -> Result<Self, MatchupError> {
+        let mut regions = Vec::new();
+        for (i, bbox) in opts.region_bbox.iter().enumerate() {
+            let name = bbox.name.clone().unwrap_or_else(|| format!("bbox{}", i + 1));
+            regions.push(Region::from_bbox(name, bbox.west, bbox.south, bbox.east, bbox.north)?);
+        }
+
+        for geojson_file in opts.region_geojson.iter() {
+            regions.extend(read_geojson_regions(geojson_file)?);
+        }
+
+        if let Some(region_file) = opts.region_file.as_deref() {
+            let named = read_region_file(region_file)?;
+            if opts.regions.is_empty() {
+                regions.extend(named);
+            } else {
+                for name in opts.regions.iter() {
+                    let region = named.iter()
+                        .find(|r| &r.name == name)
+                        .ok_or_else(|| MatchupError::RegionError { file: Some(region_file.to_owned()), reason: format!("no region named '{name}'") })?;
+                    regions.push(region.clone());
+                }
+            }
+        } else if !opts.regions.is_empty() {
+            return Err(MatchupError::ArgumentError("--region requires --region-file to give the named regions".to_owned()));
+        }
+
+        let names = regions.iter().map(|r| r.name.as_str()).collect_vec();
+        if let Some(dup) = names.iter().duplicates().next() {
+            return Err(MatchupError::ArgumentError(format!("More than one region is named '{dup}'")));
+        }
+
+        Ok(Self { regions, filter: opts.region_filter })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Whether the point is in any of the regions.
+    pub fn any_contains(&self, lon: f64, lat: f64) -> bool {
+        self.regions.iter().any(|r| r.contains(lon, lat))
+    }
+
+    /// Space separated names of the regions, for output metadata
+    pub fn names(&self) -> String {
+        self.regions.iter().map(|r| r.name.as_str()).join(" ")
+    }
+}
+
+fn read_region_file(region_file: &Path) -> Result<Vec<Region>, MatchupError> {
+    let region_err = |reason: String| MatchupError::RegionError { file: Some(region_file.to_owned()), reason };
+    let contents = std::fs::read_to_string(region_file)?;
+    let file: RegionFile = toml::from_str(&contents)?;
+
+    file.regions.into_iter().map(|entry| {
+        match (entry.bbox, entry.polygon, entry.geojson) {
+            (Some([west, south, east, north]), None, None) => Region::from_bbox(entry.name, west, south, east, north),
+            (None, Some(vertices), None) => {
+                let poly = Polygon::new(vertices.into_iter().map(|[lon, lat]| (lon, lat)).collect(), vec![])
+                    .map_err(|e| region_err(format!("region '{}': {e}", entry.name)))?;
+                Ok(Region { name: entry.name, shape: RegionShape::Polygons(vec![poly]) })
+            },
+            (None, None, Some(geojson)) => {
+                let geojson = region_file.parent().map(|d| d.join(&geojson)).unwrap_or(geojson);
+                let polys = read_geojson_regions(&geojson)?
+                    .into_iter()
+                    .flat_map(|r| match r.shape {
+                        RegionShape::Polygons(p) => p,
+                        RegionShape::BoundingBox(_) => vec![],
+                    }).collect_vec();
+                Ok(Region { name: entry.name, shape: RegionShape::Polygons(polys) })
+            },
+            _ => Err(region_err(format!("region '{}' must have exactly one of bbox, polygon, or geojson", entry.name)))
+        }
+    }).collect()
+}
+
+/// Read the polygons in a GeoJSON file as regions: one region per feature, named by the feature's "name"
+/// property if it has one, otherwise by the file name and the feature's index. A bare geometry is one region
+/// named after the file. Only Polygon and MultiPolygon geometries are used.
+fn read_geojson_regions(geojson_file: &Path) -> Result<Vec<Region>, MatchupError> {
+    let region_err = |reason: String| MatchupError::RegionError { file: Some(geojson_file.to_owned()), reason };
+    let contents = std::fs::read_to_string(geojson_file)?;
+    let json: serde_json::Value = serde_json::from_str(&contents)
+        .map_err(|e| region_err(format!("not valid JSON: {e}")))?;
+    let stem = geojson_file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "region".to_owned());
+
+    let features = match json.get("type").and_then(|t| t.as_str()) {
+        Some("FeatureCollection") => json.get("features")
+            .and_then(|f| f.as_array())
+            .ok_or_else(|| region_err("FeatureCollection has no features array".to_owned()))?
+            .iter()
+            .collect_vec(),
+        Some(_) => vec![&json],
+        None => return Err(region_err("missing GeoJSON \"type\"".to_owned())),
+    };
+    let single = features.len() == 1;
+
+    let mut regions = Vec::new();
+    for (i, feature) in features.into_iter().enumerate() {
+        let geometry = if feature.get("type").and_then(|t| t.as_str()) == Some("Feature") {
+            feature.get("geometry").unwrap_or(&serde_json::Value::Null)
+        } else {
+            feature
+        };
+
+        let name = feature.get("properties")
+            .and_then(|p| p.get("name"))
+            .and_then(|n| n.as_str())
+            .map(|n| n.to_owned())
+            .unwrap_or_else(|| if single { stem.clone() } else { format!("{stem}{}", i + 1) });
+
+        let polys = geojson_polygons(geometry).map_err(|e| region_err(format!("feature {}: {e}", i + 1)))?;
+        if polys.is_empty() {
+            log::warn!("Feature {} in {} has no polygons, skipping it", i + 1, geojson_file.display());
+            continue;
+        }
+        regions.push(Region { name, shape: RegionShape::Polygons(polys) });
+    }
+
+    Ok(regions)
+}
+
+fn geojson_polygons(geometry: &serde_json::Value) -> Result<Vec<Polygon>, String> {
+    fn ring(value: &serde_json::Value) -> Result<Vec<(f64, f64)>, String> {
+        value.as_array()
+            .ok_or_else(|| "polygon ring is not an array".to_owned())?
+            .iter()
+            .map(|pt| {
+                let lon = pt.get(0).and_then(|v| v.as_f64());
+                let lat = pt.get(1).and_then(|v| v.as_f64());
+                lon.zip(lat).ok_or_else(|| format!("invalid position {pt}"))
+            }).collect()
+    }
+
+    fn polygon(value: &serde_json::Value) -> Result<Polygon, String> {
+        let rings = value.as_array().ok_or_else(|| "polygon coordinates are not an array".to_owned())?;
+        let mut rings = rings.iter().map(ring).collect::<Result<Vec<_>, _>>()?.into_iter();
+        let exterior = rings.next().ok_or_else(|| "polygon has no rings".to_owned())?;
+        Polygon::new(exterior, rings.collect())
+    }
+
+    let coords = geometry.get("coordinates");
+    match geometry.get("type").and_then(|t| t.as_str()) {
+        Some("Polygon") => Ok(vec![polygon(coords.ok_or("Polygon has no coordinates")?)?]),
+        Some("MultiPolygon") => coords.and_then(|c| c.as_array())
+            .ok_or_else(|| "MultiPolygon has no coordinates".to_owned())?
+            .iter()
+            .map(polygon)
+            .collect(),
+        Some("GeometryCollection") => {
+            let geoms = geometry.get("geometries").and_then(|g| g.as_array()).ok_or("GeometryCollection has no geometries")?;
+            let polys = geoms.iter().map(geojson_polygons).collect::<Result<Vec<_>, _>>()?;
+            Ok(polys.into_iter().flatten().collect())
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(west: f64, south: f64, east: f64, north: f64) -> Vec<(f64, f64)> {
+        vec![(west, south), (east, south), (east, north), (west, north)]
+    }
+
+    #[test]
+    fn test_bbox_contains() {
+        let bbox = BoundingBox::new(-125.0, 24.0, -66.0, 50.0).unwrap();
+        assert!(bbox.contains(-100.0, 40.0));
+        assert!(bbox.contains(-125.0, 24.0));
+        assert!(!bbox.contains(-130.0, 40.0));
+        assert!(!bbox.contains(-100.0, 55.0));
+        // The same longitude given in [0, 360)
+        assert!(bbox.contains(260.0, 40.0));
+    }
+
+    #[test]
+    fn test_bbox_contains_across_dateline() {
+        let bbox = BoundingBox::new(170.0, -10.0, -170.0, 10.0).unwrap();
+        assert!(bbox.contains(175.0, 0.0));
+        assert!(bbox.contains(-175.0, 0.0));
+        assert!(bbox.contains(180.0, 0.0));
+        assert!(bbox.contains(-180.0, 0.0));
+        assert!(!bbox.contains(0.0, 0.0));
+        assert!(!bbox.contains(160.0, 0.0));
+        assert!(!bbox.contains(-160.0, 0.0));
+
+        let global = BoundingBox::new(-180.0, -90.0, 180.0, 90.0).unwrap();
+        assert!(global.contains(0.0, 0.0) && global.contains(179.9, 89.0) && global.contains(-180.0, -90.0));
+    }
+
+    #[test]
+    fn test_ring_contains() {
+        let ring = square(0.0, 0.0, 10.0, 10.0);
+        assert!(ring_contains(&ring, 5.0, 5.0));
+        assert!(!ring_contains(&ring, 15.0, 5.0));
+        assert!(!ring_contains(&ring, 5.0, -1.0));
+
+        // A concave ring (an L shape): the notch is outside
+        let ell = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 4.0), (4.0, 4.0), (4.0, 10.0), (0.0, 10.0)];
+        assert!(ring_contains(&ell, 2.0, 8.0));
+        assert!(ring_contains(&ell, 8.0, 2.0));
+        assert!(!ring_contains(&ell, 8.0, 8.0));
+    }
+
+    #[test]
+    fn test_polygon_with_hole() {
+        let poly = Polygon::new(square(0.0, 0.0, 10.0, 10.0), vec![square(4.0, 4.0, 6.0, 6.0)]).unwrap();
+        assert!(poly.contains(2.0, 2.0));
+        assert!(!poly.contains(5.0, 5.0));
+        assert!(poly.contains(5.0, 7.0));
+        assert!(!poly.contains(11.0, 5.0));
+    }
+
+    #[test]
+    fn test_polygon_near_dateline() {
+        // Polygons must not cross the dateline, but one touching it should match points given with either sign
+        let east = Polygon::new(square(170.0, -10.0, 180.0, 10.0), vec![]).unwrap();
+        assert!(east.contains(175.0, 0.0));
+        assert!(east.contains(-185.0, 0.0));
+        assert!(!east.contains(-175.0, 0.0));
+
+        let west = Polygon::new(square(-180.0, -10.0, -170.0, 10.0), vec![]).unwrap();
+        assert!(west.contains(-175.0, 0.0));
+        assert!(west.contains(185.0, 0.0));
+        assert!(!west.contains(175.0, 0.0));
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use crate::checksum::ChecksumCache;
+use crate::config::RemapOptions;
+use crate::error::MatchupError;
+
+/// A rule that replaces the start of a path, e.g. to find lite files under a different
+/// directory than the one recorded in a match file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixRule {
+    /// The leading part of the recorded path to replace
+    pub from: PathBuf,
+    /// What to replace it with
+    pub to: PathBuf,
+}
+
+impl PrefixRule {
+    /// If `path` starts with this rule's `from` prefix (compared by whole components), return the
+    /// path with that prefix replaced by `to`.
+    pub fn apply(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.from)
+            .ok()
+            .map(|rest| self.to.join(rest))
+    }
+}
+
+impl FromStr for PrefixRule {
+    type Err = MatchupError;
+
+    /// Parse a rule written as `FROM=TO`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s.split_once('=')
+            .ok_or_else(|| MatchupError::ArgumentError(format!("Path remapping '{s}' must be given as OLD_PREFIX=NEW_PREFIX")))?;
+        if from.is_empty() {
+            return Err(MatchupError::ArgumentError(format!("Path remapping '{s}' has an empty prefix to replace")));
+        }
+        Ok(Self { from: PathBuf::from(from), to: PathBuf::from(to) })
+    }
+}
+
+/// Translates lite file paths recorded in match files to where the files are on this system.
+///
+/// Match files record the absolute paths of the lite files they were made from, which break when
+/// match files are copied to another system or the lite files are moved. A remapper finds each file
+/// in two ways:
+///
+/// 1. prefix rules replace the start of the recorded path (e.g. `/data/oco2` to `/mnt/archive/oco2`),
+///    with the first matching rule used, and
+/// 2. if that path does not exist (or has the wrong checksum), each search directory is checked for
+///    a file with the same name.
+///
+/// When the match file recorded a checksum for a lite file, a candidate is only accepted if it has
+/// that checksum, so a different file that happens to have the same name is not picked up.
+#[derive(Debug, Clone, Default)]
+pub struct PathRemapper {
+    prefix_rules: Vec<PrefixRule>,
+    search_dirs: Vec<PathBuf>,
+}
+
+impl PathRemapper {
+    /// Create a remapper that applies `prefix_rules` and then looks in `search_dirs`.
+    pub fn new(prefix_rules: Vec<PrefixRule>, search_dirs: Vec<PathBuf>) -> Self {
+        Self { prefix_rules, search_dirs }
+    }
+
+    /// Create the remapper requested by the command line options.
+    pub fn from_options(opts: &RemapOptions) -> Self {
+        Self::new(opts.remap.clone(), opts.search_dirs.clone())
+    }
+
+    /// Returns `true` if this remapper has no rules or search directories, and so always
+    /// returns recorded paths unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.prefix_rules.is_empty() && self.search_dirs.is_empty()
+    }
+
+    /// Return `recorded_path` with the first matching prefix rule applied, or `recorded_path`
+    /// itself if no rule matches. This does not check the file system.
+    pub fn resolve(&self, recorded_path: &Path) -> PathBuf {
+        self.prefix_rules.iter()
+            .find_map(|rule| rule.apply(recorded_path))
+            .unwrap_or_else(|| recorded_path.to_owned())
+    }
+
+    /// Find the lite file recorded in a match file as `recorded_path` on this system.
+    ///
+    /// If `expected_sha256` is given, the first candidate (the prefix-remapped path, then the same file name in each
+    /// search directory) with that checksum is returned. If none match, the prefix-remapped path is returned if it
+    /// exists, so that the caller can report that the file has changed. Without an expected checksum, the first
+    /// candidate that exists is returned. Returns `None` if no candidate exists.
+    pub fn locate(&self, recorded_path: &Path, expected_sha256: Option<&str>, checksums: &ChecksumCache) -> Result<Option<PathBuf>, MatchupError> {
+        let remapped = self.resolve(recorded_path);
+        let search_candidates = recorded_path.file_name()
+            .map(|name| self.search_dirs.iter().map(|d| d.join(name)).collect_vec())
+            .unwrap_or_default();
+
+        for candidate in std::iter::once(&remapped).chain(search_candidates.iter()) {
+            if !candidate.is_file() {
+                continue;
+            }
+
+            let is_match = match (expected_sha256, checksums.checksums(&[candidate])?) {
+                (Some(expected), Some(current)) => current.first().map(|c| c == expected).unwrap_or(false),
+                // Without both checksums, trust the file name
+                _ => true,
+            };
+
+            if is_match {
+                if candidate != recorded_path {
+                    log::debug!("Remapped lite file {} to {}", recorded_path.display(), candidate.display());
+                }
+                return Ok(Some(candidate.to_owned()));
+            }
+        }
+
+        Ok(if remapped.exists() { Some(remapped) } else { None })
+    }
+
+    /// Replace each of `recorded_paths` with where it is found on this system by [`PathRemapper::locate`],
+    /// leaving paths that cannot be found unchanged. `expected_sha256`, if given, must be the same length.
+    pub fn locate_all(&self, recorded_paths: &[PathBuf], expected_sha256: Option<&[String]>, checksums: &ChecksumCache) -> Result<Vec<PathBuf>, MatchupError> {
+        recorded_paths.iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let expected = expected_sha256.and_then(|v| v.get(i)).map(|s| s.as_str());
+                let found = self.locate(p, expected, checksums)?;
+                if found.is_none() {
+                    log::warn!("Could not find lite file {} on this system", p.display());
+                }
+                Ok(found.unwrap_or_else(|| p.to_owned()))
+            }).collect()
+    }
+}
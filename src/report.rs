@@ -0,0 +1,122 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+use crate::error::MatchupError;
+
+/// Summary statistics from one successful matchup.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct MatchupStats {
+    /// Number of soundings from the first instrument compared. This is `None` if the matches
+    /// were read from a previous full match file rather than computed.
+    pub n_first_soundings: Option<u64>,
+    /// Number of soundings from the second instrument compared, `None` under the same
+    /// conditions as `n_first_soundings`.
+    pub n_second_soundings: Option<u64>,
+    /// Number of matched pairs of soundings between the two instruments
+    pub n_matched_pairs: u64,
+    /// Number of groups written to the output file
+    pub n_groups: u64,
+}
+
+/// The outcome of one matchup in a multi run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchupStatus {
+    /// The matchup ran and its output was written.
+    Succeeded,
+    /// The matchup returned an error.
+    Failed,
+    /// The matchup was not run because its output already existed.
+    Skipped
+}
+
+/// The record of one `[[matchups]]` entry in a [`RunReport`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchupReport {
+    /// The output group file for this matchup, used to identify which entry this is.
+    pub output_file: PathBuf,
+    /// Whether this matchup succeeded, failed, or was skipped.
+    pub status: MatchupStatus,
+    /// Wall clock time spent on this matchup in seconds.
+    pub elapsed_seconds: f64,
+    /// Number of soundings from the first instrument compared, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_first_soundings: Option<u64>,
+    /// Number of soundings from the second instrument compared, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_second_soundings: Option<u64>,
+    /// Number of matched pairs of soundings; only present for successful matchups.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_matched_pairs: Option<u64>,
+    /// Number of groups written to the output file; only present for successful matchups.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_groups: Option<u64>,
+    /// The error message for failed matchups.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl MatchupReport {
+    /// Create a report for a matchup that was skipped.
+    pub fn skipped(output_file: PathBuf) -> Self {
+        Self {
+            output_file,
+            status: MatchupStatus::Skipped,
+            elapsed_seconds: 0.0,
+            n_first_soundings: None,
+            n_second_soundings: None,
+            n_matched_pairs: None,
+            n_groups: None,
+            error: None
+        }
+    }
+
+    /// Create a report from the result of running one matchup.
+    pub fn from_result(output_file: PathBuf, elapsed_seconds: f64, result: &Result<MatchupStats, MatchupError>) -> Self {
+        match result {
+            Ok(stats) => Self {
+                output_file,
+                status: MatchupStatus::Succeeded,
+                elapsed_seconds,
+                n_first_soundings: stats.n_first_soundings,
+                n_second_soundings: stats.n_second_soundings,
+                n_matched_pairs: Some(stats.n_matched_pairs),
+                n_groups: Some(stats.n_groups),
+                error: None
+            },
+            Err(e) => Self {
+                output_file,
+                status: MatchupStatus::Failed,
+                elapsed_seconds,
+                n_first_soundings: None,
+                n_second_soundings: None,
+                n_matched_pairs: None,
+                n_groups: None,
+                error: Some(e.to_string())
+            }
+        }
+    }
+}
+
+/// A structured report of all the matchups in a multi run.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub matchups: Vec<MatchupReport>
+}
+
+impl RunReport {
+    /// Number of matchups in the report with the given status.
+    pub fn count(&self, status: MatchupStatus) -> usize {
+        self.matchups.iter().filter(|m| m.status == status).count()
+    }
+
+    /// Write this report to `path` as a TOML file.
+    pub fn write_toml(&self, path: &Path) -> Result<(), MatchupError> {
+        let report_str = toml::to_string_pretty(self)?;
+        let mut f = std::fs::File::create(path)?;
+        write!(f, "{}", report_str)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Scoring matched pairs of soundings by how close together they are in both space and time.
+//!
+//! The score of a pair with distance `d` and time difference `t` is `sqrt((d / D)^2 + (t / T)^2)`, where `D` and
+//! `T` are configurable scales, so lower scores are better and a score of 1 is as far apart as a pair `D` kilometers
+//! apart with no time difference or `T` seconds apart at the same place.
+use crate::config::ScoreOptions;
+use crate::error::MatchupError;
+
+/// Default distance scale, in kilometers
+pub const DEFAULT_DISTANCE_SCALE_KM: f32 = 100.0;
+/// Default time scale, in seconds
+pub const DEFAULT_TIME_SCALE_S: f32 = 3600.0;
+
+/// How to score matched pairs, and the highest score a pair may have to match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchScore {
+    /// Distance in kilometers equivalent to a score of 1
+    pub distance_scale_km: f32,
+    /// Time difference in seconds equivalent to a score of 1
+    pub time_scale_s: f32,
+    /// Pairs with a higher score than this do not match. If `None`, only the distance and time criteria apply.
+    pub max_score: Option<f32>,
+}
+
+impl Default for MatchScore {
+    fn default() -> Self {
+        Self { distance_scale_km: DEFAULT_DISTANCE_SCALE_KM, time_scale_s: DEFAULT_TIME_SCALE_S, max_score: None }
+    }
+}
+
+impl MatchScore {
+    /// Make the scoring rule from the command line or configuration options, returning an error if the scales or
+    /// maximum score are not positive.
+    pub fn from_options(opts: &ScoreOptions) -> Result<Self, MatchupError> {
+        let checks = [
+            ("--space-time-distance-scale", Some(opts.space_time_distance_scale_km)),
+            ("--space-time-time-scale", Some(opts.space_time_time_scale_s)),
+            ("--max-score", opts.max_score),
+        ];
+        for (name, value) in checks {
+            if let Some(value) = value {
+                if !value.is_finite() || value <= 0.0 {
+                    return Err(MatchupError::ArgumentError(format!("{name} must be positive, got {value}")));
+                }
+            }
+        }
+
+        Ok(Self {
+            distance_scale_km: opts.space_time_distance_scale_km,
+            time_scale_s: opts.space_time_time_scale_s,
+            max_score: opts.max_score,
+        })
+    }
+
+    /// The score of a pair `dist_km` apart with time difference `dt_s`
+    pub fn score(&self, dist_km: f32, dt_s: f32) -> f32 {
+        (dist_km / self.distance_scale_km).hypot(dt_s / self.time_scale_s)
+    }
+
+    /// Whether a pair with score `score` may match
+    pub fn accepts(&self, score: f32) -> bool {
+        self.max_score.is_none_or(|max| score <= max)
+    }
+
+    /// The formula used, for output file metadata, e.g. "sqrt((distance / 100 km)^2 + (time_difference / 3600 s)^2)".
+    pub fn formula(&self) -> String {
+        format!("sqrt((distance / {} km)^2 + (time_difference / {} s)^2)", self.distance_scale_km, self.time_scale_s)
+    }
+}
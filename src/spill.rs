@@ -0,0 +1,170 @@
+//! Temporary on-disk storage for matched soundings.
+//!
+//! When matching very large inputs, holding every [`Match2to3`] in memory at once can use
+//! more memory than is available. The chunked matcher instead writes each block of matches
+//! to a spill file as it is computed, and grouping then streams the matches back from it.
+//! The spill file is a simple sequence of little-endian binary records, one per first
+//! instrument sounding with matches, and is only meant to be read back by the same run.
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::MatchupError;
+use crate::oco::Match2to3;
+
+/// Writes matches to a spill file as they are computed.
+pub struct MatchSpillWriter {
+    writer: BufWriter<File>,
+    spilled: SpilledMatches,
+}
+
+impl MatchSpillWriter {
+    /// Create a new spill file at `path`, overwriting it if it exists. `oco2_files` and `oco3_files`
+    /// are the lite files from each instrument that the file and sounding indices in the matches refer to.
+    pub fn create(path: &Path, oco2_files: Vec<PathBuf>, oco3_files: Vec<PathBuf>) -> Result<Self, MatchupError> {
+        let f = File::create(path)?;
+        let spilled = SpilledMatches {
+            path: path.to_owned(),
+            oco2_files,
+            oco3_files,
+            n_records: 0,
+            n_matched_pairs: 0
+        };
+        Ok(Self { writer: BufWriter::new(f), spilled })
+    }
+
+    /// Append a block of matches to the spill file. To be grouped correctly later, matches must be
+    /// written in order of first instrument sounding ID, both within and across blocks.
+    pub(crate) fn write_matches(&mut self, matches: &[Match2to3]) -> Result<(), MatchupError> {
+        for m in matches {
+            write_match(&mut self.writer, m)?;
+            self.spilled.n_records += 1;
+            self.spilled.n_matched_pairs += m.oco3_sounding_ids.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Finish writing the spill file and return a handle to read it back.
+    pub fn finish(mut self) -> Result<SpilledMatches, MatchupError> {
+        self.writer.flush()?;
+        Ok(self.spilled)
+    }
+}
+
+/// A handle to a completed spill file. The file is deleted when this is dropped.
+#[derive(Debug)]
+pub struct SpilledMatches {
+    path: PathBuf,
+    pub(crate) oco2_files: Vec<PathBuf>,
+    pub(crate) oco3_files: Vec<PathBuf>,
+    n_records: u64,
+    n_matched_pairs: u64,
+}
+
+impl SpilledMatches {
+    /// Total number of matched pairs of soundings between the two instruments.
+    pub fn num_matched_pairs(&self) -> u64 {
+        self.n_matched_pairs
+    }
+
+    /// Number of first instrument soundings with at least one match.
+    pub fn num_records(&self) -> u64 {
+        self.n_records
+    }
+
+    /// The IDs of the first and second instrument soundings with at least one match, read from the spill file.
+    pub fn matched_sounding_ids(&self) -> Result<(HashSet<u64>, HashSet<u64>), MatchupError> {
+        let mut first = HashSet::new();
+        let mut second = HashSet::new();
+        for m in self.iter()? {
+            let m = m?;
+            first.insert(m.oco2_sounding_id);
+            second.extend(m.oco3_sounding_ids.iter().copied());
+        }
+        Ok((first, second))
+    }
+
+    /// Iterate over the matches in the spill file, in the order they were written.
+    pub(crate) fn iter(&self) -> Result<MatchSpillReader, MatchupError> {
+        let f = File::open(&self.path)?;
+        Ok(MatchSpillReader { reader: BufReader::new(f) })
+    }
+}
+
+impl Drop for SpilledMatches {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Could not remove temporary match file {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// An iterator over the matches in a spill file.
+pub(crate) struct MatchSpillReader {
+    reader: BufReader<File>,
+}
+
+impl Iterator for MatchSpillReader {
+    type Item = Result<Match2to3, MatchupError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_match(&mut self.reader).transpose()
+    }
+}
+
+fn write_match<W: Write>(w: &mut W, m: &Match2to3) -> std::io::Result<()> {
+    w.write_all(&[m.oco2_file_index])?;
+    w.write_all(&m.oco2_sounding_index.to_le_bytes())?;
+    w.write_all(&m.oco2_sounding_id.to_le_bytes())?;
+    w.write_all(&(m.oco3_sounding_ids.len() as u32).to_le_bytes())?;
+    for i in 0..m.oco3_sounding_ids.len() {
+        w.write_all(&[m.oco3_file_indices[i]])?;
+        w.write_all(&m.oco3_sounding_indices[i].to_le_bytes())?;
+        w.write_all(&m.oco3_sounding_ids[i].to_le_bytes())?;
+        w.write_all(&m.distance_km[i].to_le_bytes())?;
+        w.write_all(&m.time_diff_s[i].to_le_bytes())?;
+        w.write_all(&m.score[i].to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_match<R: Read>(r: &mut R) -> Result<Option<Match2to3>, MatchupError> {
+    // A clean end of file can only happen at the start of a record; anywhere
+    // else it means the file was truncated.
+    let mut first = [0u8; 1];
+    if r.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+
+    let mut m = Match2to3::new(first[0], read_u64(r)?, read_u64(r)?);
+    let n = read_u32(r)?;
+    for _ in 0..n {
+        let fi = read_u8(r)?;
+        let idx = read_u64(r)?;
+        let sid = read_u64(r)?;
+        let dist = f32::from_le_bytes(read_bytes(r)?);
+        let dt = f32::from_le_bytes(read_bytes(r)?);
+        let score = f32::from_le_bytes(read_bytes(r)?);
+        m.add_oco3_match(fi, idx as usize, sid, dist, dt, score);
+    }
+    Ok(Some(m))
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u8<R: Read>(r: &mut R) -> std::io::Result<u8> {
+    Ok(read_bytes::<_, 1>(r)?[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(r)?))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(r)?))
+}
@@ -0,0 +1,139 @@
+//! Land/ocean classification of soundings and groups.
+//!
+//! Each sounding's land fraction (in percent) comes from `Sounding/land_fraction` in the lite files,
+//! or from `Retrieval/surface_type` for lite files without it. Soundings and groups are classified
+//! as land, ocean, or mixed by comparing land fractions to two thresholds.
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::SurfaceOptions;
+use crate::error::MatchupError;
+
+/// Group in the lite files containing the land fraction variable
+pub const LAND_FRACTION_GROUP: &str = "Sounding";
+/// Name of the land fraction variable in the lite files
+pub const LAND_FRACTION_VARIABLE: &str = "land_fraction";
+/// Group in the lite files containing the surface type variable
+pub const SURFACE_TYPE_GROUP: &str = "Retrieval";
+/// Name of the surface type variable in the lite files
+pub const SURFACE_TYPE_VARIABLE: &str = "surface_type";
+/// Default minimum land fraction, in percent, for a sounding to be classed as land
+pub const DEFAULT_LAND_THRESHOLD: f32 = 80.0;
+/// Default maximum land fraction, in percent, for a sounding to be classed as ocean
+pub const DEFAULT_OCEAN_THRESHOLD: f32 = 20.0;
+/// Value written for groups whose surface class could not be determined
+pub const UNKNOWN_SURFACE_CODE: u8 = u8::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SurfaceClass {
+    Ocean,
+    Land,
+    /// Soundings between the ocean and land thresholds, or groups with both land and ocean soundings
+    Mixed,
+}
+
+impl SurfaceClass {
+    /// All classes, in order of their output codes
+    pub const ALL: [SurfaceClass; 3] = [Self::Ocean, Self::Land, Self::Mixed];
+
+    /// The code used for this class in output files
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ocean => "ocean",
+            Self::Land => "land",
+            Self::Mixed => "mixed",
+        }
+    }
+}
+
+impl Display for SurfaceClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Convert a lite file surface type (0 = ocean, 1 = land) to an equivalent land fraction in percent.
+pub fn surface_type_to_land_fraction(surface_type: u8) -> f32 {
+    match surface_type {
+        0 => 0.0,
+        1 => 100.0,
+        _ => f32::NAN,
+    }
+}
+
+/// Thresholds for classifying land fractions, and the surface class to restrict matching to, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurfaceRules {
+    /// Minimum land fraction (percent) for a sounding to be land
+    pub land_threshold: f32,
+    /// Maximum land fraction (percent) for a sounding to be ocean
+    pub ocean_threshold: f32,
+    /// If given, only soundings of this class are matched
+    pub only: Option<SurfaceClass>,
+}
+
+impl Default for SurfaceRules {
+    fn default() -> Self {
+        Self { land_threshold: DEFAULT_LAND_THRESHOLD, ocean_threshold: DEFAULT_OCEAN_THRESHOLD, only: None }
+    }
+}
+
+impl SurfaceRules {
+    /// Create the rules from the command line or configuration options, checking that the thresholds make sense.
+    pub fn from_options(opts: &SurfaceOptions) -> Result<Self, MatchupError> {
+        let rules = Self { land_threshold: opts.land_threshold, ocean_threshold: opts.ocean_threshold, only: opts.surface };
+        if !(0.0..=100.0).contains(&rules.land_threshold) || !(0.0..=100.0).contains(&rules.ocean_threshold) {
+            return Err(MatchupError::ArgumentError("Land and ocean thresholds must be between 0 and 100 percent".to_owned()));
+        }
+        if rules.ocean_threshold >= rules.land_threshold {
+            return Err(MatchupError::ArgumentError(format!(
+                "The ocean threshold ({}%) must be less than the land threshold ({}%)", rules.ocean_threshold, rules.land_threshold
+            )));
+        }
+        Ok(rules)
+    }
+
+    /// Classify one sounding by its land fraction in percent. Returns `None` if the land fraction is unknown (NaN).
+    pub fn classify(&self, land_fraction: f32) -> Option<SurfaceClass> {
+        if land_fraction.is_nan() {
+            None
+        } else if land_fraction >= self.land_threshold {
+            Some(SurfaceClass::Land)
+        } else if land_fraction <= self.ocean_threshold {
+            Some(SurfaceClass::Ocean)
+        } else {
+            Some(SurfaceClass::Mixed)
+        }
+    }
+
+    /// Classify a group by the land fractions of all its soundings: land or ocean if every sounding is,
+    /// otherwise mixed. Returns `None` if any sounding's land fraction is unknown.
+    pub fn classify_group<I: IntoIterator<Item = f32>>(&self, land_fractions: I) -> Option<SurfaceClass> {
+        let mut group_class = None;
+        for frac in land_fractions {
+            let this_class = self.classify(frac)?;
+            group_class = match group_class {
+                None => Some(this_class),
+                Some(c) if c == this_class => Some(c),
+                Some(_) => Some(SurfaceClass::Mixed),
+            };
+        }
+        group_class
+    }
+
+    /// Whether a sounding with this land fraction may be matched. Soundings with unknown land
+    /// fractions are only allowed if matching is not restricted to one surface class.
+    pub fn allows(&self, land_fraction: f32) -> bool {
+        match self.only {
+            Some(only) => self.classify(land_fraction) == Some(only),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Times of groups of matched soundings, including local solar time for diurnal cycle studies.
+use chrono::{Datelike, TimeZone, Utc};
+
+use crate::geometry::{GroupGeometry, SoundingPoint};
+
+/// Units of the timestamps in lite files and of the time variables written to output files
+pub const TIME_UNITS: &str = "seconds since 1970-01-01 00:00:00";
+
+/// Times of one group of matched soundings. Timestamps are in seconds since 1970-01-01 00:00:00 UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupTiming {
+    /// Time of the first instrument's earliest and latest soundings in the group
+    pub first_start_end: [f64; 2],
+    /// Time of the second instrument's earliest and latest soundings in the group
+    pub second_start_end: [f64; 2],
+    /// Midpoint between the mean times of each instrument's soundings in the group. Each instrument counts
+    /// equally, however many soundings it has; the instruments' own times are in the start/end times and
+    /// local solar times.
+    pub crossing_time: f64,
+    /// Local solar time in hours at the crossing location at `crossing_time`
+    pub crossing_local_solar_time: f32,
+    /// Local solar time in hours at the crossing location at the mean time of the first instrument's soundings
+    pub first_local_solar_time: f32,
+    /// Local solar time in hours at the crossing location at the mean time of the second instrument's soundings
+    pub second_local_solar_time: f32,
+}
+
+impl GroupTiming {
+    /// Compute the times for a group from its soundings from each instrument and its geometry. Local solar
+    /// times are calculated at the crossing point, or the center of the group's bounding box if the crossing
+    /// point could not be found. Neither slice may be empty.
+    pub fn from_soundings(first: &[SoundingPoint], second: &[SoundingPoint], geometry: &GroupGeometry) -> Self {
+        let lst_longitude = if geometry.crossing_longitude.is_finite() {
+            geometry.crossing_longitude
+        } else {
+            bbox_center_longitude(geometry.min_longitude, geometry.max_longitude)
+        };
+
+        let first_mean = mean_time(first);
+        let second_mean = mean_time(second);
+        let crossing_time = 0.5 * (first_mean + second_mean);
+
+        Self {
+            first_start_end: start_end_time(first),
+            second_start_end: start_end_time(second),
+            crossing_time,
+            crossing_local_solar_time: local_solar_time(crossing_time, lst_longitude),
+            first_local_solar_time: local_solar_time(first_mean, lst_longitude),
+            second_local_solar_time: local_solar_time(second_mean, lst_longitude),
+        }
+    }
+}
+
+/// Apparent local solar time, in hours between 0 and 24, at `longitude` (degrees east) at `timestamp`
+/// (seconds since 1970-01-01 00:00:00 UTC). Returns NaN if either input is not finite.
+///
+/// This is the mean solar time for the longitude, corrected by the equation of time, which is accurate
+/// to within about a minute.
+pub fn local_solar_time(timestamp: f64, longitude: f32) -> f32 {
+    if !timestamp.is_finite() || !longitude.is_finite() {
+        return f32::NAN;
+    }
+
+    let datetime = match Utc.timestamp_opt(timestamp.floor() as i64, 0).single() {
+        Some(dt) => dt,
+        None => return f32::NAN,
+    };
+
+    let utc_hours = timestamp.rem_euclid(86400.0) / 3600.0;
+    let lst = utc_hours + longitude as f64 / 15.0 + equation_of_time_minutes(datetime.ordinal()) / 60.0;
+    lst.rem_euclid(24.0) as f32
+}
+
+/// Approximate equation of time in minutes for a day of the year
+fn equation_of_time_minutes(day_of_year: u32) -> f64 {
+    let b = 2.0 * std::f64::consts::PI * (day_of_year as f64 - 81.0) / 364.0;
+    9.87 * (2.0 * b).sin() - 7.53 * b.cos() - 1.5 * b.sin()
+}
+
+fn start_end_time(points: &[SoundingPoint]) -> [f64; 2] {
+    points.iter()
+        .map(|p| p.timestamp)
+        .fold([f64::INFINITY, f64::NEG_INFINITY], |[start, end], t| [start.min(t), end.max(t)])
+}
+
+fn mean_time(points: &[SoundingPoint]) -> f64 {
+    if points.is_empty() {
+        return f64::NAN;
+    }
+    points.iter().map(|p| p.timestamp).sum::<f64>() / points.len() as f64
+}
+
+/// Center longitude of a bounding box, allowing for boxes that cross the dateline (where `min_lon > max_lon`)
+fn bbox_center_longitude(min_lon: f32, max_lon: f32) -> f32 {
+    let width = (max_lon - min_lon).rem_euclid(360.0);
+    let center = min_lon + width / 2.0;
+    (center + 180.0).rem_euclid(360.0) - 180.0
+}
@@ -5,9 +5,6 @@ use ndarray::{Array1, ArrayView1, Ix1};
 
 use crate::error::MatchupError;
 
-/// Radius of Earth in kilometers
-pub const EARTH_RADIUS_STD: f32 = 6378.137;
-
 /// Converstion factor from degrees to radians.
 pub const DEG2RAD: f32 = std::f32::consts::PI / 180.0;
 
@@ -141,6 +138,17 @@ pub fn write_nc_var<T: netcdf::NcPutGet>(
     Ok(())
 }
 
+/// Add string attributes to a variable that has already been written, e.g. CF `long_name`,
+/// `standard_name`, or `coordinates` attributes.
+pub fn add_string_attributes(grp: &mut netcdf::GroupMut, varname: &str, attrs: &[(&str, &str)]) -> Result<(), MatchupError> {
+    let mut var = grp.variable_mut(varname)
+        .ok_or_else(|| MatchupError::NetcdfMissingVar { file: None, varname: varname.to_owned() })?;
+    for &(name, value) in attrs {
+        var.add_attribute(name, value)?;
+    }
+    Ok(())
+}
+
 /// Write a series of strings to a 1D string variable in a netCDF file.
 /// 
 /// # Parameters
@@ -189,6 +197,76 @@ pub fn write_string_nc_var<T: AsRef<str>>(
     Ok(())
 }
 
+/// Read a 1D string variable from a netCDF group.
+/// 
+/// # Returns
+/// The strings in the variable. An `Err` is returned if the variable does not exist, is not 1D,
+/// or any of its values cannot be read as a string.
+pub fn read_string_nc_var(grp: &netcdf::Group, varname: &str) -> Result<Vec<String>, MatchupError> {
+    let var = grp.variable(varname)
+        .ok_or_else(|| MatchupError::NetcdfMissingVar { file: None, varname: varname.to_owned() })?;
+
+    let n = if let [dim] = var.dimensions() {
+        dim.len()
+    } else {
+        return Err(MatchupError::InternalError(format!("Expected string variable {varname} to have only 1 dimension")));
+    };
+
+    (0..n).map(|i| Ok(var.string_value(i)?))
+        .collect()
+}
+
+/// Name of the global attribute that marks an output netCDF file as completely written.
+///
+/// This must be the last thing written to a file, so that its presence means all the
+/// groups and variables before it were written successfully.
+pub const COMPLETE_ATTRIBUTE: &str = "matchup_complete";
+
+/// Return the temporary path to write an output file to before it is complete.
+///
+/// Output files should be written to this path and moved to `final_path` with
+/// [`finalize_partial_output`] once they are finished, so that a run which dies
+/// partway through writing never leaves a file at `final_path` that looks like a result.
+pub fn partial_output_path(final_path: &Path) -> PathBuf {
+    let mut name = final_path.file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(".partial");
+    final_path.with_file_name(name)
+}
+
+/// Add the [`COMPLETE_ATTRIBUTE`] to the root group of a netCDF file being written.
+pub fn mark_nc_file_complete(ds: &mut netcdf::MutableFile) -> Result<(), MatchupError> {
+    let file = nc_file(ds);
+    ds.add_attribute(COMPLETE_ATTRIBUTE, 1u8)
+        .map_err(|e| MatchupError::from_nc_error(e, file))?;
+    Ok(())
+}
+
+/// Check whether the netCDF file at `path` was completely written.
+///
+/// Returns `false` if the file does not exist, cannot be opened as a netCDF file, or
+/// does not have the [`COMPLETE_ATTRIBUTE`] in its root group.
+pub fn nc_file_is_complete(path: &Path) -> bool {
+    if !path.exists() {
+        return false;
+    }
+
+    netcdf::open(path)
+        .map(|ds| ds.attribute(COMPLETE_ATTRIBUTE).is_some())
+        .unwrap_or(false)
+}
+
+/// Move a finished output file from its temporary path to its final path.
+///
+/// This is the counterpart to [`partial_output_path`]; any existing file at `final_path`
+/// is replaced.
+pub fn finalize_partial_output(partial_path: &Path, final_path: &Path) -> Result<(), MatchupError> {
+    log::debug!("Moving {} to {}", partial_path.display(), final_path.display());
+    std::fs::rename(partial_path, final_path)?;
+    Ok(())
+}
+
 /// Return an array that has only good-quality OCO-2/3 data
 /// 
 /// Given a 1D array `arr` representing a variable in an OCO lite file and an array
@@ -207,19 +285,15 @@ pub fn filter_by_quality<T: Copy>(arr: ArrayView1<T>, flags: ArrayView1<u8>) ->
     Array1::<T>::from_iter(it)
 }
 
-/// Calculate the great circle distance in kilometers between two locations on Earth.
-pub fn great_circle_distance(lon1: f32, lat1: f32, lon2: f32, lat2: f32) -> f32 {
-    let lon1 = lon1 * DEG2RAD;
-    let lat1 = lat1 * DEG2RAD;
-    let lon2 = lon2 * DEG2RAD;
-    let lat2 = lat2 * DEG2RAD;
-
-    let dlon = (lon2 - lon1).abs();
-    let dlat = (lat2 - lat1).abs();
-
-    let inner = ((dlat/2.0).sin()).powi(2) + (1.0 - (dlat/2.0).sin().powi(2) - ((lat1 + lat2)/2.0).sin().powi(2)) * (dlon/2.0).sin().powi(2);
-    let central_angle = 2.0 * inner.sqrt().asin();
-    central_angle * EARTH_RADIUS_STD
+/// Return an array with only the values from `arr` where `keep` is `true`.
+///
+/// This is the general form of [`filter_by_quality`], for when the same selection must be
+/// applied to several arrays. `keep` must be the same length as `arr`.
+pub fn filter_by_mask<T: Copy>(arr: ArrayView1<T>, keep: &[bool]) -> Array1<T> {
+    let it = arr.into_iter()
+                 .zip(keep.iter())
+                 .filter_map(|(v, &k)| if k { Some(*v) } else { None });
+    Array1::<T>::from_iter(it)
 }
 
 /// Calculate the SHA256 checksum of a file.
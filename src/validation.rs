@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::config::{RunMultiConfig, RunOneArgs};
+use crate::grouping::GroupingRules;
+use crate::nearest::NearestRule;
+use crate::oco::OcoGeo;
+use crate::region::RegionSet;
+use crate::score::MatchScore;
+use crate::surface::SurfaceRules;
+
+/// The problems found in a multi-matchup configuration by [`validate_multi_config`].
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Problems that would cause one or more matchups to fail or overwrite each other's output.
+    pub errors: Vec<String>,
+    /// Issues that will not cause a failure, but probably are not what was intended.
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no errors were found (warnings are allowed).
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Check a multi-matchup configuration for problems before running it.
+///
+/// This checks that:
+///
+/// * every input lite file exists and is a netCDF file with the variables needed for matching,
+/// * every file to read previous full matches from exists,
+/// * the surface, score, nearest match, and grouping options are valid,
+/// * every output file's directory exists,
+/// * no two matchups write to the same output file, and no lite file or full match file to read is also
+///   written as an `output_file` or `save_full_matches_as` by any matchup, and
+/// * every OCO-3 self-crossing matchup includes its base file among the files it is matched against
+///   (this one is a warning, since it may be intentional).
+///
+/// All matchups are checked, so the returned report lists every problem found rather than just the first.
+pub fn validate_multi_config(cfg: &RunMultiConfig) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    // Many lite files are used by several matchups, so only check each one once.
+    let lite_files = cfg.matchups.iter()
+        .flat_map(|m| std::iter::once(&m.oco2_lite_file).chain(m.oco3_lite_files.iter()))
+        .unique()
+        .collect_vec();
+    let lite_file_problems: HashMap<&PathBuf, String> = lite_files.into_par_iter()
+        .filter_map(|p| {
+            OcoGeo::check_lite_file(p).err().map(|e| (p, e.to_string()))
+        }).collect();
+
+    for (i, m) in cfg.matchups.iter().enumerate() {
+        let label = matchup_label(i, m);
+
+        if m.oco3_lite_files.is_empty() {
+            report.errors.push(format!("{label}: no second instrument (oco3_lite_files) files given"));
+        }
+
+        for lite_file in std::iter::once(&m.oco2_lite_file).chain(m.oco3_lite_files.iter()) {
+            if !lite_file.exists() {
+                report.errors.push(format!("{label}: lite file {} does not exist", lite_file.display()));
+            } else if let Some(msg) = lite_file_problems.get(lite_file) {
+                report.errors.push(format!("{label}: lite file {} is not usable ({msg})", lite_file.display()));
+            }
+        }
+
+        for msg in match_rule_errors(m) {
+            report.errors.push(format!("{label}: {msg}"));
+        }
+
+        if let Some(full_matches) = m.read_full_matches.as_deref() {
+            if !full_matches.exists() {
+                report.errors.push(format!("{label}: full match file to read, {}, does not exist", full_matches.display()));
+            }
+        }
+
+        for out_file in output_files(m) {
+            let out_dir = out_file.parent()
+                .filter(|d| !d.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            if !out_dir.is_dir() {
+                report.errors.push(format!("{label}: directory for output file {} does not exist", out_file.display()));
+            }
+        }
+
+        if m.oco3_self_cross && !m.oco3_lite_files.iter().any(|p| normalize_path(p) == normalize_path(&m.oco2_lite_file)) {
+            report.warnings.push(format!(
+                "{label}: self crossing base file {} is not listed in oco3_lite_files, so crossings within that file will not be found",
+                m.oco2_lite_file.display()
+            ));
+        }
+    }
+
+    check_output_collisions(cfg, &mut report);
+    report
+}
+
+/// Build the matchup's match rules from its options, as running it would, and return any errors.
+fn match_rule_errors(matchup: &RunOneArgs) -> Vec<String> {
+    let errors = [
+        SurfaceRules::from_options(&matchup.surface_options).err(),
+        MatchScore::from_options(&matchup.score_options).err(),
+        NearestRule::from_options(&matchup.nearest_options).err(),
+        GroupingRules::from_options(&matchup.grouping_options, matchup.flag0_only).err(),
+    ];
+    let mut errors = errors.into_iter().flatten().map(|e| e.to_string()).collect_vec();
+
+    let missing = matchup.region_options.region_files()
+        .filter(|(_, p)| !p.exists())
+        .map(|(opt, p)| format!("{opt} {} does not exist", p.display()))
+        .collect_vec();
+    if missing.is_empty() {
+        errors.extend(RegionSet::from_options(&matchup.region_options).err().map(|e| e.to_string()));
+    } else {
+        errors.extend(missing);
+    }
+    errors
+}
+
+fn matchup_label(index: usize, matchup: &RunOneArgs) -> String {
+    format!("Matchup #{} ({})", index + 1, matchup.output_file.display())
+}
+
+fn output_files(matchup: &RunOneArgs) -> impl Iterator<Item = &Path> {
+    labeled_output_files(matchup).map(|(_, p)| p)
+}
+
+/// The files a matchup writes, each with the name of the option that gives it
+fn labeled_output_files(matchup: &RunOneArgs) -> impl Iterator<Item = (&'static str, &Path)> {
+    std::iter::once(("output_file", matchup.output_file.as_path()))
+        .chain(matchup.save_full_matches_as.as_deref().map(|p| ("save_full_matches_as", p)))
+}
+
+fn check_output_collisions(cfg: &RunMultiConfig, report: &mut ValidationReport) {
+    // Map each normalized output path to the matchups that write it, as "<label> (<option>)"
+    let mut writers: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for (i, m) in cfg.matchups.iter().enumerate() {
+        for (option, out_file) in labeled_output_files(m) {
+            writers.entry(normalize_path(out_file))
+                .or_default()
+                .push(format!("{} ({option})", matchup_label(i, m)));
+        }
+    }
+
+    for (out_file, labels) in writers.iter().sorted_by_key(|(k, _)| *k) {
+        if labels.len() > 1 {
+            report.errors.push(format!("Output file {} is written by more than one matchup: {}", out_file.display(), labels.join(", ")));
+        }
+    }
+
+    // Inputs are only a problem if some matchup writes to the same path as an output_file or save_full_matches_as
+    let lite_files = cfg.matchups.iter()
+        .flat_map(|m| std::iter::once(&m.oco2_lite_file).chain(m.oco3_lite_files.iter()))
+        .map(|p| normalize_path(p))
+        .unique()
+        .sorted();
+    for lite_file in lite_files {
+        if let Some(labels) = writers.get(&lite_file) {
+            report.errors.push(format!("Lite file {} would be overwritten as an output by: {}", lite_file.display(), labels.join(", ")));
+        }
+    }
+
+    for (i, m) in cfg.matchups.iter().enumerate() {
+        let Some(full_matches) = m.read_full_matches.as_deref() else {
+            continue;
+        };
+        if let Some(labels) = writers.get(&normalize_path(full_matches)) {
+            report.errors.push(format!(
+                "{}: full match file to read, {}, would be overwritten as an output by: {}",
+                matchup_label(i, m), full_matches.display(), labels.join(", ")
+            ));
+        }
+    }
+}
+
+/// Make a path absolute and remove "." and ".." components without touching the file system,
+/// so that different spellings of the same path compare equal.
+fn normalize_path(p: &Path) -> PathBuf {
+    let p = if p.is_absolute() {
+        p.to_owned()
+    } else {
+        std::env::current_dir().map(|d| d.join(p)).unwrap_or_else(|_| p.to_owned())
+    };
+
+    let mut out = PathBuf::new();
+    for comp in p.components() {
+        match comp {
+            Component::CurDir => {},
+            Component::ParentDir => { out.pop(); },
+            c => out.push(c)
+        }
+    }
+    out
+}
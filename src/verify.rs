@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::checksum::ChecksumCache;
+use crate::error::MatchupError;
+use crate::remap::PathRemapper;
+use crate::utils;
+
+/// The state of one lite file referenced by a match file, as found by [`verify_match_file`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiteFileStatus {
+    /// The file exists and has the checksum recorded in the match file.
+    Unchanged,
+    /// The file exists but its checksum differs from the one recorded.
+    Changed { recorded_sha256: String, current_sha256: String },
+    /// The file does not exist (after remapping its path).
+    Missing,
+    /// The match file did not record a checksum for this file, so it could not be checked.
+    NoChecksum,
+}
+
+/// The result of checking one lite file referenced by a match file.
+#[derive(Debug, Clone)]
+pub struct LiteFileCheck {
+    /// The path as recorded in the match file
+    pub recorded_path: PathBuf,
+    /// The path checked, after applying any remapping
+    pub resolved_path: PathBuf,
+    pub status: LiteFileStatus,
+}
+
+/// The result of checking all the lite files referenced by one match file.
+#[derive(Debug, Clone)]
+pub struct MatchFileVerification {
+    pub match_file: PathBuf,
+    pub lite_files: Vec<LiteFileCheck>,
+}
+
+impl MatchFileVerification {
+    /// A match file is stale if any of its lite files have changed or are missing. Lite files
+    /// without a recorded checksum do not make a match file stale, since they cannot be checked.
+    pub fn is_stale(&self) -> bool {
+        self.lite_files.iter()
+            .any(|f| matches!(f.status, LiteFileStatus::Changed { .. } | LiteFileStatus::Missing))
+    }
+}
+
+/// The lite file paths and their recorded checksums (if any) from a match file.
+pub fn read_lite_file_records(match_file: &Path) -> Result<Vec<(PathBuf, Option<String>)>, MatchupError> {
+    let ds = netcdf::open(match_file)
+        .map_err(|e| MatchupError::from_nc_error(e, match_file.to_owned()))?;
+    let grp = ds.root()
+        .ok_or_else(|| MatchupError::NetcdfError { nc_error: "Cannot get root group".into(), file: Some(match_file.to_owned()) })?;
+
+    // Group files name their lite file variables after the instruments (e.g. "oco2_lite_file" and "oco3_lite_file",
+    // or "oco3a_lite_file" and "oco3b_lite_file" for self crossings), with a matching "_sha256" variable for each.
+    let lite_file_varnames = grp.variables()
+        .map(|v| v.name())
+        .filter(|name| name.ends_with("_lite_file"))
+        .sorted()
+        .collect_vec();
+
+    if lite_file_varnames.is_empty() {
+        return Err(MatchupError::NetcdfMissingVar { file: Some(match_file.to_owned()), varname: "*_lite_file".to_owned() });
+    }
+
+    let mut records = Vec::new();
+    for varname in lite_file_varnames {
+        let paths = utils::read_string_nc_var(&grp, &varname)
+            .map_err(|e| e.set_file(match_file.to_owned()))?;
+        let sha_varname = format!("{varname}_sha256");
+        let checksums = if grp.variable(&sha_varname).is_some() {
+            utils::read_string_nc_var(&grp, &sha_varname)
+                .map_err(|e| e.set_file(match_file.to_owned()))?
+                .into_iter()
+                .map(Some)
+                .collect_vec()
+        } else {
+            vec![None; paths.len()]
+        };
+
+        if checksums.len() != paths.len() {
+            return Err(MatchupError::InternalError(format!(
+                "In {}, {varname} and {sha_varname} have different lengths", match_file.display()
+            )));
+        }
+
+        records.extend(paths.into_iter().map(PathBuf::from).zip(checksums));
+    }
+
+    Ok(records)
+}
+
+/// Check whether the lite files that `match_file` was made from still exist and are unchanged.
+///
+/// Each lite file recorded in the match file is found with `remapper` (see [`PathRemapper::locate`]),
+/// to allow for lite files that have been moved since the match file was made. Current checksums come
+/// from `checksums`, so must not be disabled.
+pub fn verify_match_file(match_file: &Path, remapper: &PathRemapper, checksums: &ChecksumCache) -> Result<MatchFileVerification, MatchupError> {
+    if !checksums.is_enabled() {
+        return Err(MatchupError::ArgumentError("Cannot verify match files with checksums disabled".to_owned()));
+    }
+
+    let records = read_lite_file_records(match_file)?;
+    let mut lite_files = Vec::with_capacity(records.len());
+    for (recorded_path, recorded_sha256) in records {
+        let found = remapper.locate(&recorded_path, recorded_sha256.as_deref(), checksums)?;
+        let (resolved_path, status) = match (found, recorded_sha256) {
+            (None, _) => (remapper.resolve(&recorded_path), LiteFileStatus::Missing),
+            (Some(p), None) => (p, LiteFileStatus::NoChecksum),
+            (Some(p), Some(recorded_sha256)) => {
+                let current_sha256 = checksums.checksums(&[&p])?
+                    .and_then(|v| v.into_iter().next())
+                    .ok_or_else(|| MatchupError::InternalError("Expected one checksum when checksums are enabled".to_owned()))?;
+                let status = if current_sha256 == recorded_sha256 {
+                    LiteFileStatus::Unchanged
+                } else {
+                    LiteFileStatus::Changed { recorded_sha256, current_sha256 }
+                };
+                (p, status)
+            }
+        };
+
+        lite_files.push(LiteFileCheck { recorded_path, resolved_path, status });
+    }
+
+    Ok(MatchFileVerification { match_file: match_file.to_owned(), lite_files })
+}